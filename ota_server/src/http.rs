@@ -0,0 +1,431 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// Upper bound for request line and headers, nothing we serve needs more.
+const MAX_HEAD_LINES: usize = 64;
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: Method,
+    /// Request target without the query string.
+    pub path: String,
+    pub query: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Case-insensitive header lookup.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_parameter(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    Io(io::Error),
+    /// The client closed the connection before sending a request.
+    Closed,
+    BadRequest(String),
+    MethodNotAllowed(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Io(e) => write!(f, "{e}"),
+            RequestError::Closed => write!(f, "connection closed"),
+            RequestError::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            RequestError::MethodNotAllowed(method) => write!(f, "method not allowed: {method}"),
+        }
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, RequestError> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(RequestError::Io)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(RequestError::BadRequest("line too long".into()));
+    }
+
+    let line = String::from_utf8(line)
+        .map_err(|_| RequestError::BadRequest("request is not utf-8".into()))?;
+
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+pub fn read_request(stream: impl Read) -> Result<Request, RequestError> {
+    let mut reader = BufReader::new(stream);
+
+    let request_line = read_line(&mut reader)?.ok_or(RequestError::Closed)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(RequestError::BadRequest(format!(
+            "malformed request line: {request_line}"
+        )));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(RequestError::BadRequest(format!(
+            "unsupported version: {version}"
+        )));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        if headers.len() >= MAX_HEAD_LINES {
+            return Err(RequestError::BadRequest("too many headers".into()));
+        }
+
+        let line = read_line(&mut reader)?
+            .ok_or_else(|| RequestError::BadRequest("incomplete request head".into()))?;
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| RequestError::BadRequest(format!("malformed header: {line}")))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    // Methods are checked after the head was read, so the 405 does not cut off the request.
+    let method = match method {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        other => return Err(RequestError::MethodNotAllowed(other.to_string())),
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+    })
+}
+
+/// Maps a request path to a path relative to the served folder.
+///
+/// Percent-escapes are decoded first, so `%2e%2e` is caught as well. Every `..`, root or
+/// prefix component is rejected instead of being resolved, nothing outside the folder
+/// can be reached.
+pub fn normalize_path(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(path)?;
+    if decoded.contains(['\\', '\0']) {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(relative)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Inclusive byte positions.
+    Satisfiable {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range against a body of `len` bytes.
+///
+/// Returns `None` if the header should be ignored and the full body served, which is
+/// the case for syntax we do not understand and for multiple ranges.
+pub fn parse_range(header: &str, len: u64) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start.is_empty(), end.is_empty()) {
+        // bytes=-n: the last n bytes
+        (true, false) => {
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            ByteRange::Satisfiable {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        }
+        // bytes=n-: everything from n
+        (false, true) => {
+            let start: u64 = start.parse().ok()?;
+            if start >= len {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            ByteRange::Satisfiable {
+                start,
+                end: len - 1,
+            }
+        }
+        (false, false) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            ByteRange::Satisfiable {
+                start,
+                end: end.min(len - 1),
+            }
+        }
+        (true, true) => return None,
+    };
+
+    Some(range)
+}
+
+pub struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Content-Length always describes the body, also if it is left out for HEAD.
+    pub fn write(&self, stream: &mut impl Write, method: Option<Method>) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        head.push_str("Connection: close\r\n\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        if method != Some(Method::Head) {
+            stream.write_all(&self.body)?;
+        }
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_path() {
+        assert_eq!(
+            super::normalize_path("/v2_6/kombiinstrument.bin"),
+            Some(PathBuf::from("v2_6/kombiinstrument.bin"))
+        );
+        assert_eq!(
+            super::normalize_path("/./a%20b.bin"),
+            Some(PathBuf::from("a b.bin"))
+        );
+        assert_eq!(super::normalize_path("/"), Some(PathBuf::new()));
+        // Absolute paths stay inside the folder.
+        assert_eq!(
+            super::normalize_path("//etc/passwd"),
+            Some(PathBuf::from("etc/passwd"))
+        );
+    }
+
+    #[test]
+    fn normalize_path_rejects_traversal() {
+        for path in [
+            "/../signing.key",
+            "/v2_6/../../signing.key",
+            "/%2e%2e/signing.key",
+            "/%2E%2E%2Fsigning.key",
+            "/v2_6/..%2f..%2fsigning.key",
+            "/..\\signing.key",
+            "/%5c..%5csigning.key",
+            "/a%00.bin",
+        ] {
+            assert_eq!(super::normalize_path(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn normalize_path_rejects_bad_escapes() {
+        assert_eq!(super::normalize_path("/a%2"), None);
+        assert_eq!(super::normalize_path("/a%zz"), None);
+        assert_eq!(super::normalize_path("/%ff.bin"), None);
+    }
+
+    #[test]
+    fn parse_range() {
+        let range = |start, end| Some(ByteRange::Satisfiable { start, end });
+
+        assert_eq!(super::parse_range("bytes=0-99", 1000), range(0, 99));
+        assert_eq!(super::parse_range("bytes=900-", 1000), range(900, 999));
+        assert_eq!(super::parse_range("bytes=-100", 1000), range(900, 999));
+        assert_eq!(super::parse_range("bytes=-5000", 1000), range(0, 999));
+        assert_eq!(super::parse_range("bytes=990-5000", 1000), range(990, 999));
+        assert_eq!(super::parse_range(" bytes=1-1 ", 1000), range(1, 1));
+    }
+
+    #[test]
+    fn parse_range_unsatisfiable() {
+        for header in ["bytes=1000-", "bytes=1000-1100", "bytes=-0"] {
+            assert_eq!(
+                super::parse_range(header, 1000),
+                Some(ByteRange::Unsatisfiable),
+                "{header}"
+            );
+        }
+        assert_eq!(
+            super::parse_range("bytes=-1", 0),
+            Some(ByteRange::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn parse_range_ignored() {
+        for header in [
+            "items=0-99",
+            "bytes=0-9,20-29",
+            "bytes=-",
+            "bytes=9-0",
+            "bytes=a-b",
+            "bytes=0",
+        ] {
+            assert_eq!(super::parse_range(header, 1000), None, "{header}");
+        }
+    }
+
+    #[test]
+    fn read_request() {
+        let raw = "GET /manifest/kombiinstrument?pcb_revision=v2_6 HTTP/1.1\r\n\
+                   range: bytes=0-\r\n\r\n";
+        let request = super::read_request(raw.as_bytes()).unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/manifest/kombiinstrument");
+        assert_eq!(request.query_parameter("pcb_revision"), Some("v2_6"));
+        assert_eq!(request.query_parameter("role"), None);
+        assert_eq!(request.header("Range"), Some("bytes=0-"));
+    }
+
+    #[test]
+    fn read_request_rejects() {
+        let read = |request: &str| super::read_request(request.as_bytes());
+
+        assert!(matches!(read(""), Err(RequestError::Closed)));
+        assert!(matches!(
+            read("POST /a.bin HTTP/1.1\r\n\r\n"),
+            Err(RequestError::MethodNotAllowed(method)) if method == "POST"
+        ));
+        for request in [
+            "GET /a.bin\r\n\r\n",
+            "GET /a.bin HTTP/2\r\n\r\n",
+            "GET /a.bin HTTP/1.1 x\r\n\r\n",
+            "GET /a.bin HTTP/1.1\r\nno colon\r\n\r\n",
+            "GET /a.bin HTTP/1.1\r\n",
+        ] {
+            assert!(
+                matches!(read(request), Err(RequestError::BadRequest(_))),
+                "{request:?}"
+            );
+        }
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        assert!(matches!(read(&long), Err(RequestError::BadRequest(_))));
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEAD_LINES)
+        );
+        assert!(matches!(read(&many), Err(RequestError::BadRequest(_))));
+    }
+}
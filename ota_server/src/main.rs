@@ -1,13 +1,27 @@
 use std::{
+    env,
     fs::read,
-    io::{BufRead, BufReader, Write},
-    net::TcpListener,
+    net::{TcpListener, TcpStream},
     path::Path,
+    thread,
+    time::Duration,
 };
 
+use http::{ByteRange, Request, RequestError, Response};
+use manifest::{FirmwareEntry, Manifest};
+
+mod http;
+mod manifest;
+mod signing;
+
 const SERVER_ADRESS: &str = "0.0.0.0:6969";
 const FOLDER_PATH: &str = "./bin/";
 
+/// Drops clients that stop sending in the middle of a request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage: ota_server [keygen | manifest <role> <version> <pcb_revision> <file>]";
+
 fn main() {
     // check if folder path exists
     if !Path::new(FOLDER_PATH).exists() {
@@ -15,57 +29,470 @@ fn main() {
         return;
     }
 
-    let listener = TcpListener::bind(SERVER_ADRESS).unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("keygen") => {
+            generate_key();
+            return;
+        }
+        Some("manifest") => {
+            add_to_manifest(&args[1..]);
+            return;
+        }
+        Some(_) => {
+            eprintln!("{USAGE}");
+            return;
+        }
+    }
+
+    let listener = match TcpListener::bind(SERVER_ADRESS) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind {SERVER_ADRESS}: {e}");
+            return;
+        }
+    };
     println!("--> OTA server started on {SERVER_ADRESS}");
 
+    serve(listener, Path::new(FOLDER_PATH));
+}
+
+/// Serves `folder` to every client of `listener`.
+fn serve(listener: TcpListener, folder: &Path) {
     for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("-> Connection failed: {e}");
+                continue;
+            }
+        };
+
         println!("-> Connection");
-        let mut stream = stream.unwrap();
+        // One thread per client, so a slow download does not block the other nodes.
+        let folder = folder.to_path_buf();
+        thread::spawn(move || handle_connection(stream, &folder));
+    }
+}
 
-        let buf_reader = BufReader::new(&stream);
-        let http_request: Vec<_> = buf_reader
-            .lines()
-            .map(|result| result.unwrap())
-            .take_while(|line| !line.is_empty())
-            .collect();
+fn handle_connection(mut stream: TcpStream, folder: &Path) {
+    _ = stream.set_read_timeout(Some(READ_TIMEOUT));
 
-        let requested_path = http_request
-            .iter()
-            .find(|line| line.starts_with("GET "))
-            .map(|line| line.split_whitespace().nth(1).unwrap_or("/"))
-            .unwrap_or("/");
+    let (response, method) = match http::read_request(&stream) {
+        Ok(request) => (respond(&request, folder), Some(request.method)),
+        Err(RequestError::Closed) => return,
+        Err(RequestError::Io(e)) => {
+            eprintln!("--> Failed to read request: {e}");
+            return;
+        }
+        Err(e @ RequestError::BadRequest(_)) => {
+            eprintln!("--> {e}");
+            (Response::new(400), None)
+        }
+        Err(e @ RequestError::MethodNotAllowed(_)) => {
+            eprintln!("--> {e}");
+            (Response::new(405).header("Allow", "GET, HEAD"), None)
+        }
+    };
 
-        let file_path = Path::new(FOLDER_PATH).join(requested_path.trim_start_matches('/'));
+    if let Err(e) = response.write(&mut stream, method) {
+        eprintln!("--> Failed to send response {}: {e}", response.status());
+    }
+}
 
-        println!("-> requested: {}", file_path.display());
+fn respond(request: &Request, folder: &Path) -> Response {
+    if let Some(role) = request.path.strip_prefix("/manifest/") {
+        return manifest_response(folder, role, request.query_parameter("pcb_revision"));
+    }
 
-        let binary = match read(file_path) {
-            Ok(binary) => binary,
-            Err(e) => {
-                eprintln!("--> Not found: {e}");
+    let Some(relative_path) = http::normalize_path(&request.path) else {
+        eprintln!("--> Rejected path: {}", request.path);
+        return Response::new(400);
+    };
+    let file_path = folder.join(relative_path);
 
-                let response = "HTTP/1.1 404 Not Found\r\n\r\n";
-                _ = stream.write_all(response.as_bytes());
-                continue;
-            }
-        };
+    println!("-> requested: {}", file_path.display());
+
+    if !file_path.is_file() {
+        eprintln!("--> Not found: {}", file_path.display());
+        return Response::new(404);
+    }
+
+    let binary = match read(&file_path) {
+        Ok(binary) => binary,
+        Err(e) => {
+            eprintln!("--> Not found: {e}");
+            return Response::new(404);
+        }
+    };
+
+    let len = binary.len() as u64;
+    let crc32 = crc32fast::hash(&binary);
 
-        let headers = [
-            "HTTP/1.1 200 OK",
-            "Content-Type: application/octet-stream",
-            &format!("Content-Length: {}", binary.len()),
-        ]
-        .join("\r\n");
+    println!("--> found: binary: {len} bytes / crc32: {crc32}");
 
-        let response = format!("{headers}\r\n\r\n");
+    let range = request
+        .header("Range")
+        .and_then(|range| http::parse_range(range, len));
+    let (status, body) = match range {
+        None => (200, binary),
+        Some(ByteRange::Satisfiable { start, end }) => {
+            println!("--> range: {start}-{end}");
+            (206, binary[start as usize..=end as usize].to_vec())
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            eprintln!("--> Range not satisfiable: {:?}", request.header("Range"));
+            return Response::new(416).header("Content-Range", format!("bytes */{len}"));
+        }
+    };
 
+    // The device checks the downloaded image against X-Crc32 before it marks it bootable.
+    // It always describes the whole file, also for a partial response.
+    let mut response = Response::new(status)
+        .header("Content-Type", "application/octet-stream")
+        .header("Accept-Ranges", "bytes")
+        .header("X-Crc32", crc32);
+    if let Some(ByteRange::Satisfiable { start, end }) = range {
+        response = response.header("Content-Range", format!("bytes {start}-{end}/{len}"));
+    }
+
+    response.body(body)
+}
+
+/// `keygen`: creates the signing key and prints the public key for the devices.
+fn generate_key() {
+    match signing::generate_key(Path::new(signing::SIGNING_KEY_FILE)) {
+        Ok(key) => {
+            println!("--> signing key written to {}", signing::SIGNING_KEY_FILE);
+            println!("--> add to software/src/secret.rs:");
+            println!("{}", signing::public_key_constant(&key.verifying_key()));
+        }
+        Err(e) => eprintln!("Failed to create {}: {e}", signing::SIGNING_KEY_FILE),
+    }
+}
+
+/// `manifest <role> <version> <pcb_revision> <file>`: adds or replaces the manifest entry
+/// for role and PCB revision, size and checksums are read from the file in `bin/`. The
+/// image is signed and the signature written to `<file>.sig`.
+fn add_to_manifest(args: &[String]) {
+    let [role, version, pcb_revision, file] = args else {
+        eprintln!("{USAGE}");
+        return;
+    };
+
+    let version = match semver::Version::parse(version) {
+        Ok(version) => version,
+        Err(e) => {
+            eprintln!("Invalid version {version}: {e}");
+            return;
+        }
+    };
+
+    let key = match signing::load_key(Path::new(signing::SIGNING_KEY_FILE)) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Failed to load {}: {e}", signing::SIGNING_KEY_FILE);
+            eprintln!("Run `ota_server keygen` first");
+            return;
+        }
+    };
+
+    let folder = Path::new(FOLDER_PATH);
+    let result = Manifest::load(folder).and_then(|mut manifest| {
+        let entry = FirmwareEntry::from_file(folder, role, version, pcb_revision, file, &key)?;
+        entry.write_signature(folder)?;
         println!(
-            "--> found: binary: {} bytes / crc32: {}",
-            binary.len(),
-            crc32fast::hash(&binary)
+            "--> {} {} ({}): {} / {} bytes / crc32: {} / sha256: {}",
+            entry.role,
+            entry.version,
+            entry.pcb_revision,
+            entry.file,
+            entry.size,
+            entry.crc32,
+            entry.sha256
+        );
+        manifest.upsert(entry);
+        manifest.save(folder)
+    });
+
+    if let Err(e) = result {
+        eprintln!("Failed to update manifest: {e}");
+    }
+}
+
+/// `GET /manifest/<role>[?pcb_revision=<revision>]`: the manifest entries of the role
+/// as JSON array. Entries whose file or signature does not match the manifest are left out.
+fn manifest_response(folder: &Path, role: &str, pcb_revision: Option<&str>) -> Response {
+    let key = match signing::load_key(Path::new(signing::SIGNING_KEY_FILE)) {
+        Ok(key) => key.verifying_key(),
+        Err(e) => {
+            eprintln!("--> Failed to load {}: {e}", signing::SIGNING_KEY_FILE);
+            return Response::new(500);
+        }
+    };
+
+    let manifest = match Manifest::load(folder) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("--> Invalid manifest: {e}");
+            return Response::new(500);
+        }
+    };
+
+    let entries: Vec<&FirmwareEntry> = manifest
+        .for_role(role, pcb_revision)
+        .filter(|entry| match entry.verify(folder, &key) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("--> Manifest entry skipped: {e}");
+                false
+            }
+        })
+        .collect();
+
+    if entries.is_empty() {
+        eprintln!("--> No firmware for role: {role}");
+        return Response::new(404);
+    }
+
+    println!("--> manifest: {role}: {} entries", entries.len());
+
+    let body = serde_json::to_vec(&entries).expect("manifest entries serialize");
+    Response::new(200)
+        .header("Content-Type", "application/json")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::SocketAddr,
+        path::PathBuf,
+        process,
+    };
+
+    use super::*;
+
+    const IMAGE_LEN: usize = 100_000;
+
+    struct Reply {
+        status: u16,
+        head: String,
+        body: Vec<u8>,
+    }
+
+    impl Reply {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.head.lines().find_map(|line| {
+                let (n, value) = line.split_once(':')?;
+                n.eq_ignore_ascii_case(name).then(|| value.trim())
+            })
+        }
+    }
+
+    fn image() -> Vec<u8> {
+        (0..IMAGE_LEN).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Serves a fresh `bin/` with `v2_6/kombiinstrument.bin` on a free local port. A
+    /// `secret` file next to `bin/` must never be reachable.
+    fn start(name: &str) -> SocketAddr {
+        let root: PathBuf = env::temp_dir().join(format!("ota_server_{}_{name}", process::id()));
+        let folder = root.join("bin");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(folder.join("v2_6")).unwrap();
+        fs::write(folder.join("v2_6/kombiinstrument.bin"), image()).unwrap();
+        fs::write(root.join("secret"), "signing key").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, &folder));
+
+        address
+    }
+
+    fn send(address: SocketAddr, request: &str) -> Reply {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+
+        let split = reply
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("complete head");
+        let head = String::from_utf8(reply[..split].to_vec()).unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+
+        Reply {
+            status,
+            head,
+            body: reply[split + 4..].to_vec(),
+        }
+    }
+
+    fn get(address: SocketAddr, path: &str, headers: &str) -> Reply {
+        send(address, &format!("GET {path} HTTP/1.1\r\n{headers}\r\n"))
+    }
+
+    #[test]
+    fn download() {
+        let address = start("download");
+        let reply = get(address, "/v2_6/kombiinstrument.bin", "");
+
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body, image());
+        assert_eq!(reply.header("Content-Length"), Some("100000"));
+        assert_eq!(
+            reply.header("X-Crc32"),
+            Some(crc32fast::hash(&image()).to_string().as_str())
+        );
+        assert_eq!(reply.header("Accept-Ranges"), Some("bytes"));
+
+        assert_eq!(get(address, "/v2_6/missing.bin", "").status, 404);
+        assert_eq!(get(address, "/v2_6", "").status, 404);
+    }
+
+    #[test]
+    fn head() {
+        let address = start("head");
+        let reply = send(address, "HEAD /v2_6/kombiinstrument.bin HTTP/1.1\r\n\r\n");
+
+        assert_eq!(reply.status, 200);
+        assert!(reply.body.is_empty());
+        assert_eq!(reply.header("Content-Length"), Some("100000"));
+        assert!(reply.header("X-Crc32").is_some());
+    }
+
+    #[test]
+    fn range() {
+        let address = start("range");
+        let reply = get(
+            address,
+            "/v2_6/kombiinstrument.bin",
+            "Range: bytes=40000-\r\n",
+        );
+
+        assert_eq!(reply.status, 206);
+        assert_eq!(reply.body, image()[40_000..]);
+        assert_eq!(
+            reply.header("Content-Range"),
+            Some("bytes 40000-99999/100000")
+        );
+        assert_eq!(reply.header("Content-Length"), Some("60000"));
+        // The checksum always covers the whole image.
+        assert_eq!(
+            reply.header("X-Crc32"),
+            Some(crc32fast::hash(&image()).to_string().as_str())
+        );
+
+        let reply = get(address, "/v2_6/kombiinstrument.bin", "range: bytes=-10\r\n");
+        assert_eq!(reply.status, 206);
+        assert_eq!(reply.body, image()[IMAGE_LEN - 10..]);
+
+        // Multiple ranges are not supported, the whole image is served.
+        let reply = get(
+            address,
+            "/v2_6/kombiinstrument.bin",
+            "Range: bytes=0-1,5-6\r\n",
+        );
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body.len(), IMAGE_LEN);
+    }
+
+    #[test]
+    fn range_not_satisfiable() {
+        let address = start("range_not_satisfiable");
+        let reply = get(
+            address,
+            "/v2_6/kombiinstrument.bin",
+            "Range: bytes=100000-\r\n",
+        );
+
+        assert_eq!(reply.status, 416);
+        assert_eq!(reply.header("Content-Range"), Some("bytes */100000"));
+        assert!(reply.body.is_empty());
+    }
+
+    #[test]
+    fn path_traversal() {
+        let address = start("path_traversal");
+
+        for path in [
+            "/../secret",
+            "/v2_6/../../secret",
+            "/%2e%2e/secret",
+            "/%2E%2E%2Fsecret",
+            "/v2_6/%2e%2e%2f%2e%2e%2fsecret",
+            "/..%5csecret",
+        ] {
+            let reply = get(address, path, "");
+            assert_eq!(reply.status, 400, "{path}");
+            assert!(reply.body.is_empty(), "{path}");
+        }
+
+        // Inside the folder, `..` is rejected as well instead of being resolved.
+        assert_eq!(
+            get(address, "/v2_6/../v2_6/kombiinstrument.bin", "").status,
+            400
         );
+    }
+
+    #[test]
+    fn bad_request() {
+        let address = start("bad_request");
+
+        assert_eq!(send(address, "GET /\r\n\r\n").status, 400);
+        assert_eq!(send(address, "GET / HTTP/2\r\n\r\n").status, 400);
+        assert_eq!(
+            send(address, "GET / HTTP/1.1\r\nbroken\r\n\r\n").status,
+            400
+        );
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let address = start("method_not_allowed");
+        let reply = send(
+            address,
+            "POST /v2_6/kombiinstrument.bin HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+        );
+
+        assert_eq!(reply.status, 405);
+        assert_eq!(reply.header("Allow"), Some("GET, HEAD"));
+    }
+
+    #[test]
+    fn concurrent_downloads() {
+        let address = start("concurrent_downloads");
+
+        // A client that stalls in the middle of its request must not hold up the others.
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled
+            .write_all(b"GET /v2_6/kombiinstrument.bin HTTP/1.1\r\n")
+            .unwrap();
+
+        let downloads: Vec<_> = (0..8)
+            .map(|i| {
+                thread::spawn(move || {
+                    let range = format!("Range: bytes={}-\r\n", i * 1000);
+                    get(address, "/v2_6/kombiinstrument.bin", &range)
+                })
+            })
+            .collect();
+        for (i, download) in downloads.into_iter().enumerate() {
+            let reply = download.join().unwrap();
+            assert_eq!(reply.status, 206);
+            assert_eq!(reply.body, image()[i * 1000..]);
+        }
 
-        _ = stream.write_all(response.as_bytes());
-        _ = stream.write_all(&binary);
+        stalled.write_all(b"\r\n").unwrap();
+        let mut reply = Vec::new();
+        stalled.read_to_end(&mut reply).unwrap();
+        assert!(reply.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(reply.ends_with(&image()));
     }
 }
@@ -0,0 +1,147 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::signing;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// One firmware image in `bin/`, built for one role and one PCB revision.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirmwareEntry {
+    pub role: String,
+    pub version: Version,
+    /// Name of the PCB folder the image was built for, e.g. `v2_5` or `v2_6`.
+    pub pcb_revision: String,
+    /// File name relative to `bin/`.
+    pub file: String,
+    pub size: u64,
+    pub crc32: u32,
+    /// Lowercase hex.
+    pub sha256: String,
+    /// Ed25519 signature of the SHA-256 digest, lowercase hex. Entries written before images
+    /// were signed have none and are not served.
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl FirmwareEntry {
+    /// Reads `file` from `folder`, fills in size and checksums and signs it with `key`.
+    pub fn from_file(
+        folder: &Path,
+        role: &str,
+        version: Version,
+        pcb_revision: &str,
+        file: &str,
+        key: &SigningKey,
+    ) -> io::Result<Self> {
+        let binary = fs::read(folder.join(file))?;
+        let sha256: [u8; 32] = Sha256::digest(&binary).into();
+
+        Ok(Self {
+            role: role.to_string(),
+            version,
+            pcb_revision: pcb_revision.to_string(),
+            file: file.to_string(),
+            size: binary.len() as u64,
+            crc32: crc32fast::hash(&binary),
+            sha256: hex::encode(sha256),
+            signature: hex::encode(signing::sign(key, &sha256).to_bytes()),
+        })
+    }
+
+    /// `<file>.sig` next to the image, for tools that fetch the image without the manifest.
+    pub fn signature_path(&self, folder: &Path) -> PathBuf {
+        folder.join(format!("{}.{}", self.file, signing::SIGNATURE_EXTENSION))
+    }
+
+    pub fn write_signature(&self, folder: &Path) -> io::Result<()> {
+        fs::write(self.signature_path(folder), format!("{}\n", self.signature))
+    }
+
+    /// Checks that the file in `folder` still matches size and checksums of the entry, and
+    /// that the signature was made by `key`.
+    pub fn verify(&self, folder: &Path, key: &VerifyingKey) -> Result<(), String> {
+        let binary =
+            fs::read(folder.join(&self.file)).map_err(|e| format!("{}: {e}", self.file))?;
+
+        if binary.len() as u64 != self.size {
+            return Err(format!(
+                "{}: size {} does not match manifest size {}",
+                self.file,
+                binary.len(),
+                self.size
+            ));
+        }
+        if crc32fast::hash(&binary) != self.crc32 {
+            return Err(format!("{}: crc32 does not match manifest", self.file));
+        }
+        let sha256: [u8; 32] = Sha256::digest(&binary).into();
+        if hex::encode(sha256) != self.sha256 {
+            return Err(format!("{}: sha256 does not match manifest", self.file));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok())
+            .ok_or_else(|| format!("{}: not signed", self.file))?;
+        if !signing::verify(key, &sha256, &signature) {
+            return Err(format!("{}: signature does not match", self.file));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub firmware: Vec<FirmwareEntry>,
+}
+
+impl Manifest {
+    pub fn path(folder: &Path) -> PathBuf {
+        folder.join(MANIFEST_FILE)
+    }
+
+    /// A missing manifest is an empty one.
+    pub fn load(folder: &Path) -> io::Result<Self> {
+        let json = match fs::read_to_string(Self::path(folder)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, folder: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        fs::write(Self::path(folder), json + "\n")
+    }
+
+    /// Replaces the entry for the same role and PCB revision, there is only one current
+    /// image per combination.
+    pub fn upsert(&mut self, entry: FirmwareEntry) {
+        self.firmware
+            .retain(|e| e.role != entry.role || e.pcb_revision != entry.pcb_revision);
+        self.firmware.push(entry);
+    }
+
+    pub fn for_role<'a>(
+        &'a self,
+        role: &'a str,
+        pcb_revision: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a FirmwareEntry> + 'a {
+        self.firmware.iter().filter(move |e| {
+            e.role == role && pcb_revision.is_none_or(|revision| e.pcb_revision == revision)
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use ed25519_dalek::{SECRET_KEY_LENGTH, Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+/// Kept next to the server binary, not in `bin/`, everything in there is served.
+pub const SIGNING_KEY_FILE: &str = "./signing.key";
+
+/// Extension of the signature file published next to each image.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Creates a new signing key, an existing key is never overwritten.
+pub fn generate_key(path: &Path) -> io::Result<SigningKey> {
+    let mut secret = [0; SECRET_KEY_LENGTH];
+    File::open("/dev/urandom")?.read_exact(&mut secret)?;
+    let key = SigningKey::from_bytes(&secret);
+
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(hex::encode(secret).as_bytes())?;
+
+    Ok(key)
+}
+
+pub fn load_key(path: &Path) -> io::Result<SigningKey> {
+    let hex = fs::read_to_string(path)?;
+    let secret: [u8; SECRET_KEY_LENGTH] = hex::decode(hex.trim())
+        .ok()
+        .and_then(|secret| secret.try_into().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: not a hex encoded ed25519 key", path.display()),
+            )
+        })?;
+
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// The image is signed by its SHA-256 digest, so the device can hash the download in chunks
+/// and only verify the 32 byte digest.
+pub fn sign(key: &SigningKey, sha256: &[u8; 32]) -> Signature {
+    key.sign(sha256)
+}
+
+pub fn verify(key: &VerifyingKey, sha256: &[u8; 32], signature: &Signature) -> bool {
+    key.verify(sha256, signature).is_ok()
+}
+
+/// Formats the public key the way `software/src/secret.rs` expects it.
+pub fn public_key_constant(key: &VerifyingKey) -> String {
+    let bytes: Vec<String> = key
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("0x{byte:02x}"))
+        .collect();
+
+    format!(
+        "pub const OTA_PUBLIC_KEY: [u8; 32] = [{}];",
+        bytes.join(", ")
+    )
+}
@@ -0,0 +1,69 @@
+//! Receive path of a node's CAN bus.
+//!
+//! The can_receiver thread blocks on the bus and forwards every subscribed frame to the
+//! app_thread as soon as it arrives. Transmitting goes straight into the controller's
+//! TX queue from the app_thread; the bus is not locked, so a waiting receive never
+//! delays a transmission.
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::Builder,
+    time::Duration,
+};
+
+use crate::{
+    can_stats::CanStats,
+    dbg_println,
+    hal::{CanFrame, SharedCan},
+    protocol::filter::AcceptanceFilter,
+};
+
+/// Frames the app_thread has not picked up yet. The kombiinstrument gets about 30 frames
+/// per 100 ms app cycle, most of them from the engine ECU.
+const INCOMING_QUEUE_LEN: usize = 64;
+
+/// Upper bound of a single blocking receive, the thread simply waits again afterwards.
+const RX_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spawns the can_receiver thread. `tag` prefixes its log lines, e.g. `KBI`.
+pub fn spawn_receiver(
+    can: SharedCan,
+    acceptance_filter: AcceptanceFilter,
+    stats: Arc<CanStats>,
+    tag: &'static str,
+) -> Receiver<CanFrame> {
+    let (incoming_frames_tx, incoming_frames_rx) = mpsc::sync_channel(INCOMING_QUEUE_LEN);
+
+    let can_receiver_thread_builder = Builder::new()
+        .name("can_receiver".into())
+        .stack_size(8 * 1024);
+    let _ = can_receiver_thread_builder.spawn(move || loop {
+        let Some(frame) = can.receive(RX_TIMEOUT) else {
+            continue;
+        };
+
+        dbg_println!(
+            "[{}/can <-] {:X} {:?}",
+            tag,
+            frame.identifier(),
+            frame.data()
+        );
+        // The hardware filter lets a few more identifiers through than subscribed.
+        if !acceptance_filter.subscribed(frame.identifier()) {
+            continue;
+        }
+        if let Err(e) = incoming_frames_tx.try_send(frame) {
+            stats.rx_dropped();
+            dbg_println!(
+                "[{}/can   ] Incoming frame dropped, channel full: {:?}",
+                tag,
+                e
+            );
+        }
+    });
+
+    incoming_frames_rx
+}
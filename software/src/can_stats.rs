@@ -0,0 +1,196 @@
+//! Traffic and error statistics of a node's CAN bus.
+//!
+//! [`CountingBus`] sits between the node and its bus and counts every frame, the
+//! can_receiver adds the frames it had to drop. Once per [`STATS_INTERVAL`] the
+//! can_stats thread publishes the counts of the past interval on the node's diagnostic
+//! identifier, see [`crate::protocol::CanTraffic`] and [`crate::protocol::CanErrors`].
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread::{self, Builder},
+    time::Duration,
+};
+
+use crate::{
+    config::Bitrate,
+    dbg_println,
+    hal::{Alerts, BusStatus, CanBus, CanFrame, SharedCan},
+    protocol::{CanErrors, CanTraffic},
+};
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bits of a standard data frame on the wire, including the interframe space but
+/// without stuff bits.
+fn frame_bits(frame: &CanFrame) -> u32 {
+    47 + 8 * frame.data().len() as u32
+}
+
+/// Counters of the running interval, reset by every publication.
+#[derive(Debug, Default)]
+pub struct CanStats {
+    rx_frames: AtomicU32,
+    tx_frames: AtomicU32,
+    /// Bits of all received and transmitted frames, for the bus load.
+    bits: AtomicU32,
+    rx_dropped: AtomicU32,
+    tx_failed: AtomicU32,
+    tx_retried: AtomicU32,
+    tec_peak: AtomicU32,
+    rec_peak: AtomicU32,
+}
+
+impl CanStats {
+    /// A received frame the app_thread's channel had no room for.
+    pub fn rx_dropped(&self) {
+        self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn error_counters(&self, status: &BusStatus) {
+        self.tec_peak
+            .fetch_max(status.tx_error_counter, Ordering::Relaxed);
+        self.rec_peak
+            .fetch_max(status.rx_error_counter, Ordering::Relaxed);
+    }
+}
+
+/// Counts the traffic of the wrapped bus into [`CanStats`].
+pub struct CountingBus {
+    bus: SharedCan,
+    stats: Arc<CanStats>,
+}
+
+impl CountingBus {
+    pub fn new(bus: SharedCan, stats: Arc<CanStats>) -> Self {
+        Self { bus, stats }
+    }
+}
+
+impl CanBus for CountingBus {
+    fn transmit(&self, frame: &CanFrame) -> anyhow::Result<()> {
+        let result = self.bus.transmit(frame);
+        if result.is_ok() {
+            self.stats.tx_frames.fetch_add(1, Ordering::Relaxed);
+            self.stats
+                .bits
+                .fetch_add(frame_bits(frame), Ordering::Relaxed);
+        } else {
+            self.stats.tx_failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<CanFrame> {
+        let frame = self.bus.receive(timeout)?;
+        self.stats.rx_frames.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .bits
+            .fetch_add(frame_bits(&frame), Ordering::Relaxed);
+
+        Some(frame)
+    }
+
+    fn wait_for_alerts(&self, timeout: Duration) -> Alerts {
+        let alerts = self.bus.wait_for_alerts(timeout);
+        // The alert is latched, several retries between two reads count once.
+        if alerts.transmit_retried {
+            self.stats.tx_retried.fetch_add(1, Ordering::Relaxed);
+        }
+
+        alerts
+    }
+
+    fn status(&self) -> anyhow::Result<BusStatus> {
+        let status = self.bus.status()?;
+        self.stats.error_counters(&status);
+
+        Ok(status)
+    }
+
+    fn initiate_recovery(&self) -> anyhow::Result<()> {
+        self.bus.initiate_recovery()
+    }
+
+    fn restart(&self) -> anyhow::Result<()> {
+        self.bus.restart()
+    }
+}
+
+fn saturating_u8(count: u32) -> u8 {
+    count.min(u8::MAX as u32) as u8
+}
+
+/// Spawns the can_stats thread, which publishes on `diagnostic_identifier`. The bus load
+/// is relative to `bitrate`. `tag` prefixes its log lines, e.g. `KBI`.
+pub fn spawn_publisher(
+    can: SharedCan,
+    stats: Arc<CanStats>,
+    diagnostic_identifier: u32,
+    bitrate: Bitrate,
+    tag: &'static str,
+) {
+    let can_stats_thread_builder = Builder::new().name("can_stats".into()).stack_size(4 * 1024);
+    let _ = can_stats_thread_builder.spawn(move || {
+        // The controller counts since the driver was installed, only the increase is sent.
+        let mut previous = can.status().unwrap_or_default();
+
+        loop {
+            thread::sleep(STATS_INTERVAL);
+
+            let status = can.status().unwrap_or(previous);
+            let take = |counter: &AtomicU32| counter.swap(0, Ordering::Relaxed);
+            let bits = take(&stats.bits) as u64;
+            let traffic = CanTraffic {
+                rx_frames: take(&stats.rx_frames).min(u16::MAX as u32) as u16,
+                tx_frames: take(&stats.tx_frames).min(u16::MAX as u32) as u16,
+                bus_load_perc: saturating_u8(
+                    (100 * bits / (bitrate.bits_per_second() as u64 * STATS_INTERVAL.as_secs()))
+                        as u32,
+                ),
+                rx_dropped: saturating_u8(take(&stats.rx_dropped)),
+                rx_missed: saturating_u8(status.rx_missed.wrapping_sub(previous.rx_missed)),
+            };
+            let errors = CanErrors {
+                tx_failed: saturating_u8(take(&stats.tx_failed)),
+                tx_retried: saturating_u8(take(&stats.tx_retried)),
+                bus_errors: saturating_u8(status.bus_errors.wrapping_sub(previous.bus_errors)),
+                arbitration_lost: saturating_u8(
+                    status
+                        .arbitration_lost
+                        .wrapping_sub(previous.arbitration_lost),
+                ),
+                tec_peak: saturating_u8(take(&stats.tec_peak)),
+                rec_peak: saturating_u8(take(&stats.rec_peak)),
+            };
+            previous = status;
+
+            dbg_println!(
+                "[{}/stats ] RX: {} (dropped {}, missed {}) | TX: {} (failed {}, retried {}) | Load: {}% | Bus errors: {} | Arb. lost: {} | TEC/REC peak: {}/{}",
+                tag,
+                traffic.rx_frames,
+                traffic.rx_dropped,
+                traffic.rx_missed,
+                traffic.tx_frames,
+                errors.tx_failed,
+                errors.tx_retried,
+                traffic.bus_load_perc,
+                errors.bus_errors,
+                errors.arbitration_lost,
+                errors.tec_peak,
+                errors.rec_peak
+            );
+
+            // Sent through the counting bus, so they show up in the next interval.
+            for data in [traffic.encode(), errors.encode()] {
+                let frame = CanFrame::new(diagnostic_identifier, &data).unwrap();
+                if let Err(e) = can.transmit(&frame) {
+                    dbg_println!("[{}/stats ] Failed to send the statistics: {}", tag, e);
+                }
+            }
+        }
+    });
+}
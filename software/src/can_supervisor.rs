@@ -0,0 +1,121 @@
+//! Error state supervision of the CAN controller.
+//!
+//! The can_supervisor thread wakes up on every TWAI alert, reads the error counters and
+//! brings the controller back after a bus-off: recovery first, then a restart of the
+//! driver. The app_thread publishes the result with [`CanHealth::report`].
+
+use std::{
+    sync::{Arc, Mutex},
+    thread::Builder,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dbg_println,
+    hal::{BusState, BusStatus, SharedCan},
+    protocol::ErrorCode,
+    status::NodeStatus,
+};
+
+/// Upper bound of a single wait for alerts, the status is read at least this often.
+const ALERT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The controller raises its own error warning above this TEC/REC value.
+const ERROR_WARNING_LIMIT: u32 = 96;
+
+/// A bus-off cannot be published while it lasts, so it stays a warning this long after
+/// the recovery to reach the dashboard.
+const BUS_OFF_HOLD: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Health {
+    status: BusStatus,
+    /// Recovery was initiated and the driver still has to be restarted.
+    recovering: bool,
+    recovered_at: Option<Instant>,
+}
+
+/// Shared between the can_supervisor thread and the app_thread.
+#[derive(Debug, Clone)]
+pub struct CanHealth(Arc<Mutex<Health>>);
+
+impl CanHealth {
+    /// Raises `CanBusOff` as critical while the controller is off the bus and as warning
+    /// for [`BUS_OFF_HOLD`] after it came back, `CanErrorCounters` while TEC or REC are
+    /// above the warning limit.
+    pub fn report(&self, node_status: &mut NodeStatus, now: Instant) {
+        let health = self.0.lock().unwrap();
+
+        if health.recovering || health.status.state == BusState::BusOff {
+            node_status.critical(ErrorCode::CanBusOff);
+        } else {
+            let recently_recovered = health
+                .recovered_at
+                .is_some_and(|at| now.saturating_duration_since(at) < BUS_OFF_HOLD);
+            node_status.check(ErrorCode::CanBusOff, !recently_recovered);
+        }
+
+        node_status.check(
+            ErrorCode::CanErrorCounters,
+            health.status.tx_error_counter <= ERROR_WARNING_LIMIT
+                && health.status.rx_error_counter <= ERROR_WARNING_LIMIT,
+        );
+    }
+}
+
+/// Spawns the can_supervisor thread. `tag` prefixes its log lines, e.g. `KBI`.
+pub fn spawn_supervisor(can: SharedCan, tag: &'static str) -> CanHealth {
+    let health = CanHealth(Arc::new(Mutex::new(Health {
+        status: BusStatus::default(),
+        recovering: false,
+        recovered_at: None,
+    })));
+
+    let thread_health = health.clone();
+    let can_supervisor_thread_builder = Builder::new()
+        .name("can_supervisor".into())
+        .stack_size(4 * 1024);
+    let _ = can_supervisor_thread_builder.spawn(move || loop {
+        can.wait_for_alerts(ALERT_TIMEOUT);
+
+        let status = match can.status() {
+            Ok(status) => status,
+            Err(e) => {
+                dbg_println!("[{}/can   ] Failed to read the bus status: {}", tag, e);
+                continue;
+            }
+        };
+
+        let mut health = thread_health.0.lock().unwrap();
+        if status.state != health.status.state {
+            dbg_println!(
+                "[{}/can   ] {:?} -> {:?} (TEC {}, REC {})",
+                tag,
+                health.status.state,
+                status.state,
+                status.tx_error_counter,
+                status.rx_error_counter
+            );
+        }
+
+        match status.state {
+            BusState::BusOff if !health.recovering => match can.initiate_recovery() {
+                Ok(()) => health.recovering = true,
+                Err(e) => dbg_println!("[{}/can   ] Failed to start the recovery: {}", tag, e),
+            },
+            // The recovery ends in the stopped state.
+            BusState::Stopped if health.recovering => match can.restart() {
+                Ok(()) => {
+                    dbg_println!("[{}/can   ] Back on the bus", tag);
+                    health.recovering = false;
+                    health.recovered_at = Some(Instant::now());
+                }
+                Err(e) => dbg_println!("[{}/can   ] Failed to restart the driver: {}", tag, e),
+            },
+            _ => {}
+        }
+        health.status = status;
+    });
+
+    health
+}
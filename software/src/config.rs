@@ -0,0 +1,352 @@
+//! Node configuration, read from the `espio` NVS namespace at boot.
+//!
+//! Every board runs the same image, the role decides what it does. Only `role` has to
+//! be written, all other keys fall back to the defaults of that role:
+//!
+//! | key          | type | default                                 |
+//! |--------------|------|-----------------------------------------|
+//! | `role`       | str  | -                                       |
+//! | `can_id`     | u32  | [`Role::default_identifier`]            |
+//! | `bitrate`    | u32  | 500000, see [`Bitrate`]                 |
+//! | `can_tx_pin` | u32  | [`Role::default_can_pins`]              |
+//! | `can_rx_pin` | u32  | [`Role::default_can_pins`]              |
+//! | `pcb_rev`    | str  | [`DEFAULT_PCB_REVISION`]                |
+//!
+//! The other nodes subscribe to the default identifiers, only change `can_id` together
+//! with them.
+//!
+//! Only the CAN pins can be mapped. The inputs and outputs of a node are fixed by the PCB
+//! and listed in [`Role::fixed_pins`].
+
+use std::fmt;
+
+use crate::protocol::{
+    diagnostic_id,
+    oem::{GRA_NEU_ID, MOTOR_1_ID, MOTOR_2_ID, MOTOR_3_ID, MOTOR_5_ID, MOTOR_6_ID, MOTOR_7_ID},
+    DEV_CAN_SENDER_ID, ENGINE_BAY_UNIT_ID, KOMBIINSTRUMENT_ID, NODE_REQUEST_ID, OUTPUT_TEST_ID,
+    PARAMETER_REQUEST_ID, WHEEL_SPEEDS_ID,
+};
+
+#[cfg(target_os = "espidf")]
+pub const NAMESPACE: &str = "espio";
+
+/// Selects the image from the ota_server manifest, together with the role.
+pub const DEFAULT_PCB_REVISION: &str = "v2_6";
+
+/// Highest standard (11 bit) identifier.
+const MAX_IDENTIFIER: u32 = 0x7ff;
+
+/// GPIO26 to GPIO32 connect the flash and PSRAM of the ESP32-S3-WROOM module.
+const FLASH_PINS: std::ops::RangeInclusive<u8> = 26..=32;
+/// GPIO22 to GPIO25 do not exist on the ESP32-S3.
+const MISSING_PINS: std::ops::RangeInclusive<u8> = 22..=25;
+const MAX_PIN: u8 = 48;
+
+/// Key-value storage the configuration is read from, NVS on the ESP32-S3. The
+/// [`crate::parameters`] are saved in it as well.
+pub trait ConfigStore {
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>>;
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>>;
+    fn set_u32(&mut self, key: &str, value: u32) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Kombiinstrument,
+    EngineBayUnit,
+    DevCanSender,
+    OutputTest,
+}
+
+impl Role {
+    const ALL: [Role; 4] = [
+        Role::Kombiinstrument,
+        Role::EngineBayUnit,
+        Role::DevCanSender,
+        Role::OutputTest,
+    ];
+
+    /// Stored in NVS and used as the ota_server manifest name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Kombiinstrument => "kombiinstrument",
+            Role::EngineBayUnit => "engine_bay_unit",
+            Role::DevCanSender => "dev_can_sender",
+            Role::OutputTest => "output_test",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    pub fn default_identifier(self) -> u32 {
+        match self {
+            Role::Kombiinstrument => KOMBIINSTRUMENT_ID,
+            Role::EngineBayUnit => ENGINE_BAY_UNIT_ID,
+            Role::DevCanSender => DEV_CAN_SENDER_ID,
+            Role::OutputTest => OUTPUT_TEST_ID,
+        }
+    }
+
+    /// The engine bay unit needs GPIO48 for the onboard LED.
+    pub fn default_can_pins(self) -> CanPins {
+        match self {
+            Role::EngineBayUnit => CanPins { tx: 46, rx: 47 },
+            _ => CanPins { tx: 48, rx: 47 },
+        }
+    }
+
+    /// GPIOs the role drives or reads itself, the CAN pins must not use them.
+    pub fn fixed_pins(self) -> &'static [u8] {
+        match self {
+            Role::Kombiinstrument => &[10, 11, 12, 13, 14, 21, 45],
+            Role::EngineBayUnit => &[4, 5, 6, 7, 14, 15, 16, 17, 18, 21, 45, 48],
+            Role::DevCanSender => &[],
+            Role::OutputTest => &[1],
+        }
+    }
+
+    /// Whether the role publishes CAN statistics on its diagnostic identifier.
+    fn sends_diagnostics(self) -> bool {
+        matches!(self, Role::Kombiinstrument | Role::EngineBayUnit)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bitrate {
+    B125K,
+    B250K,
+    #[default]
+    B500K,
+    B1M,
+}
+
+impl Bitrate {
+    pub fn from_bits_per_second(bits_per_second: u32) -> Option<Self> {
+        match bits_per_second {
+            125_000 => Some(Bitrate::B125K),
+            250_000 => Some(Bitrate::B250K),
+            500_000 => Some(Bitrate::B500K),
+            1_000_000 => Some(Bitrate::B1M),
+            _ => None,
+        }
+    }
+
+    pub fn bits_per_second(self) -> u32 {
+        match self {
+            Bitrate::B125K => 125_000,
+            Bitrate::B250K => 250_000,
+            Bitrate::B500K => 500_000,
+            Bitrate::B1M => 1_000_000,
+        }
+    }
+}
+
+/// GPIO numbers of the CAN transceiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanPins {
+    pub tx: u8,
+    pub rx: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeConfig {
+    pub role: Role,
+    pub own_identifier: u32,
+    pub bitrate: Bitrate,
+    pub can_pins: CanPins,
+    pub pcb_revision: String,
+}
+
+impl NodeConfig {
+    pub fn defaults(role: Role) -> Self {
+        Self {
+            role,
+            own_identifier: role.default_identifier(),
+            bitrate: Bitrate::default(),
+            can_pins: role.default_can_pins(),
+            pcb_revision: DEFAULT_PCB_REVISION.into(),
+        }
+    }
+
+    /// Fails for a missing role and for any value that does not pass [`Self::validate`].
+    pub fn load(store: &impl ConfigStore) -> anyhow::Result<Self> {
+        let role = store
+            .get_str("role")?
+            .ok_or_else(|| anyhow::anyhow!("no role configured"))?;
+        let role =
+            Role::from_name(&role).ok_or_else(|| anyhow::anyhow!("unknown role {role:?}"))?;
+        let mut config = Self::defaults(role);
+
+        if let Some(identifier) = store.get_u32("can_id")? {
+            config.own_identifier = identifier;
+        }
+        if let Some(bits_per_second) = store.get_u32("bitrate")? {
+            config.bitrate = Bitrate::from_bits_per_second(bits_per_second)
+                .ok_or_else(|| anyhow::anyhow!("unsupported bitrate {bits_per_second}"))?;
+        }
+        let pin = |key: &str| -> anyhow::Result<Option<u8>> {
+            store.get_u32(key)?.map_or(Ok(None), |pin| {
+                u8::try_from(pin)
+                    .map(Some)
+                    .map_err(|_| anyhow::anyhow!("{key} GPIO{pin} does not exist"))
+            })
+        };
+        if let Some(tx) = pin("can_tx_pin")? {
+            config.can_pins.tx = tx;
+        }
+        if let Some(rx) = pin("can_rx_pin")? {
+            config.can_pins.rx = rx;
+        }
+        if let Some(pcb_revision) = store.get_str("pcb_rev")? {
+            config.pcb_revision = pcb_revision;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let identifier = self.own_identifier;
+        if identifier > MAX_IDENTIFIER {
+            anyhow::bail!("can_id 0x{identifier:X} is not a standard identifier");
+        }
+        if self.role.sends_diagnostics() && diagnostic_id(identifier) > MAX_IDENTIFIER {
+            anyhow::bail!("can_id 0x{identifier:X} leaves no room for the diagnostic identifier");
+        }
+        let reserved = [
+            NODE_REQUEST_ID,
+            PARAMETER_REQUEST_ID,
+            WHEEL_SPEEDS_ID,
+            MOTOR_1_ID,
+            MOTOR_2_ID,
+            MOTOR_3_ID,
+            GRA_NEU_ID,
+            MOTOR_5_ID,
+            MOTOR_6_ID,
+            MOTOR_7_ID,
+        ];
+        let used_by_other_role = Role::ALL
+            .into_iter()
+            .any(|role| role != self.role && role.default_identifier() == identifier);
+        if reserved.contains(&identifier) || used_by_other_role {
+            anyhow::bail!("can_id 0x{identifier:X} is already used on the bus");
+        }
+
+        let CanPins { tx, rx } = self.can_pins;
+        for pin in [tx, rx] {
+            if pin > MAX_PIN || FLASH_PINS.contains(&pin) || MISSING_PINS.contains(&pin) {
+                anyhow::bail!("GPIO{pin} cannot be used for CAN");
+            }
+            if self.role.fixed_pins().contains(&pin) {
+                anyhow::bail!("GPIO{pin} is already used by the {}", self.role);
+            }
+        }
+        if tx == rx {
+            anyhow::bail!("CAN TX and RX both on GPIO{tx}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::host::SimConfigStore;
+
+    fn load(store: SimConfigStore) -> anyhow::Result<NodeConfig> {
+        NodeConfig::load(&store)
+    }
+
+    fn with_u32(key: &str, value: u32, role: Role) -> SimConfigStore {
+        let mut store = SimConfigStore::provisioned(role);
+        store.set_u32(key, value).unwrap();
+        store
+    }
+
+    #[test]
+    fn defaults_of_every_role() {
+        for role in Role::ALL {
+            let config = load(SimConfigStore::provisioned(role)).unwrap();
+            assert_eq!(config, NodeConfig::defaults(role));
+            assert_eq!(Role::from_name(role.name()), Some(role));
+        }
+    }
+
+    #[test]
+    fn role_is_required() {
+        assert!(load(SimConfigStore::default()).is_err());
+        assert!(load(SimConfigStore::default().with_str("role", "gateway")).is_err());
+    }
+
+    #[test]
+    fn stored_values_override_the_defaults() {
+        let mut store =
+            SimConfigStore::provisioned(Role::EngineBayUnit).with_str("pcb_rev", "v3_0");
+        store.set_u32("can_id", 0x211).unwrap();
+        store.set_u32("bitrate", 250_000).unwrap();
+        store.set_u32("can_tx_pin", 8).unwrap();
+        store.set_u32("can_rx_pin", 9).unwrap();
+
+        assert_eq!(
+            load(store).unwrap(),
+            NodeConfig {
+                role: Role::EngineBayUnit,
+                own_identifier: 0x211,
+                bitrate: Bitrate::B250K,
+                can_pins: CanPins { tx: 8, rx: 9 },
+                pcb_revision: "v3_0".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let invalid = [
+            ("bitrate", 800_000),
+            ("can_tx_pin", 300),
+            ("can_id", 0x800),
+            // Its diagnostic identifier would not be a standard one.
+            ("can_id", 0x400),
+            ("can_id", MOTOR_1_ID),
+            ("can_id", ENGINE_BAY_UNIT_ID),
+        ];
+        for (key, value) in invalid {
+            let store = with_u32(key, value, Role::Kombiinstrument);
+            assert!(load(store).is_err(), "{key} {value:#x}");
+        }
+
+        // Only roles that send diagnostics need the room above their identifier.
+        assert!(load(with_u32("can_id", 0x500, Role::DevCanSender)).is_ok());
+    }
+
+    #[test]
+    fn can_pins_are_checked() {
+        let config = |role: Role, tx: u8, rx: u8| NodeConfig {
+            can_pins: CanPins { tx, rx },
+            ..NodeConfig::defaults(role)
+        };
+
+        assert!(config(Role::Kombiinstrument, 8, 9).validate().is_ok());
+        assert!(config(Role::Kombiinstrument, 8, 8).validate().is_err());
+        assert!(config(Role::Kombiinstrument, 28, 9).validate().is_err());
+        assert!(config(Role::Kombiinstrument, 22, 9).validate().is_err());
+        assert!(config(Role::Kombiinstrument, 8, 25).validate().is_err());
+        assert!(config(Role::Kombiinstrument, 8, 49).validate().is_err());
+        // The engine bay unit drives the LED on the default pin of the other roles.
+        assert!(config(Role::EngineBayUnit, 48, 47).validate().is_err());
+        for role in Role::ALL {
+            for &pin in role.fixed_pins() {
+                assert!(config(role, pin, 9).validate().is_err(), "{role} GPIO{pin}");
+            }
+        }
+    }
+}
@@ -1,11 +1,13 @@
 use enumset::enum_set;
 use esp_idf_hal::{
     can::{
-        config::{Config, Mode, Timing},
+        config::{Config, Mode},
         Alert, CanDriver,
     },
     peripherals::Peripherals,
 };
+
+use crate::{hal::esp::can_pins, ota::BootValidation, self_test::SelfTestCheck, EspData};
 use std::{
     sync::{Arc, Mutex},
     thread::{self, Builder},
@@ -16,22 +18,26 @@ use std::{
 // DO NOT COPY FROM THIS FILE AS THERE IS NO APP-STATE IN HERE
 // ------------------------------------------------------------
 
-pub fn dev_can_sender(own_identifier: u32) {
+pub fn dev_can_sender(data: EspData) {
+    let own_identifier = data.config().own_identifier;
     println!("Init Dev CAN Sender at 0x{own_identifier:X}");
     let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
-    let pins = peripherals.pins;
     let can_config = Config::new()
-        .timing(Timing::B500K)
+        .timing(data.config().bitrate.into())
         .mode(Mode::Normal)
         .alerts(enum_set!(
             Alert::BusOffline | Alert::TransmitFailed | Alert::BusError | Alert::TransmitRetried
         ));
 
+    let (can_tx_pin, can_rx_pin) = can_pins(data.config().can_pins);
     let mut can_driver =
-        CanDriver::new(peripherals.can, pins.gpio48, pins.gpio47, &can_config).unwrap();
+        CanDriver::new(peripherals.can, can_tx_pin, can_rx_pin, &can_config).unwrap();
 
     can_driver.start().expect("Failed to start CAN driver");
 
+    // Only checks the CAN driver, so an updated dev_can_sender does not roll back by itself.
+    BootValidation::start(data.nvs(), &[SelfTestCheck::CanStarted]).pass(SelfTestCheck::CanStarted);
+
     let can_driver = Arc::new(Mutex::new(can_driver));
 
     let can_reader_thread_builder = Builder::new()
@@ -0,0 +1,148 @@
+use esp_idf_hal::{
+    adc::{
+        attenuation::DB_11,
+        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+    },
+    can::CanDriver,
+    gpio::{AnyInputPin, InputPin, PinDriver},
+    prelude::Peripherals,
+};
+use esp_idf_svc::nvs::EspNvs;
+use std::{sync::Arc, thread::Builder};
+
+use super::{acceptance_filter, app_thread, spawn_can_receiver, EngineBayUnitIo};
+use crate::{
+    can_stats::{self, CanStats, CountingBus},
+    can_supervisor, config, dbg_println,
+    hal::{
+        esp::{can_pins, EdgeTimer, EspCan, EspFirmware},
+        SharedCan, SystemClock,
+    },
+    logging,
+    ota::{BootValidation, OtaHandle},
+    protocol::diagnostic_id,
+    self_test::SelfTestCheck,
+    EspData,
+};
+
+pub fn engine_bay_unit(data: EspData) {
+    let own_identifier = data.config().own_identifier;
+    logging::init(true);
+    dbg_println!("Init Engine Bay Unit at 0x{own_identifier:X}");
+
+    let boot_validation = BootValidation::start(
+        data.nvs(),
+        &[
+            SelfTestCheck::CanStarted,
+            SelfTestCheck::HeartbeatSent,
+            SelfTestCheck::AdcReadable,
+        ],
+    );
+
+    let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
+    let pins = peripherals.pins;
+
+    // Note: The default CAN TX is GPIO46 instead of GPIO48 to avoid conflict with onboard LED
+    // GPIO48 is the onboard LED pin on ESP32-S3-DevKit-C1
+
+    // init CAN/TWAI
+    let mut can_config = data.can_config().clone();
+    // The identifiers the hardware filter lets through in addition are dropped by the can_receiver.
+    let acceptance_filter = acceptance_filter();
+    dbg_println!(
+        "[ECU/can   ] {:?}, {} unsubscribed identifiers pass",
+        acceptance_filter.filters(),
+        acceptance_filter.false_positives()
+    );
+    can_config = can_config.filter(acceptance_filter.filters().into());
+
+    let (can_tx_pin, can_rx_pin) = can_pins(data.config().can_pins);
+    let mut can_driver =
+        CanDriver::new(peripherals.can, can_tx_pin, can_rx_pin, &can_config).unwrap();
+    can_driver.start().expect("Failed to start CAN driver");
+    boot_validation.pass(SelfTestCheck::CanStarted);
+    let can_stats = Arc::new(CanStats::default());
+    let can_driver: SharedCan = Arc::new(CountingBus::new(
+        Arc::new(EspCan::new(can_driver)),
+        Arc::clone(&can_stats),
+    ));
+
+    let incoming_frames_rx = spawn_can_receiver(Arc::clone(&can_driver), Arc::clone(&can_stats));
+    let can_health = can_supervisor::spawn_supervisor(Arc::clone(&can_driver), "ECU");
+    can_stats::spawn_publisher(
+        Arc::clone(&can_driver),
+        can_stats,
+        diagnostic_id(own_identifier),
+        data.config().bitrate,
+        "ECU",
+    );
+
+    let firmware = EspFirmware {
+        ota: OtaHandle::spawn(peripherals.modem, data.nvs(), data.config()),
+        boot_validation,
+        reset_reason: data.reset_reason(),
+    };
+
+    let parameter_store =
+        EspNvs::new(data.nvs(), config::NAMESPACE, true).expect("Failed to open NVS");
+
+    let app_thread_builder = Builder::new()
+        .name("app_thread".into())
+        .stack_size(8 * 1024);
+    let _ = app_thread_builder.spawn(move || {
+        // --- Hardware and peripheral setup ---
+        let brake_pedal_pins = (
+            PinDriver::output(pins.gpio21).unwrap(),
+            PinDriver::output(pins.gpio45).unwrap(),
+        );
+        let vdc_pin = pins.gpio14;
+        let abs_fl_pins = (pins.gpio4, pins.gpio5);
+        let abs_fr_pins = (pins.gpio17, pins.gpio18);
+        let abs_rl_pins = (pins.gpio6, pins.gpio16);
+        let abs_rr_pins = (pins.gpio7, pins.gpio15);
+
+        let adc_driver = AdcDriver::new(peripherals.adc2).unwrap();
+        let adc_config = AdcChannelConfig {
+            attenuation: DB_11,
+            ..Default::default()
+        };
+        let adc_channel_driver = AdcChannelDriver::new(adc_driver, vdc_pin, &adc_config).unwrap();
+
+        // Initialize onboard LED control (GPIO48) to ensure it stays off
+        let mut onboard_led = PinDriver::output(pins.gpio48).unwrap();
+        onboard_led.set_low().unwrap(); // Set onboard LED to 0% duty cycle (off)
+
+        // The sensors switch the second pin of each pair.
+        let abs_sensor = |pin: AnyInputPin, name| {
+            EdgeTimer::new(pin)
+                .unwrap_or_else(|e| panic!("[ECU/abs   ] Failed to set up {name}: {e}"))
+        };
+        let abs_fl = abs_sensor(abs_fl_pins.1.downgrade_input(), "FL");
+        let abs_fr = abs_sensor(abs_fr_pins.1.downgrade_input(), "FR");
+        let abs_rl = abs_sensor(abs_rl_pins.1.downgrade_input(), "RL");
+        let abs_rr = abs_sensor(abs_rr_pins.1.downgrade_input(), "RR");
+
+        let io = EngineBayUnitIo {
+            brake_lights: (Box::new(brake_pedal_pins.0), Box::new(brake_pedal_pins.1)),
+            onboard_led: Box::new(onboard_led),
+            vdc: Box::new(adc_channel_driver),
+            abs_sensors: [
+                Box::new(abs_fl),
+                Box::new(abs_fr),
+                Box::new(abs_rl),
+                Box::new(abs_rr),
+            ],
+            firmware: Box::new(firmware),
+            parameter_store: Box::new(parameter_store),
+        };
+
+        app_thread(
+            io,
+            Box::new(SystemClock),
+            can_driver,
+            can_health,
+            own_identifier,
+            incoming_frames_rx,
+        );
+    });
+}
@@ -1,258 +1,545 @@
-use enumset::enum_set;
-use esp_idf_hal::{
-    adc::{
-        attenuation::DB_11,
-        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
-    },
-    can::{config::Filter, CanDriver, Flags, Frame},
-    gpio::{AnyIOPin, PinDriver},
-    pcnt::{self, PcntChannel, PcntDriver, PinIndex},
-    prelude::Peripherals,
-};
 use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread::{self, Builder},
+    sync::{mpsc::Receiver, Arc},
     time::{Duration, Instant},
 };
 
 use crate::{
-    dbg_println, logging, util::frame_data_to_bit_array, EspData
+    can_service,
+    can_stats::CanStats,
+    can_supervisor::CanHealth,
+    config::ConfigStore,
+    dbg_println,
+    freshness::FreshnessTracker,
+    hal::{AnalogInput, CanFrame, Clock, DigitalOutput, EdgeCapture, Firmware, SharedCan},
+    parameters::{Parameter, Parameters},
+    protocol::{
+        filter::AcceptanceFilter, EngineBayStatus, ErrorCode, KombiinstrumentStatus, NodeCommand,
+        NodeRequest, ParameterRequest, UniversalFrame, WheelSpeeds, KOMBIINSTRUMENT_ID,
+        NODE_REQUEST_ID, PARAMETER_REQUEST_ID, WHEEL_SPEEDS_ID,
+    },
+    scheduler::{Scheduler, TaskContext},
+    self_test::SelfTestCheck,
+    status::NodeStatus,
+    wheel_speed::WheelSpeed,
+};
+
+#[cfg(target_os = "espidf")]
+mod esp;
+#[cfg(target_os = "espidf")]
+pub use esp::engine_bay_unit;
+
+/// Frames the app_thread handles, everything else is dropped by the TWAI filter or the
+/// can_receiver.
+const SUBSCRIBED_IDS: [u32; 3] = [NODE_REQUEST_ID, PARAMETER_REQUEST_ID, KOMBIINSTRUMENT_ID];
+
+/// Period of the `can_rx` task, the brake lights follow the pedal within it.
+const CAN_RX_PERIOD: Duration = Duration::from_millis(10);
+
+// --- Parameters, see crate::parameters ---
+
+/// Period of the `cycle` task in ms, which also sends the wheel speeds and the status
+/// frame. Applies after a restart, the kombiinstrument times out after 500 ms.
+const CYCLE_TIME: Parameter = Parameter {
+    number: 0x01,
+    key: "cycle_ms",
+    default: 100,
+    min: 20,
+    max: 200,
 };
 
-pub fn engine_bay_unit(data: EspData, own_identifier: u32) {
-    logging::init(true);
-    dbg_println!("Init Engine Bay Unit at 0x{own_identifier:X}");
-
-    // Channel for the CAN receiver to send received frames to the app_thread
-    let (incoming_frames_tx, incoming_frames_rx) = mpsc::sync_channel(20);
-
-    let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
-    let pins = peripherals.pins;
-
-    // Note: Changed CAN TX from GPIO48 to GPIO46 to avoid conflict with onboard LED
-    // GPIO48 is the onboard LED pin on ESP32-S3-DevKit-C1
-
-    // init CAN/TWAI
-    let mut can_config = data.can_config().clone();
-    // Filter for incoming brake commands, using a full 11-bit mask.
-    can_config = can_config.filter(Filter::Standard { filter: 0x310, mask: 0x7ff });
-
-    let mut can_driver =
-        CanDriver::new(peripherals.can, pins.gpio46, pins.gpio47, &can_config).unwrap();
-    can_driver.start().expect("Failed to start CAN driver");
-    let can_driver = Arc::new(Mutex::new(can_driver));
-
-    let can_receiver_can_driver = Arc::clone(&can_driver);
-    let can_receiver_thread_builder = Builder::new()
-        .name("can_receiver".into())
-        .stack_size(8 * 1024);
-    let _ = can_receiver_thread_builder.spawn(move || {
-        loop {
-            {
-                let can = can_receiver_can_driver.lock().unwrap();
-                // Drain the queue of any pending frames.
-                for _ in 0..10 {
-                    if let Ok(frame) = can.receive(0) {
-                        dbg_println!("[ECU/can <-] {:X} {:?}", frame.identifier(), frame.data());
-                        if frame.identifier() == 0x310 {
-                            if let Err(e) = incoming_frames_tx.try_send(frame) {
-                                dbg_println!(
-                                    "[ECU/can   ] Incoming frame dropped, channel full: {:?}",
-                                    e
-                                );
+static PARAMETERS: [Parameter; 1] = [CYCLE_TIME];
+
+/// 0x310 is sent every 100 ms, so five missed frames mean the kombiinstrument is gone.
+const KOMBIINSTRUMENT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Brake pedal state used while the kombiinstrument is gone. The brake lights stay on,
+/// as following traffic is better off with a lit brake light than with a dark one.
+const BRAKE_PEDAL_SAFE_STATE: (bool, bool) = (true, true);
+
+/// Everything the app_thread drives or reads, see [`crate::hal`].
+pub struct EngineBayUnitIo<'a> {
+    /// Driven low while the brake pedal is active.
+    pub brake_lights: (Box<dyn DigitalOutput + 'a>, Box<dyn DigitalOutput + 'a>),
+    pub onboard_led: Box<dyn DigitalOutput + 'a>,
+    pub vdc: Box<dyn AnalogInput + 'a>,
+    /// Front left, front right, rear left, rear right.
+    pub abs_sensors: [Box<dyn EdgeCapture + 'a>; 4],
+    pub firmware: Box<dyn Firmware + 'a>,
+    /// Where the parameters are saved.
+    pub parameter_store: Box<dyn ConfigStore + 'a>,
+}
+
+pub fn acceptance_filter() -> AcceptanceFilter {
+    AcceptanceFilter::new(&SUBSCRIBED_IDS)
+}
+
+/// Spawns the can_receiver thread, which forwards the frames of interest to the app_thread.
+pub fn spawn_can_receiver(can: SharedCan, stats: Arc<CanStats>) -> Receiver<CanFrame> {
+    can_service::spawn_receiver(can, acceptance_filter(), stats, "ECU")
+}
+
+/// State shared by the tasks of the app_thread.
+struct EngineBayUnit<'a> {
+    io: EngineBayUnitIo<'a>,
+    can: SharedCan,
+    can_health: CanHealth,
+    own_identifier: u32,
+    incoming_frames_rx: Receiver<CanFrame>,
+    node_status: NodeStatus,
+    freshness: FreshnessTracker,
+    parameters: Parameters,
+    /// Same order as the `abs_sensors`.
+    wheel_speeds: [WheelSpeed; 4],
+    brake_pedal_active_0: bool,
+    brake_pedal_active_1: bool,
+}
+
+impl EngineBayUnit<'_> {
+    /// Picks up the received frames, follows the brake pedal with the brake lights and
+    /// keeps the onboard LED off.
+    fn receive(&mut self, context: &TaskContext) {
+        let mut latest_brake_data: Option<(bool, bool)> = None;
+        while let Ok(frame) = self.incoming_frames_rx.try_recv() {
+            dbg_println!("[ECU/can <-] {:X} {:?}", frame.identifier(), frame.data());
+            match frame.identifier() {
+                NODE_REQUEST_ID => match NodeRequest::decode(frame.data()) {
+                    Ok(request) if request.addresses(self.own_identifier) => {
+                        match request.command {
+                            NodeCommand::Update => {
+                                dbg_println!("[ECU/can   ] Update requested");
+                                self.io.firmware.request_update();
                             }
+                            NodeCommand::Reset => self.reset(),
                         }
-                    } else {
-                        // No more frames in the queue
-                        break;
                     }
-                }
+                    Ok(_) => {}
+                    Err(e) => dbg_println!("[ECU/can   ] Invalid node request: {}", e),
+                },
+                PARAMETER_REQUEST_ID => match ParameterRequest::decode(frame.data()) {
+                    Ok(request) if request.target == self.own_identifier => {
+                        let response = self
+                            .parameters
+                            .handle(&request, &mut *self.io.parameter_store);
+                        dbg_println!("[ECU/param ] {:?} -> {:?}", request, response);
+                        let frame = CanFrame::new(
+                            self.own_identifier,
+                            &UniversalFrame::Parameter(response).encode(),
+                        )
+                        .unwrap();
+                        if let Err(e) = self.can.transmit(&frame) {
+                            dbg_println!("[ECU/param ] Failed to answer: {}", e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => dbg_println!("[ECU/can   ] Invalid parameter request: {}", e),
+                },
+                KOMBIINSTRUMENT_ID => match KombiinstrumentStatus::decode(frame.data()) {
+                    Ok(status) => {
+                        self.freshness.received(KOMBIINSTRUMENT_ID, context.now);
+                        latest_brake_data =
+                            Some((status.brake_pedal_active_0, status.brake_pedal_active_1));
+                    }
+                    Err(e) => {
+                        dbg_println!("[ECU/can   ] Invalid kombiinstrument status: {}", e)
+                    }
+                },
+                _ => {}
             }
-            // Yield CPU time to other threads.
-            thread::sleep(Duration::from_millis(20));
         }
-    });
-
-    let app_thread_can_driver = Arc::clone(&can_driver);
-    let app_thread_builder = Builder::new()
-        .name("app_thread".into())
-        .stack_size(8 * 1024);
-    let _ = app_thread_builder.spawn(move || {
-        let cycle_time: u8 = 100;
-        let abs_sens_can_identifier = 0x222;
-
-        // --- Hardware and peripheral setup ---
-        let mut brake_pedal_pins = (
-            PinDriver::output(pins.gpio21).unwrap(),
-            PinDriver::output(pins.gpio45).unwrap(),
+
+        if let Some((b0, b1)) = latest_brake_data {
+            self.brake_pedal_active_0 = b0;
+            self.brake_pedal_active_1 = b1;
+        }
+
+        if self.freshness.is_stale(KOMBIINSTRUMENT_ID, context.now) {
+            (self.brake_pedal_active_0, self.brake_pedal_active_1) = BRAKE_PEDAL_SAFE_STATE;
+        }
+
+        // --- Actuator/Output Logic ---
+        let brake_0_result = self.io.brake_lights.0.set(!self.brake_pedal_active_0);
+        let brake_1_result = self.io.brake_lights.1.set(!self.brake_pedal_active_1);
+        // Ensure both LEDs stay off
+        let led_result = self.io.onboard_led.set(false);
+        self.node_status.check(
+            ErrorCode::OutputFailed,
+            brake_0_result.is_ok() && brake_1_result.is_ok() && led_result.is_ok(),
         );
-        let vdc_pin = pins.gpio14;
-        let abs_fl_pins = (pins.gpio4, pins.gpio5);
-        let abs_fr_pins = (pins.gpio17, pins.gpio18);
-        let abs_rl_pins = (pins.gpio6, pins.gpio16);
-        let abs_rr_pins = (pins.gpio7, pins.gpio15);
-
-        let adc_driver = AdcDriver::new(peripherals.adc2).unwrap();
-        let adc_config = AdcChannelConfig {
-            attenuation: DB_11,
-            ..Default::default()
-        };
-        let mut adc_channel_driver =
-            AdcChannelDriver::new(adc_driver, vdc_pin, &adc_config).unwrap();
-
-        // Initialize onboard LED control (GPIO48) to ensure it stays off
-        let mut onboard_led = PinDriver::output(pins.gpio48).unwrap();
-        onboard_led.set_low().unwrap(); // Set onboard LED to 0% duty cycle (off)
-
-        let config = pcnt::PcntChannelConfig {
-            pos_mode: pcnt::PcntCountMode::Increment,
-            neg_mode: pcnt::PcntCountMode::Hold,
-            lctrl_mode: pcnt::PcntControlMode::Keep,
-            hctrl_mode: pcnt::PcntControlMode::Keep,
-            counter_h_lim: 32767,
-            counter_l_lim: 0,
-        };
+    }
+
+    /// Confirms the reset command before restarting.
+    fn reset(&mut self) {
+        dbg_println!("[ECU/can   ] Reset requested");
+        let frame =
+            CanFrame::new(self.own_identifier, &UniversalFrame::Resetting.encode()).unwrap();
+        if let Err(e) = self.can.transmit(&frame) {
+            dbg_println!("[ECU/can   ] Failed to confirm the reset: {}", e);
+        }
+        self.io.firmware.restart();
+    }
+
+    /// Measures the wheel speeds and sends them with the status frame.
+    fn cycle(&mut self, context: &TaskContext) {
+        let now = context.now;
+        let io = &mut self.io;
+        let node_status = &mut self.node_status;
+
+        self.freshness.report(node_status, now);
+        self.can_health.report(node_status, now);
+        io.firmware.report(node_status);
+
+        // --- Sensor Reading ---
+        let edges: [_; 4] = std::array::from_fn(|i| io.abs_sensors[i].take_edges());
+        node_status.check(
+            ErrorCode::PulseCounterFailed,
+            edges.iter().all(|edges| edges.is_ok()),
+        );
+        let frequencies: [f32; 4] = std::array::from_fn(|i| match &edges[i] {
+            Ok(edges) => self.wheel_speeds[i].update(*edges, now),
+            Err(_) => 0.0,
+        });
+        let [freq_fl, freq_fr, freq_rl, freq_rr] = frequencies;
+
+        let vdc = io.vdc.read();
+        node_status.check(ErrorCode::AdcReadFailed, vdc.is_ok());
+        if vdc.is_ok() {
+            io.firmware.pass(SelfTestCheck::AdcReadable);
+        }
+        let vdc = vdc.unwrap_or(0);
 
-        let mut abs_fl =
-            PcntDriver::new(peripherals.pcnt0, Some(abs_fl_pins.1), None::<AnyIOPin>, None::<AnyIOPin>, None::<AnyIOPin>)
-                .expect("[PCNT0] Failed to initialize PCNT driver");
-        abs_fl.channel_config(PcntChannel::Channel0, PinIndex::Pin0, PinIndex::Pin1, &config)
-            .expect("[PCNT0] Failed to set Channel Config");
-        abs_fl.counter_resume()
-            .expect("[PCNT0] Failed to resume counter");
-
-        let mut abs_fr =
-            PcntDriver::new(peripherals.pcnt1, Some(abs_fr_pins.1), None::<AnyIOPin>, None::<AnyIOPin>, None::<AnyIOPin>)
-                .expect("[PCNT1] Failed to initialize PCNT driver");
-        abs_fr.channel_config(PcntChannel::Channel0, PinIndex::Pin0, PinIndex::Pin1, &config)
-            .expect("[PCNT1] Failed to set Channel Config");
-        abs_fr.counter_resume()
-            .expect("[PCNT1] Failed to resume counter");
-
-        let mut abs_rl =
-            PcntDriver::new(peripherals.pcnt2, Some(abs_rl_pins.1), None::<AnyIOPin>, None::<AnyIOPin>, None::<AnyIOPin>)
-                .expect("[PCNT2] Failed to initialize PCNT driver");
-        abs_rl.channel_config(PcntChannel::Channel0, PinIndex::Pin0, PinIndex::Pin1, &config)
-            .expect("[PCNT2] Failed to set Channel Config");
-        abs_rl.counter_resume()
-            .expect("[PCNT2] Failed to resume counter");
-
-        let mut abs_rr =
-            PcntDriver::new(peripherals.pcnt3, Some(abs_rr_pins.1), None::<AnyIOPin>, None::<AnyIOPin>, None::<AnyIOPin>)
-                .expect("[PCNT3] Failed to initialize PCNT driver");
-        abs_rr.channel_config(PcntChannel::Channel0, PinIndex::Pin0, PinIndex::Pin1, &config)
-            .expect("[PCNT3] Failed to set Channel Config");
-        abs_rr.counter_resume()
-            .expect("[PCNT3] Failed to resume counter");
-
-        
-        // --- Local state variables ---
-        let mut brake_pedal_active_0 = false;
-        let mut brake_pedal_active_1 = false;
-        let mut tct_perc = 0;
-
-        loop {
-            let start_time = Instant::now();
-
-            // --- CAN Frame Reception ---
-            let mut latest_brake_data: Option<(bool, bool)> = None;
-            while let Ok(frame) = incoming_frames_rx.try_recv() {
-                dbg_println!(
-                    "[ECU/can <-] {:X} {:?}",
-                    frame.identifier(),
-                    frame.data()
-                );
-                let bit_array = frame_data_to_bit_array(&frame.data()[1]);
-                latest_brake_data = Some((bit_array[0], bit_array[1]));
-            }
+        // --- CAN Frame Transmission ---
+        let tct_perc = context.load_perc();
+        let abs_frame_data = WheelSpeeds::from_hertz(frequencies).encode();
 
-            if let Some((b0, b1)) = latest_brake_data {
-                brake_pedal_active_0 = b0;
-                brake_pedal_active_1 = b1;
-            }
+        let general_frame_data = EngineBayStatus {
+            reset_reason: io.firmware.reset_reason(),
+            tct_perc,
+        }
+        .encode();
+
+        let abs_frame = CanFrame::new(WHEEL_SPEEDS_ID, &abs_frame_data).unwrap();
+        let general_frame = CanFrame::new(self.own_identifier, &general_frame_data).unwrap();
+
+        // Warnings, errors and update progress are sent in addition to the general frame,
+        // which already carries the "online" status byte.
+        let universal_frame = node_status.next_frame();
+        let universal_frame = (universal_frame != UniversalFrame::Online)
+            .then(|| CanFrame::new(self.own_identifier, &universal_frame.encode()).unwrap());
+
+        let can_send_status_abs = self.can.transmit(&abs_frame).is_ok();
+        let can_send_status_general = self.can.transmit(&general_frame).is_ok()
+            && universal_frame.map_or(true, |frame| self.can.transmit(&frame).is_ok());
+        node_status.check(
+            ErrorCode::CanTransmitFailed,
+            can_send_status_abs && can_send_status_general,
+        );
+        if can_send_status_general {
+            io.firmware.pass(SelfTestCheck::HeartbeatSent);
+        }
 
-            // --- Actuator/Output Logic ---
-            if brake_pedal_active_0 {
-                brake_pedal_pins.0.set_low().unwrap();
-            } else {
-                brake_pedal_pins.0.set_high().unwrap();
-            }
+        // --- Logging ---
+        dbg_println!(
+            "[ECU/app   ] FL:{:.1} FR:{:.1} RL:{:.1} RR:{:.1} Hz | B0:{} B1:{} | VDC:{} | Q_gen:{} Q_abs:{} | {:?} | Cycle: {:?} / {}%",
+            freq_fl, freq_fr, freq_rl, freq_rr,
+            self.brake_pedal_active_0, self.brake_pedal_active_1,
+            vdc,
+            can_send_status_general, can_send_status_abs,
+            node_status.state(),
+            context.stats.last_runtime, tct_perc
+        );
+    }
+}
 
-            // --- Actuator/Output Logic ---
-            if brake_pedal_active_1 {
-                brake_pedal_pins.1.set_low().unwrap();
-            } else {
-                brake_pedal_pins.1.set_high().unwrap();
-            }
+impl<'a> EngineBayUnit<'a> {
+    fn new(
+        io: EngineBayUnitIo<'a>,
+        now: Instant,
+        can: SharedCan,
+        can_health: CanHealth,
+        own_identifier: u32,
+        incoming_frames_rx: Receiver<CanFrame>,
+    ) -> Self {
+        let parameters = Parameters::load(&PARAMETERS, &*io.parameter_store, "ECU");
+        let freshness = FreshnessTracker::new(now).with_signal(
+            KOMBIINSTRUMENT_ID,
+            KOMBIINSTRUMENT_TIMEOUT,
+            ErrorCode::KombiinstrumentTimeout,
+        );
 
-            // Ensure both LEDs stay off
-            onboard_led.set_low().unwrap();
-
-            // --- Sensor Reading ---
-            let count_fl = abs_fl.get_counter_value().unwrap();
-            let count_fr = abs_fr.get_counter_value().unwrap();
-            let count_rl = abs_rl.get_counter_value().unwrap();
-            let count_rr = abs_rr.get_counter_value().unwrap();
-
-            let cycle_time_sec = cycle_time as f32 / 1000.0;
-            let freq_fl = (count_fl as f32) / cycle_time_sec;
-            let freq_fr = (count_fr as f32) / cycle_time_sec;
-            let freq_rl = (count_rl as f32) / cycle_time_sec;
-            let freq_rr = (count_rr as f32) / cycle_time_sec;
-
-
-            abs_fl.counter_clear().unwrap();
-            abs_fr.counter_clear().unwrap();
-            abs_rl.counter_clear().unwrap();
-            abs_rr.counter_clear().unwrap();
-
-            let vdc = adc_channel_driver.read().unwrap();
-
-            // --- CAN Frame Transmission ---
-            let abs_frame_data = [
-                (freq_fl as u16 >> 8) as u8,
-                freq_fl as u16 as u8,
-                (freq_fr as u16 >> 8) as u8,
-                freq_fr as u16 as u8,
-                (freq_rl as u16 >> 8) as u8,
-                freq_rl as u16 as u8,
-                (freq_rr as u16 >> 8) as u8,
-                freq_rr as u16 as u8,
-            ];
-
-            let general_frame_data = [0x11, 0, 0, 0, 0, 0, tct_perc, 0];
-
-            let abs_frame =
-                Frame::new(abs_sens_can_identifier, enum_set!(Flags::None), &abs_frame_data)
-                    .unwrap();
-            let general_frame =
-                Frame::new(own_identifier, enum_set!(Flags::None), &general_frame_data).unwrap();
-
-            let (can_send_status_abs, can_send_status_general) = {
-                let can = app_thread_can_driver.lock().unwrap();
-                let s1 = can.transmit(&abs_frame, 2).is_ok();
-                let s2 = can.transmit(&general_frame, 2).is_ok();
-                (s1, s2)
-            };
+        Self {
+            io,
+            can,
+            can_health,
+            own_identifier,
+            incoming_frames_rx,
+            node_status: NodeStatus::new(),
+            freshness,
+            parameters,
+            wheel_speeds: Default::default(),
+            brake_pedal_active_0: false,
+            brake_pedal_active_1: false,
+        }
+    }
+}
 
-            // --- Cycle Time Calculation and Logging ---
-            let elapsed = start_time.elapsed();
-            let cycle_time_percentage = 100 * elapsed.as_millis() / cycle_time as u128;
-            tct_perc = cycle_time_percentage as u8; // Update with time after CAN send
-
-            dbg_println!(
-                "[ECU/app   ] FL:{:.1} FR:{:.1} RL:{:.1} RR:{:.1} Hz | B0:{} B1:{} | VDC:{} | Q_gen:{} Q_abs:{} | Cycle: {:?} / {}%",
-                freq_fl, freq_fr, freq_rl, freq_rr,
-                brake_pedal_active_0, brake_pedal_active_1,
-                vdc,
-                can_send_status_general, can_send_status_abs,
-                elapsed, tct_perc
+pub fn app_thread(
+    io: EngineBayUnitIo<'_>,
+    clock: Box<dyn Clock + '_>,
+    can: SharedCan,
+    can_health: CanHealth,
+    own_identifier: u32,
+    incoming_frames_rx: Receiver<CanFrame>,
+) {
+    let mut engine_bay_unit = EngineBayUnit::new(
+        io,
+        clock.now(),
+        can,
+        can_health,
+        own_identifier,
+        incoming_frames_rx,
+    );
+    let cycle_period = Duration::from_millis(engine_bay_unit.parameters.get(&CYCLE_TIME) as u64);
+
+    Scheduler::new(clock, "ECU")
+        .with_task("can_rx", CAN_RX_PERIOD, EngineBayUnit::receive)
+        .with_task("cycle", cycle_period, EngineBayUnit::cycle)
+        .run(&mut engine_bay_unit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        can_supervisor,
+        hal::host::{SimAnalogInput, SimConfigStore, SimFirmware, SimOutput, SimWheel, TestBus},
+        protocol::{
+            ParameterCommand, ParameterResponse, ParameterResult, ResetReason, ENGINE_BAY_UNIT_ID,
+        },
+        scheduler::TaskStats,
+    };
+
+    /// The node on a virtual bus and its outputs.
+    struct Bench {
+        node: EngineBayUnit<'static>,
+        bus: TestBus,
+        brake_lights: (SimOutput, SimOutput),
+        start: Instant,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            let (bus, incoming_frames_rx) = TestBus::new();
+            let brake_lights = (SimOutput::default(), SimOutput::default());
+            let vdc = SimAnalogInput::default();
+            vdc.set(2400);
+            let wheel = SimWheel::new(0.0);
+
+            let io = EngineBayUnitIo {
+                brake_lights: (
+                    Box::new(brake_lights.0.clone()),
+                    Box::new(brake_lights.1.clone()),
+                ),
+                onboard_led: Box::new(SimOutput::default()),
+                vdc: Box::new(vdc),
+                abs_sensors: [
+                    Box::new(wheel.clone()),
+                    Box::new(wheel.clone()),
+                    Box::new(wheel.clone()),
+                    Box::new(wheel),
+                ],
+                firmware: Box::new(SimFirmware),
+                parameter_store: Box::new(SimConfigStore::default()),
+            };
+            let can_health = can_supervisor::spawn_supervisor(bus.node_can(), "ECU");
+            let start = Instant::now();
+            let node = EngineBayUnit::new(
+                io,
+                start,
+                bus.node_can(),
+                can_health,
+                ENGINE_BAY_UNIT_ID,
+                incoming_frames_rx,
             );
 
-            // --- Sleep ---
-            if let Some(remaining) = Duration::from_millis(cycle_time as u64).checked_sub(elapsed) {
-                thread::sleep(remaining);
+            Self {
+                node,
+                bus,
+                brake_lights,
+                start,
             }
         }
-    });
+
+        /// Sends `frame` to the node and runs `can_rx` at `at` after the start, returns the
+        /// frames the node sent.
+        fn receive(&mut self, frame: Option<CanFrame>, at: Duration) -> Vec<CanFrame> {
+            if let Some(frame) = frame {
+                self.bus.send(frame, &acceptance_filter());
+            }
+            self.node.receive(&self.context(CAN_RX_PERIOD, at));
+            self.bus.sent()
+        }
+
+        /// Runs `cycle` at `at` after the start, returns the frames the node sent.
+        fn cycle(&mut self, at: Duration) -> Vec<CanFrame> {
+            self.node
+                .cycle(&self.context(Duration::from_millis(100), at));
+            self.bus.sent()
+        }
+
+        fn context(&self, period: Duration, at: Duration) -> TaskContext {
+            TaskContext {
+                now: self.start + at,
+                period,
+                stats: TaskStats::default(),
+            }
+        }
+
+        /// The outputs are driven low while the brake pedal is active.
+        fn brake_lights_on(&self) -> (bool, bool) {
+            (
+                !self.brake_lights.0.is_high(),
+                !self.brake_lights.1.is_high(),
+            )
+        }
+    }
+
+    fn kombiinstrument_status(brake_pedal_active: (bool, bool)) -> Option<CanFrame> {
+        let status = KombiinstrumentStatus {
+            brake_pedal_active_0: brake_pedal_active.0,
+            brake_pedal_active_1: brake_pedal_active.1,
+            reset_reason: ResetReason::PowerOn,
+            tct_perc: 0,
+        };
+        CanFrame::new(KOMBIINSTRUMENT_ID, &status.encode())
+    }
+
+    #[test]
+    fn brake_lights_follow_the_kombiinstrument() {
+        let mut bench = Bench::new();
+
+        bench.receive(kombiinstrument_status((false, false)), Duration::ZERO);
+        assert_eq!(bench.brake_lights_on(), (false, false));
+
+        bench.receive(
+            kombiinstrument_status((true, false)),
+            Duration::from_millis(100),
+        );
+        assert_eq!(bench.brake_lights_on(), (true, false));
+
+        bench.receive(
+            kombiinstrument_status((true, true)),
+            Duration::from_millis(200),
+        );
+        assert_eq!(bench.brake_lights_on(), (true, true));
+    }
+
+    #[test]
+    fn brake_lights_forced_on_without_the_kombiinstrument() {
+        let mut bench = Bench::new();
+        bench.receive(kombiinstrument_status((false, false)), Duration::ZERO);
+
+        bench.receive(None, KOMBIINSTRUMENT_TIMEOUT);
+        assert_eq!(bench.brake_lights_on(), (false, false));
+
+        let stale = KOMBIINSTRUMENT_TIMEOUT + Duration::from_millis(10);
+        bench.receive(None, stale);
+        assert_eq!(bench.brake_lights_on(), (true, true));
+        let warning = UniversalFrame::Warning(ErrorCode::KombiinstrumentTimeout.number());
+        assert!(bench
+            .cycle(stale)
+            .iter()
+            .any(|frame| frame.identifier() == ENGINE_BAY_UNIT_ID
+                && frame.data() == warning.encode()));
+
+        // Only a status frame counts as received, not an error frame of the kombiinstrument.
+        bench.receive(
+            CanFrame::new(KOMBIINSTRUMENT_ID, &warning.encode()),
+            stale + Duration::from_millis(10),
+        );
+        assert_eq!(bench.brake_lights_on(), (true, true));
+
+        let back = stale + Duration::from_millis(20);
+        bench.receive(kombiinstrument_status((false, false)), back);
+        assert_eq!(bench.brake_lights_on(), (false, false));
+        assert!(bench
+            .cycle(back)
+            .iter()
+            .all(|frame| frame.data() != warning.encode()));
+    }
+
+    #[test]
+    fn cycle_sends_wheel_speeds_and_status() {
+        let mut bench = Bench::new();
+        bench.receive(kombiinstrument_status((false, false)), Duration::ZERO);
+        let sent = bench.cycle(Duration::ZERO);
+
+        // Online, so no universal frame in addition.
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].identifier(), WHEEL_SPEEDS_ID);
+        assert_eq!(
+            WheelSpeeds::decode(sent[0].data()),
+            Ok(WheelSpeeds::from_hertz([0.0; 4]))
+        );
+        assert_eq!(sent[1].identifier(), ENGINE_BAY_UNIT_ID);
+        assert_eq!(
+            EngineBayStatus::decode(sent[1].data()),
+            Ok(EngineBayStatus {
+                reset_reason: ResetReason::PowerOn,
+                tct_perc: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn parameter_request() {
+        let mut bench = Bench::new();
+        let request = |target| ParameterRequest {
+            command: ParameterCommand::Read,
+            target,
+            parameter: CYCLE_TIME.number,
+            value: 0,
+        };
+
+        let sent = bench.receive(
+            CanFrame::new(PARAMETER_REQUEST_ID, &request(KOMBIINSTRUMENT_ID).encode()),
+            Duration::ZERO,
+        );
+        assert!(sent.is_empty());
+
+        let sent = bench.receive(
+            CanFrame::new(PARAMETER_REQUEST_ID, &request(ENGINE_BAY_UNIT_ID).encode()),
+            Duration::ZERO,
+        );
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].identifier(), ENGINE_BAY_UNIT_ID);
+        assert_eq!(
+            UniversalFrame::decode(sent[0].data()),
+            Ok(UniversalFrame::Parameter(ParameterResponse {
+                command: ParameterCommand::Read,
+                parameter: CYCLE_TIME.number,
+                result: ParameterResult::Ok,
+                value: CYCLE_TIME.default,
+            }))
+        );
+    }
+
+    #[test]
+    fn reset_request_is_confirmed() {
+        let mut bench = Bench::new();
+        let request = NodeRequest {
+            command: NodeCommand::Reset,
+            target: NodeRequest::ALL_NODES,
+        };
+
+        let sent = bench.receive(
+            CanFrame::new(NODE_REQUEST_ID, &request.encode()),
+            Duration::ZERO,
+        );
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].identifier(), ENGINE_BAY_UNIT_ID);
+        assert_eq!(
+            UniversalFrame::decode(sent[0].data()),
+            Ok(UniversalFrame::Resetting)
+        );
+    }
 }
@@ -0,0 +1,152 @@
+//! Liveness supervision of received CAN signals.
+//!
+//! Every subscribed identifier gets its own timeout. Once a signal was not received
+//! for longer than that, it is stale: the node has to fall back to a safe value and
+//! the configured warning is raised on the universal error frame.
+
+use std::time::{Duration, Instant};
+
+use crate::{protocol::ErrorCode, status::NodeStatus};
+
+#[derive(Debug)]
+struct Signal {
+    identifier: u32,
+    timeout: Duration,
+    error_code: ErrorCode,
+    last_seen: Instant,
+}
+
+impl Signal {
+    fn is_stale(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_seen) > self.timeout
+    }
+}
+
+#[derive(Debug)]
+pub struct FreshnessTracker {
+    started: Instant,
+    signals: Vec<Signal>,
+}
+
+impl FreshnessTracker {
+    /// Signals get one timeout of grace after `now` before they count as stale.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            signals: Vec::new(),
+        }
+    }
+
+    pub fn with_signal(
+        mut self,
+        identifier: u32,
+        timeout: Duration,
+        error_code: ErrorCode,
+    ) -> Self {
+        self.signals.push(Signal {
+            identifier,
+            timeout,
+            error_code,
+            last_seen: self.started,
+        });
+        self
+    }
+
+    /// Marks `identifier` as received. Unknown identifiers are ignored.
+    pub fn received(&mut self, identifier: u32, now: Instant) {
+        if let Some(signal) = self.signals.iter_mut().find(|s| s.identifier == identifier) {
+            signal.last_seen = now;
+        }
+    }
+
+    /// Unknown identifiers are never stale.
+    pub fn is_stale(&self, identifier: u32, now: Instant) -> bool {
+        self.signals
+            .iter()
+            .find(|s| s.identifier == identifier)
+            .is_some_and(|s| s.is_stale(now))
+    }
+
+    /// Raises the warning of every stale signal and clears it for every fresh one.
+    pub fn report(&self, node_status: &mut NodeStatus, now: Instant) {
+        for signal in &self.signals {
+            node_status.check(signal.error_code, !signal.is_stale(now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::UniversalFrame, status::NodeState};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn tracker(start: Instant) -> FreshnessTracker {
+        FreshnessTracker::new(start)
+            .with_signal(0x310, TIMEOUT, ErrorCode::KombiinstrumentTimeout)
+            .with_signal(
+                0x280,
+                Duration::from_millis(100),
+                ErrorCode::EngineDataTimeout,
+            )
+    }
+
+    #[test]
+    fn grace_after_start() {
+        let start = Instant::now();
+        let tracker = tracker(start);
+
+        assert!(!tracker.is_stale(0x310, start + TIMEOUT));
+        assert!(tracker.is_stale(0x310, start + TIMEOUT + Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn received_refreshes() {
+        let start = Instant::now();
+        let mut tracker = tracker(start);
+        tracker.received(0x310, start + Duration::from_millis(400));
+
+        assert!(!tracker.is_stale(0x310, start + Duration::from_millis(900)));
+        assert!(tracker.is_stale(0x310, start + Duration::from_millis(901)));
+        // Every signal has its own timeout.
+        assert!(tracker.is_stale(0x280, start + Duration::from_millis(400)));
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        let start = Instant::now();
+        let mut tracker = tracker(start);
+        tracker.received(0x222, start);
+
+        assert!(!tracker.is_stale(0x222, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn report() {
+        let start = Instant::now();
+        let mut tracker = tracker(start);
+        let mut node_status = NodeStatus::new();
+        let now = start + Duration::from_millis(200);
+        tracker.received(0x280, now);
+
+        tracker.report(&mut node_status, now);
+        assert_eq!(node_status.state(), NodeState::Online);
+
+        let now = start + Duration::from_millis(600);
+        tracker.report(&mut node_status, now);
+        assert_eq!(
+            node_status.next_frame(),
+            UniversalFrame::Warning(ErrorCode::KombiinstrumentTimeout.number())
+        );
+        assert_eq!(
+            node_status.next_frame(),
+            UniversalFrame::Warning(ErrorCode::EngineDataTimeout.number())
+        );
+
+        tracker.received(0x310, now);
+        tracker.received(0x280, now);
+        tracker.report(&mut node_status, now);
+        assert_eq!(node_status.state(), NodeState::Online);
+    }
+}
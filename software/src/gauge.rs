@@ -0,0 +1,140 @@
+//! Needles of the instrument cluster that are driven by a pulse frequency.
+//!
+//! The gauges sweep to full scale and back at ignition-on, like the OEM cluster does,
+//! which shows that needles and outputs work.
+
+use std::time::{Duration, Instant};
+
+use crate::hal::FrequencyOutput;
+
+/// The LEDC timer is set up for 2 Hz at 14 bit, which only RC_FAST_CLK (about 17.5 MHz)
+/// reaches with the 10 bit divider. The timer keeps that clock, so the highest
+/// frequency is 17.5 MHz / 2^14 ≈ 1068 Hz.
+pub const MIN_FREQUENCY: u32 = 2;
+pub const MAX_FREQUENCY: u32 = 1000;
+
+const SWEEP_UP: Duration = Duration::from_millis(1200);
+const SWEEP_DOWN: Duration = Duration::from_millis(1200);
+
+/// Frequency for the LEDC timer, 0 below [`MIN_FREQUENCY`] stops the output.
+pub fn ledc_frequency(hertz: f32) -> u32 {
+    if hertz < MIN_FREQUENCY as f32 {
+        0
+    } else {
+        (hertz.round() as u32).min(MAX_FREQUENCY)
+    }
+}
+
+/// Linear interpolation between `points`, sorted by x. Outside of them the first or last
+/// y applies.
+pub fn interpolate(points: &[(f32, f32)], x: f32) -> f32 {
+    let Some(upper) = points.iter().position(|&(point_x, _)| point_x >= x) else {
+        return points.last().map_or(0.0, |&(_, y)| y);
+    };
+    if upper == 0 {
+        return points[0].1;
+    }
+
+    let (x0, y0) = points[upper - 1];
+    let (x1, y1) = points[upper];
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+/// Needle sweep to full scale and back, shared by all gauges so they move together.
+#[derive(Debug, Clone, Copy)]
+pub struct Sweep {
+    /// `None` once ended.
+    start: Option<Instant>,
+}
+
+impl Sweep {
+    pub fn new(now: Instant) -> Self {
+        Self { start: Some(now) }
+    }
+
+    /// Ends the sweep early, e.g. at the first real reading.
+    pub fn end(&mut self) {
+        self.start = None;
+    }
+
+    /// In fractions of the full scale, `None` once the needles are back at zero.
+    pub fn position(&mut self, now: Instant) -> Option<f32> {
+        let elapsed = now.saturating_duration_since(self.start?);
+        if elapsed < SWEEP_UP {
+            Some(elapsed.as_secs_f32() / SWEEP_UP.as_secs_f32())
+        } else if elapsed < SWEEP_UP + SWEEP_DOWN {
+            Some(1.0 - (elapsed - SWEEP_UP).as_secs_f32() / SWEEP_DOWN.as_secs_f32())
+        } else {
+            self.end();
+            None
+        }
+    }
+}
+
+/// Frequency output of one gauge.
+#[derive(Default)]
+pub struct Gauge {
+    /// Last frequency set, the timer is only reconfigured on a change.
+    frequency: Option<u32>,
+}
+
+impl Gauge {
+    pub fn set(&mut self, output: &mut dyn FrequencyOutput, hertz: f32) -> anyhow::Result<()> {
+        let frequency = ledc_frequency(hertz);
+        if self.frequency != Some(frequency) {
+            // Set again next time if it failed.
+            self.frequency = None;
+            output.set_frequency(frequency)?;
+            self.frequency = Some(frequency);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate() {
+        let points = [(20.0, 1.0), (50.0, 2.0), (100.0, 1.5)];
+
+        assert_eq!(super::interpolate(&points, 0.0), 1.0);
+        assert_eq!(super::interpolate(&points, 20.0), 1.0);
+        assert_eq!(super::interpolate(&points, 35.0), 1.5);
+        assert_eq!(super::interpolate(&points, 75.0), 1.75);
+        assert_eq!(super::interpolate(&points, 200.0), 1.5);
+    }
+
+    #[test]
+    fn ledc_frequency() {
+        assert_eq!(super::ledc_frequency(0.0), 0);
+        assert_eq!(super::ledc_frequency(1.9), 0);
+        assert_eq!(super::ledc_frequency(2.0), 2);
+        assert_eq!(super::ledc_frequency(83.4), 83);
+        assert_eq!(super::ledc_frequency(5000.0), MAX_FREQUENCY);
+    }
+
+    #[test]
+    fn sweep() {
+        let start = Instant::now();
+        let mut sweep = Sweep::new(start);
+
+        assert_eq!(sweep.position(start), Some(0.0));
+        assert_eq!(sweep.position(start + SWEEP_UP / 2), Some(0.5));
+        assert_eq!(sweep.position(start + SWEEP_UP), Some(1.0));
+        assert_eq!(sweep.position(start + SWEEP_UP + SWEEP_DOWN / 2), Some(0.5));
+        assert_eq!(sweep.position(start + SWEEP_UP + SWEEP_DOWN), None);
+        assert_eq!(sweep.position(start), None);
+    }
+
+    #[test]
+    fn sweep_ends_early() {
+        let start = Instant::now();
+        let mut sweep = Sweep::new(start);
+        sweep.end();
+
+        assert_eq!(sweep.position(start), None);
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    borrow::Borrow,
+    ffi::c_void,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+use enumset::enum_set;
+use esp_idf_hal::{
+    adc::{
+        oneshot::{AdcChannelDriver, AdcDriver},
+        ADCPin,
+    },
+    can::{
+        config::{Filter, Timing},
+        Alert, CanDriver, Flags, Frame,
+    },
+    delay::TickType,
+    gpio::{AnyInputPin, AnyOutputPin, Input, InterruptType, Output, OutputPin, PinDriver},
+    ledc::{LedcTimer, LedcTimerDriver},
+    reset,
+    sys::{
+        esp, esp_err_t, esp_reset_reason, esp_reset_reason_t_ESP_RST_BROWNOUT,
+        esp_reset_reason_t_ESP_RST_EXT, esp_reset_reason_t_ESP_RST_INT_WDT,
+        esp_reset_reason_t_ESP_RST_PANIC, esp_reset_reason_t_ESP_RST_POWERON,
+        esp_reset_reason_t_ESP_RST_SW, esp_reset_reason_t_ESP_RST_TASK_WDT,
+        esp_reset_reason_t_ESP_RST_WDT, esp_timer_get_time, gpio_install_isr_service,
+        gpio_intr_enable, gpio_isr_handler_add, gpio_isr_handler_remove, twai_get_status_info,
+        twai_initiate_recovery, twai_start, twai_state_t_TWAI_STATE_BUS_OFF,
+        twai_state_t_TWAI_STATE_RECOVERING, twai_state_t_TWAI_STATE_RUNNING, twai_status_info_t,
+        ESP_ERR_INVALID_STATE,
+    },
+    units::Hertz,
+};
+use esp_idf_svc::nvs::{EspNvs, NvsDefault};
+
+use super::{
+    Alerts, AnalogInput, BusState, BusStatus, CanBus, CanFrame, DigitalOutput, EdgeCapture, Edges,
+    Firmware, FrequencyOutput,
+};
+use crate::{
+    config::{Bitrate, CanPins, ConfigStore},
+    ota::{BootValidation, OtaHandle},
+    protocol::{filter::Filters, ResetReason},
+    self_test::SelfTestCheck,
+    status::NodeStatus,
+};
+
+/// Ticks to wait for space in the TX queue.
+const TRANSMIT_TIMEOUT: u32 = 2;
+
+/// Leaves the TX queue time to send the reset confirmation.
+pub const RESTART_DELAY: Duration = Duration::from_millis(100);
+
+/// Upper 24 bits of [`RESTART_MARKER`], the lowest byte holds the [`ResetReason`].
+const RESTART_MARKER_TAG: u32 = 0xe5_10_5e_00;
+
+/// Kept by a software restart and random after power-on, hence the tag. Only plain loads
+/// and stores, RTC memory does not support the atomic read-modify-write instructions.
+#[link_section = ".rtc_noinit"]
+static RESTART_MARKER: AtomicU32 = AtomicU32::new(0);
+
+impl From<Filters> for Filter {
+    fn from(filters: Filters) -> Self {
+        match filters {
+            Filters::Single(filter) => Filter::Standard {
+                filter: filter.filter,
+                mask: filter.mask,
+            },
+            Filters::Dual(first, second) => Filter::Dual {
+                filter1: first.filter,
+                mask1: first.mask,
+                filter2: second.filter,
+                mask2: second.mask,
+            },
+        }
+    }
+}
+
+impl From<Bitrate> for Timing {
+    fn from(bitrate: Bitrate) -> Self {
+        match bitrate {
+            Bitrate::B125K => Timing::B125K,
+            Bitrate::B250K => Timing::B250K,
+            Bitrate::B500K => Timing::B500K,
+            Bitrate::B1M => Timing::B1M,
+        }
+    }
+}
+
+/// Takes the configured CAN pins, the role takes its other pins from `Peripherals::pins`.
+pub fn can_pins(pins: CanPins) -> (AnyOutputPin, AnyInputPin) {
+    // SAFETY: `NodeConfig::validate` rejects CAN pins the role uses itself, so no other
+    // driver gets these GPIOs.
+    unsafe {
+        (
+            AnyOutputPin::new(pins.tx as i32),
+            AnyInputPin::new(pins.rx as i32),
+        )
+    }
+}
+
+impl ConfigStore for EspNvs<NvsDefault> {
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        Ok(EspNvs::get_u32(self, key)?)
+    }
+
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>> {
+        // Includes the terminating zero.
+        let Some(len) = self.str_len(key)? else {
+            return Ok(None);
+        };
+        let mut buffer = vec![0; len];
+
+        Ok(EspNvs::get_str(self, key, &mut buffer)?.map(str::to_owned))
+    }
+
+    fn set_u32(&mut self, key: &str, value: u32) -> anyhow::Result<()> {
+        Ok(EspNvs::set_u32(self, key, value)?)
+    }
+}
+
+// --- Restart ---
+
+/// Restarts the chip, the next boot reports `reason` instead of [`ResetReason::Software`].
+pub fn restart(reason: ResetReason) {
+    RESTART_MARKER.store(RESTART_MARKER_TAG | reason as u32, Ordering::SeqCst);
+    reset::restart();
+}
+
+/// Why the chip started. Clears the reason left by [`restart`], call it once at boot.
+pub fn take_reset_reason() -> ResetReason {
+    let marker = RESTART_MARKER.load(Ordering::SeqCst);
+    RESTART_MARKER.store(0, Ordering::SeqCst);
+
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+        esp_reset_reason_t_ESP_RST_EXT => ResetReason::External,
+        esp_reset_reason_t_ESP_RST_SW if marker & !0xff == RESTART_MARKER_TAG => {
+            ResetReason::decode(marker as u8)
+        }
+        esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+        esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+        esp_reset_reason_t_ESP_RST_INT_WDT
+        | esp_reset_reason_t_ESP_RST_TASK_WDT
+        | esp_reset_reason_t_ESP_RST_WDT => ResetReason::Watchdog,
+        esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+        _ => ResetReason::Unknown,
+    }
+}
+
+/// The started TWAI driver, shared by the can_receiver and the app_thread.
+pub struct EspCan(CanDriver<'static>);
+
+impl EspCan {
+    pub fn new(driver: CanDriver<'static>) -> Self {
+        Self(driver)
+    }
+}
+
+// The twai_* functions are thread safe, transmit and receive only block on the driver's
+// own TX and RX queues. The driver is never reconfigured through a shared reference.
+unsafe impl Sync for EspCan {}
+
+impl CanBus for EspCan {
+    fn transmit(&self, frame: &CanFrame) -> anyhow::Result<()> {
+        let frame = Frame::new(frame.identifier(), enum_set!(Flags::None), frame.data())
+            .ok_or_else(|| anyhow::anyhow!("invalid frame {:X}", frame.identifier()))?;
+
+        Ok(self.0.transmit(&frame, TRANSMIT_TIMEOUT)?)
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<CanFrame> {
+        let frame = self.0.receive(TickType::from(timeout).ticks()).ok()?;
+
+        CanFrame::new(frame.identifier(), frame.data())
+    }
+
+    fn wait_for_alerts(&self, timeout: Duration) -> Alerts {
+        // The error state itself is read with status() afterwards.
+        let alerts = self
+            .0
+            .read_alerts(TickType::from(timeout).ticks())
+            .unwrap_or_default();
+
+        Alerts {
+            transmit_retried: alerts.contains(Alert::TransmitRetried),
+        }
+    }
+
+    fn status(&self) -> anyhow::Result<BusStatus> {
+        let mut info = twai_status_info_t::default();
+        esp!(unsafe { twai_get_status_info(&mut info) })?;
+
+        let state = match info.state {
+            twai_state_t_TWAI_STATE_RUNNING => BusState::Running,
+            twai_state_t_TWAI_STATE_BUS_OFF => BusState::BusOff,
+            twai_state_t_TWAI_STATE_RECOVERING => BusState::Recovering,
+            _ => BusState::Stopped,
+        };
+
+        Ok(BusStatus {
+            state,
+            tx_error_counter: info.tx_error_counter,
+            rx_error_counter: info.rx_error_counter,
+            bus_errors: info.bus_error_count,
+            arbitration_lost: info.arb_lost_count,
+            rx_missed: info.rx_missed_count + info.rx_overrun_count,
+        })
+    }
+
+    fn initiate_recovery(&self) -> anyhow::Result<()> {
+        esp!(unsafe { twai_initiate_recovery() })?;
+        Ok(())
+    }
+
+    fn restart(&self) -> anyhow::Result<()> {
+        // CanDriver::start needs &mut, the driver itself only has to be in the stopped state.
+        esp!(unsafe { twai_start() })?;
+        Ok(())
+    }
+}
+
+impl<T: OutputPin> DigitalOutput for PinDriver<'_, T, Output> {
+    fn set(&mut self, high: bool) -> anyhow::Result<()> {
+        if high {
+            self.set_high()?;
+        } else {
+            self.set_low()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'d, T: ADCPin, M: Borrow<AdcDriver<'d, T::Adc>>> AnalogInput for AdcChannelDriver<'d, T, M> {
+    fn read(&mut self) -> anyhow::Result<u16> {
+        Ok(AdcChannelDriver::read(self)?)
+    }
+}
+
+// --- Edge capture ---
+
+/// Written by [`edge_isr`]. `sequence` is incremented before and after the timestamp,
+/// so it is odd while the timestamp is written and twice the number of edges otherwise.
+#[derive(Default)]
+struct EdgeState {
+    sequence: AtomicU32,
+    /// Lower 32 bits of `esp_timer_get_time`, wraps after 71 minutes.
+    latest_edge_us: AtomicU32,
+}
+
+/// Runs on every rising edge, `arg` is the [`EdgeState`] of the pin.
+unsafe extern "C" fn edge_isr(arg: *mut c_void) {
+    let state = &*(arg as *const EdgeState);
+    state.sequence.fetch_add(1, Ordering::SeqCst);
+    state
+        .latest_edge_us
+        .store(esp_timer_get_time() as u32, Ordering::SeqCst);
+    state.sequence.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Counts and timestamps the rising edges of an input in the GPIO interrupt. The count
+/// is never cleared, so no edge is lost between two reads and it cannot saturate like
+/// the 16 bit PCNT counter.
+pub struct EdgeTimer<'d> {
+    pin: PinDriver<'d, AnyInputPin, Input>,
+    state: Box<EdgeState>,
+    /// `sequence` at the previous read.
+    taken: u32,
+}
+
+impl EdgeTimer<'_> {
+    pub fn new(pin: AnyInputPin) -> anyhow::Result<Self> {
+        let mut pin = PinDriver::input(pin)?;
+        pin.set_interrupt_type(InterruptType::PosEdge)?;
+        let state = Box::<EdgeState>::default();
+
+        // Shared by all pins, only the first call installs it.
+        match unsafe { gpio_install_isr_service(0) } {
+            result if result == ESP_ERR_INVALID_STATE as esp_err_t => {}
+            result => esp!(result)?,
+        }
+        // SAFETY: the handler is removed in `drop`, before `state` is freed.
+        esp!(unsafe {
+            gpio_isr_handler_add(
+                pin.pin(),
+                Some(edge_isr),
+                &*state as *const EdgeState as *mut c_void,
+            )
+        })?;
+        esp!(unsafe { gpio_intr_enable(pin.pin()) })?;
+
+        Ok(Self {
+            pin,
+            state,
+            taken: 0,
+        })
+    }
+}
+
+impl Drop for EdgeTimer<'_> {
+    fn drop(&mut self) {
+        unsafe { gpio_isr_handler_remove(self.pin.pin()) };
+    }
+}
+
+impl EdgeCapture for EdgeTimer<'_> {
+    fn take_edges(&mut self) -> anyhow::Result<Edges> {
+        let (sequence, latest_edge_us) = loop {
+            let sequence = self.state.sequence.load(Ordering::SeqCst);
+            let latest_edge_us = self.state.latest_edge_us.load(Ordering::SeqCst);
+            if sequence % 2 == 0 && self.state.sequence.load(Ordering::SeqCst) == sequence {
+                break (sequence, latest_edge_us);
+            }
+        };
+        let now_us = unsafe { esp_timer_get_time() } as u32;
+
+        let count = sequence.wrapping_sub(self.taken) / 2;
+        self.taken = sequence;
+
+        Ok(Edges {
+            count,
+            since_latest: (sequence != 0)
+                .then(|| Duration::from_micros(now_us.wrapping_sub(latest_edge_us) as u64)),
+        })
+    }
+}
+
+/// The LEDC channel keeps its duty cycle, only the timer frequency changes. A paused
+/// timer holds the output level, so no more pulses reach the gauge.
+impl<T: LedcTimer> FrequencyOutput for LedcTimerDriver<'_, T> {
+    fn set_frequency(&mut self, hertz: u32) -> anyhow::Result<()> {
+        if hertz == 0 {
+            return Ok(self.pause()?);
+        }
+        LedcTimerDriver::set_frequency(self, Hertz(hertz))?;
+        Ok(self.resume()?)
+    }
+}
+
+pub struct EspFirmware {
+    pub ota: OtaHandle,
+    pub boot_validation: BootValidation,
+    /// See [`take_reset_reason`].
+    pub reset_reason: ResetReason,
+}
+
+impl Firmware for EspFirmware {
+    fn request_update(&self) {
+        self.ota.request_update();
+    }
+
+    fn pass(&self, check: SelfTestCheck) {
+        self.boot_validation.pass(check);
+    }
+
+    fn report(&self, node_status: &mut NodeStatus) {
+        self.ota.report(node_status);
+        self.boot_validation.report(node_status);
+    }
+
+    fn restart(&self) {
+        thread::sleep(RESTART_DELAY);
+        restart(ResetReason::Command);
+    }
+
+    fn reset_reason(&self) -> ResetReason {
+        self.reset_reason
+    }
+}
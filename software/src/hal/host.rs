@@ -0,0 +1,261 @@
+//! Simulated hardware for running the nodes on Linux.
+//!
+//! Every simulated input and output is a cheap clone around shared state, one clone
+//! goes into the node and the other one stays with the simulation to drive or watch it.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{
+    AnalogInput, CanBus, CanFrame, DigitalOutput, EdgeCapture, Edges, Firmware, FrequencyOutput,
+};
+use crate::{
+    config::{ConfigStore, Role},
+    dbg_println,
+    protocol::ResetReason,
+    self_test::SelfTestCheck,
+    status::NodeStatus,
+};
+
+/// Frames a node has not picked up yet, like the TWAI RX queue.
+const RX_QUEUE_LEN: usize = 32;
+
+/// A bus in memory, every frame is delivered to all other connected nodes.
+#[derive(Clone, Default)]
+pub struct VirtualBus {
+    nodes: Arc<Mutex<Vec<SyncSender<CanFrame>>>>,
+}
+
+impl VirtualBus {
+    pub fn connect(&self) -> VirtualCan {
+        let (tx, rx) = mpsc::sync_channel(RX_QUEUE_LEN);
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.len();
+        nodes.push(tx);
+
+        VirtualCan {
+            bus: self.clone(),
+            node,
+            rx: Mutex::new(rx),
+        }
+    }
+}
+
+pub struct VirtualCan {
+    bus: VirtualBus,
+    node: usize,
+    rx: Mutex<Receiver<CanFrame>>,
+}
+
+impl CanBus for VirtualCan {
+    fn transmit(&self, frame: &CanFrame) -> anyhow::Result<()> {
+        for (node, tx) in self.bus.nodes.lock().unwrap().iter().enumerate() {
+            // A full RX queue loses the frame, the same as on the real controller.
+            if node != self.node {
+                let _ = tx.try_send(*frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<CanFrame> {
+        self.rx.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
+
+/// The other end of a node's bus for the tests, which also stand in for its
+/// can_receiver thread.
+#[cfg(test)]
+pub struct TestBus {
+    node_can: Arc<VirtualCan>,
+    incoming_frames_tx: SyncSender<CanFrame>,
+    tester: VirtualCan,
+}
+
+#[cfg(test)]
+impl TestBus {
+    /// Returns the bus and the incoming frames for the node.
+    pub fn new() -> (Self, Receiver<CanFrame>) {
+        let bus = VirtualBus::default();
+        let node_can = Arc::new(bus.connect());
+        let tester = bus.connect();
+        let (incoming_frames_tx, incoming_frames_rx) = mpsc::sync_channel(RX_QUEUE_LEN);
+
+        let test_bus = Self {
+            node_can,
+            incoming_frames_tx,
+            tester,
+        };
+        (test_bus, incoming_frames_rx)
+    }
+
+    pub fn node_can(&self) -> super::SharedCan {
+        self.node_can.clone()
+    }
+
+    /// Sends `frame` to the node and hands it over to the node if it is subscribed.
+    pub fn send(
+        &self,
+        frame: CanFrame,
+        acceptance_filter: &crate::protocol::filter::AcceptanceFilter,
+    ) {
+        self.tester.transmit(&frame).unwrap();
+        while let Some(frame) = self.node_can.receive(Duration::ZERO) {
+            if acceptance_filter.subscribed(frame.identifier()) {
+                self.incoming_frames_tx.send(frame).unwrap();
+            }
+        }
+    }
+
+    /// Frames the node sent since the last call.
+    pub fn sent(&self) -> Vec<CanFrame> {
+        std::iter::from_fn(|| self.tester.receive(Duration::ZERO)).collect()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SimOutput(Arc<AtomicBool>);
+
+impl SimOutput {
+    pub fn is_high(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl DigitalOutput for SimOutput {
+    fn set(&mut self, high: bool) -> anyhow::Result<()> {
+        self.0.store(high, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SimAnalogInput(Arc<AtomicU16>);
+
+impl SimAnalogInput {
+    pub fn set(&self, millivolts: u16) {
+        self.0.store(millivolts, Ordering::Relaxed);
+    }
+}
+
+impl AnalogInput for SimAnalogInput {
+    fn read(&mut self) -> anyhow::Result<u16> {
+        Ok(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Rising edges of a wheel turning at a constant frequency since it was created.
+#[derive(Clone)]
+pub struct SimWheel {
+    /// Hz.
+    frequency: f64,
+    start: Instant,
+    /// Edges already taken.
+    taken: u64,
+}
+
+impl SimWheel {
+    pub fn new(frequency: f64) -> Self {
+        Self {
+            frequency,
+            start: Instant::now(),
+            taken: 0,
+        }
+    }
+}
+
+impl EdgeCapture for SimWheel {
+    fn take_edges(&mut self) -> anyhow::Result<Edges> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let total = (elapsed * self.frequency) as u64;
+        let count = (total - self.taken) as u32;
+        self.taken = total;
+
+        let since_latest =
+            (total > 0).then(|| Duration::from_secs_f64(elapsed - total as f64 / self.frequency));
+
+        Ok(Edges {
+            count,
+            since_latest,
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SimFrequencyOutput(Arc<AtomicU32>);
+
+impl SimFrequencyOutput {
+    pub fn frequency(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl FrequencyOutput for SimFrequencyOutput {
+    fn set_frequency(&mut self, hertz: u32) -> anyhow::Result<()> {
+        self.0.store(hertz, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// There is nothing to update or restart on the host, requests are only logged.
+pub struct SimFirmware;
+
+impl Firmware for SimFirmware {
+    fn request_update(&self) {
+        dbg_println!("[SIM       ] Update requested");
+    }
+
+    fn pass(&self, _check: SelfTestCheck) {}
+
+    fn report(&self, _node_status: &mut NodeStatus) {}
+
+    fn restart(&self) {
+        dbg_println!("[SIM       ] Restart requested");
+    }
+
+    fn reset_reason(&self) -> ResetReason {
+        ResetReason::PowerOn
+    }
+}
+
+/// NVS of a board, lost with the simulation.
+#[derive(Default)]
+pub struct SimConfigStore {
+    numbers: HashMap<String, u32>,
+    strings: HashMap<String, String>,
+}
+
+impl SimConfigStore {
+    /// A freshly provisioned board, only the role is written.
+    pub fn provisioned(role: Role) -> Self {
+        Self::default().with_str("role", role.name())
+    }
+
+    pub fn with_str(mut self, key: &str, value: &str) -> Self {
+        self.strings.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl ConfigStore for SimConfigStore {
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        Ok(self.numbers.get(key).copied())
+    }
+
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.strings.get(key).cloned())
+    }
+
+    fn set_u32(&mut self, key: &str, value: u32) -> anyhow::Result<()> {
+        self.numbers.insert(key.into(), value);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Hardware the node logic runs on.
+//!
+//! The app_threads only talk to these traits, so the same loop runs on the ESP32-S3
+//! ([`esp`]) and on Linux against a virtual bus ([`host`]).
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{protocol::ResetReason, self_test::SelfTestCheck, status::NodeStatus};
+
+#[cfg(target_os = "espidf")]
+pub mod esp;
+#[cfg(not(target_os = "espidf"))]
+pub mod host;
+#[cfg(target_os = "linux")]
+pub mod socketcan;
+
+/// A standard (11 bit) data frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanFrame {
+    identifier: u32,
+    data: [u8; 8],
+    len: usize,
+}
+
+impl CanFrame {
+    /// Returns `None` for more than 8 data bytes.
+    pub fn new(identifier: u32, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut frame = Self {
+            identifier,
+            data: [0; 8],
+            len: data.len(),
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+
+        Some(frame)
+    }
+
+    pub fn identifier(&self) -> u32 {
+        self.identifier
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Driver state of the CAN controller. Only the TWAI driver reports `Stopped` and
+/// `Recovering`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+pub enum BusState {
+    Stopped,
+    #[default]
+    Running,
+    BusOff,
+    /// Waiting for the 128 × 11 recessive bits that end a bus-off.
+    Recovering,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusStatus {
+    pub state: BusState,
+    pub tx_error_counter: u32,
+    pub rx_error_counter: u32,
+    /// Totals since the driver was installed.
+    pub bus_errors: u32,
+    pub arbitration_lost: u32,
+    /// Frames lost because the controller's RX queue or FIFO was full.
+    pub rx_missed: u32,
+}
+
+/// What woke up [`CanBus::wait_for_alerts`], only the alerts somebody counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Alerts {
+    pub transmit_retried: bool,
+}
+
+/// Both directions may be used from different threads at the same time.
+///
+/// The error handling defaults fit buses without an error state of their own, like the
+/// virtual bus of the host simulation.
+pub trait CanBus {
+    /// Queues the frame for transmission, fails if the TX queue stays full.
+    fn transmit(&self, frame: &CanFrame) -> anyhow::Result<()>;
+
+    /// Waits up to `timeout` for a frame, `None` if none arrived.
+    fn receive(&self, timeout: Duration) -> Option<CanFrame>;
+
+    /// Waits up to `timeout` for a change of the error state, e.g. a bus error or the bus-off.
+    fn wait_for_alerts(&self, timeout: Duration) -> Alerts {
+        thread::sleep(timeout);
+        Alerts::default()
+    }
+
+    fn status(&self) -> anyhow::Result<BusStatus> {
+        Ok(BusStatus::default())
+    }
+
+    /// Starts the bus-off recovery, the controller is [`BusState::Stopped`] afterwards.
+    fn initiate_recovery(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Restarts the controller after a finished recovery.
+    fn restart(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The bus shared by the can_receiver and the app_thread.
+pub type SharedCan = Arc<dyn CanBus + Send + Sync>;
+
+pub trait DigitalOutput {
+    fn set(&mut self, high: bool) -> anyhow::Result<()>;
+}
+
+pub trait AnalogInput {
+    /// Millivolts.
+    fn read(&mut self) -> anyhow::Result<u16>;
+}
+
+/// Rising edges of a square wave input, e.g. an ABS sensor, see [`crate::wheel_speed`].
+pub trait EdgeCapture {
+    /// Edges since the last call and the time since the latest one.
+    fn take_edges(&mut self) -> anyhow::Result<Edges>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Edges {
+    pub count: u32,
+    /// `None` before the first edge.
+    pub since_latest: Option<Duration>,
+}
+
+/// Square wave output with 50 % duty cycle, e.g. the speedometer signal.
+pub trait FrequencyOutput {
+    /// 0 stops the output.
+    fn set_frequency(&mut self, hertz: u32) -> anyhow::Result<()>;
+}
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// OTA update, boot validation and restart of the running image.
+pub trait Firmware {
+    fn request_update(&self);
+    fn pass(&self, check: SelfTestCheck);
+    fn report(&self, node_status: &mut NodeStatus);
+    /// Restarts after the reset command, the next boot reports [`ResetReason::Command`].
+    fn restart(&self);
+    /// Why the running firmware was started.
+    fn reset_reason(&self) -> ResetReason;
+}
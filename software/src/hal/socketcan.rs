@@ -0,0 +1,120 @@
+//! SocketCAN bus for running the nodes on Linux, e.g. against the kernel's virtual CAN:
+//!
+//!     sudo ip link add dev vcan0 type vcan && sudo ip link set up vcan0
+//!
+//! Frames of other sockets on the same interface are looped back by the kernel, so several
+//! nodes can share one `vcan0` with `candump`/`cansend` next to them.
+
+use std::{
+    ffi::CString,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+use super::{CanBus, CanFrame};
+
+pub struct SocketCan {
+    socket: OwnedFd,
+}
+
+impl SocketCan {
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Non-blocking, `receive` waits with poll() instead.
+        let socket = unsafe {
+            libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+
+        let mut address: libc::sockaddr_can = unsafe { mem::zeroed() };
+        address.can_family = libc::AF_CAN as libc::sa_family_t;
+        address.can_ifindex = ifindex as libc::c_int;
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_can as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { socket })
+    }
+}
+
+impl CanBus for SocketCan {
+    fn transmit(&self, frame: &CanFrame) -> anyhow::Result<()> {
+        let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+        raw.can_id = frame.identifier() & libc::CAN_SFF_MASK;
+        raw.can_dlc = frame.data().len() as u8;
+        raw.data[..frame.data().len()].copy_from_slice(frame.data());
+
+        let written = unsafe {
+            libc::write(
+                self.socket.as_raw_fd(),
+                &raw as *const libc::can_frame as *const libc::c_void,
+                mem::size_of::<libc::can_frame>(),
+            )
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<CanFrame> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut poll_fd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            // Timed out (0) or failed (-1), both mean no frame for the caller.
+            if unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } <= 0 {
+                return None;
+            }
+
+            let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+            let read = unsafe {
+                libc::read(
+                    self.socket.as_raw_fd(),
+                    &mut raw as *mut libc::can_frame as *mut libc::c_void,
+                    mem::size_of::<libc::can_frame>(),
+                )
+            };
+            // Nothing waiting (EAGAIN) or an error, both mean no frame for the caller.
+            if read != mem::size_of::<libc::can_frame>() as isize {
+                return None;
+            }
+
+            // The nodes only use standard data frames, the TWAI filter drops the rest as well.
+            let flags = libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG;
+            if raw.can_id & flags != 0 {
+                continue;
+            }
+
+            let len = (raw.can_dlc as usize).min(raw.data.len());
+            return CanFrame::new(raw.can_id & libc::CAN_SFF_MASK, &raw.data[..len]);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use esp_idf_hal::{
+    adc::{
+        attenuation::DB_11,
+        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+    },
+    can::CanDriver,
+    gpio::PinDriver,
+    ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolution},
+    peripherals::Peripherals,
+    units::Hertz,
+};
+use esp_idf_svc::nvs::EspNvs;
+use std::{sync::Arc, thread::Builder};
+
+use super::{acceptance_filter, app_thread, spawn_can_receiver, KombiinstrumentIo};
+use crate::{
+    can_stats::{self, CanStats, CountingBus},
+    can_supervisor, config, dbg_println,
+    hal::{
+        esp::{can_pins, EspCan, EspFirmware},
+        SharedCan, SystemClock,
+    },
+    logging,
+    ota::{BootValidation, OtaHandle},
+    protocol::diagnostic_id,
+    self_test::SelfTestCheck,
+    EspData,
+};
+
+pub fn kombiinstrument(data: EspData) {
+    let own_identifier = data.config().own_identifier;
+    logging::init(false);
+    dbg_println!("Init Kombiinstrument at 0x{own_identifier:X}");
+
+    let boot_validation = BootValidation::start(
+        data.nvs(),
+        &[
+            SelfTestCheck::CanStarted,
+            SelfTestCheck::HeartbeatSent,
+            SelfTestCheck::AdcReadable,
+        ],
+    );
+
+    let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
+    let pins = peripherals.pins;
+
+    // Initialize onboard LED (ESP32-S3-DevKit-C1 uses GPIO48)
+    // let mut onboard_led = PinDriver::output(pins.gpio38).unwrap();
+    // onboard_led.set_low().unwrap(); // Set LED to 0% duty cycle (off) - try low
+
+    let can_config = data.can_config().clone(); // cloning seems kind of unnecessary, but we obey the compiler
+
+    // The identifiers the hardware filter lets through in addition are dropped by the can_receiver.
+    let acceptance_filter = acceptance_filter();
+    dbg_println!(
+        "[KBI/can   ] {:?}, {} unsubscribed identifiers pass",
+        acceptance_filter.filters(),
+        acceptance_filter.false_positives()
+    );
+    let can_config = can_config.filter(acceptance_filter.filters().into());
+
+    // init CAN/TWAI
+    let (can_tx_pin, can_rx_pin) = can_pins(data.config().can_pins);
+    let mut can_driver =
+        CanDriver::new(peripherals.can, can_tx_pin, can_rx_pin, &can_config).unwrap();
+
+    can_driver.start().expect("Failed to start CAN driver");
+    boot_validation.pass(SelfTestCheck::CanStarted);
+    let can_stats = Arc::new(CanStats::default());
+    let can_driver: SharedCan = Arc::new(CountingBus::new(
+        Arc::new(EspCan::new(can_driver)),
+        Arc::clone(&can_stats),
+    ));
+
+    let incoming_frames_rx = spawn_can_receiver(Arc::clone(&can_driver), Arc::clone(&can_stats));
+    let can_health = can_supervisor::spawn_supervisor(Arc::clone(&can_driver), "KBI");
+    can_stats::spawn_publisher(
+        Arc::clone(&can_driver),
+        can_stats,
+        diagnostic_id(own_identifier),
+        data.config().bitrate,
+        "KBI",
+    );
+
+    let firmware = EspFirmware {
+        ota: OtaHandle::spawn(peripherals.modem, data.nvs(), data.config()),
+        boot_validation,
+        reset_reason: data.reset_reason(),
+    };
+
+    let parameter_store =
+        EspNvs::new(data.nvs(), config::NAMESPACE, true).expect("Failed to open NVS");
+
+    let app_thread_builder = Builder::new()
+        .name("app_thread".into())
+        .stack_size(8 * 1024);
+    let _ = app_thread_builder.spawn(move || {
+        // --- Hardware and peripheral setup ---
+        let vehicle_speed_pin = pins.gpio10;
+        let engine_speed_pin = pins.gpio11;
+        let oil_pressure_low_pressure_pin = pins.gpio21;
+        let oil_pressure_high_pressure_pin = pins.gpio45;
+        let brake_pedal_pin = pins.gpio12;
+        let vdc_pin = pins.gpio14;
+        let oil_pressure_pin = pins.gpio13;
+
+        let adc_2_driver = AdcDriver::new(peripherals.adc2).unwrap();
+        let adc_2_config = AdcChannelConfig {
+            attenuation: DB_11,
+            ..Default::default()
+        };
+
+        let vdc_channel_driver =
+            AdcChannelDriver::new(&adc_2_driver, vdc_pin, &adc_2_config).unwrap();
+        let brake_pedal_channel_driver =
+            AdcChannelDriver::new(&adc_2_driver, brake_pedal_pin, &adc_2_config).unwrap();
+        let oil_pressure_channel_driver =
+            AdcChannelDriver::new(&adc_2_driver, oil_pressure_pin, &adc_2_config).unwrap();
+
+        // Speed Timer Driver
+        let mut timer_driver = LedcTimerDriver::new(
+            peripherals.ledc.timer0,
+            &TimerConfig {
+                frequency: Hertz(2),
+                resolution: Resolution::Bits14,
+                ..Default::default()
+            },
+        )
+        .expect("Failed to init timer driver");
+
+        // Has to live as long as the app_thread, the timer only changes its frequency.
+        let mut channel = LedcDriver::new(
+            peripherals.ledc.channel0,
+            &mut timer_driver,
+            vehicle_speed_pin,
+        )
+        .expect("Failed to drive Channel");
+
+        let max_duty = channel.get_max_duty();
+        channel.set_duty(max_duty / 2).expect("Failed to set duty");
+
+        // Tachometer Timer Driver, same limits as the speedometer, see crate::gauge.
+        let mut engine_speed_timer_driver = LedcTimerDriver::new(
+            peripherals.ledc.timer1,
+            &TimerConfig {
+                frequency: Hertz(2),
+                resolution: Resolution::Bits14,
+                ..Default::default()
+            },
+        )
+        .expect("Failed to init timer driver");
+
+        let mut engine_speed_channel = LedcDriver::new(
+            peripherals.ledc.channel1,
+            &mut engine_speed_timer_driver,
+            engine_speed_pin,
+        )
+        .expect("Failed to drive Channel");
+
+        let max_duty = engine_speed_channel.get_max_duty();
+        engine_speed_channel
+            .set_duty(max_duty / 2)
+            .expect("Failed to set duty");
+
+        // Oil Pressure PinDriver init
+        let oil_status_pin_low_pressure = PinDriver::output(oil_pressure_low_pressure_pin).unwrap();
+        let oil_status_pin_high_pressure =
+            PinDriver::output(oil_pressure_high_pressure_pin).unwrap();
+
+        let io = KombiinstrumentIo {
+            vehicle_speed: Box::new(timer_driver),
+            engine_speed: Box::new(engine_speed_timer_driver),
+            oil_pressure_low_pressure: Box::new(oil_status_pin_low_pressure),
+            oil_pressure_high_pressure: Box::new(oil_status_pin_high_pressure),
+            vdc: Box::new(vdc_channel_driver),
+            oil_pressure: Box::new(oil_pressure_channel_driver),
+            brake_pedal: Box::new(brake_pedal_channel_driver),
+            firmware: Box::new(firmware),
+            parameter_store: Box::new(parameter_store),
+        };
+
+        app_thread(
+            io,
+            Box::new(SystemClock),
+            can_driver,
+            can_health,
+            own_identifier,
+            incoming_frames_rx,
+        );
+    });
+}
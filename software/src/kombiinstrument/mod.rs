@@ -1,281 +1,993 @@
-use enumset::enum_set;
-use esp_idf_hal::{
-    adc::{
-        attenuation::DB_11,
-        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
-    },
-    can::{config::Filter, CanDriver, Flags, Frame},
-    gpio::PinDriver,
-    ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolution},
-    peripherals::Peripherals,
-    units::Hertz,
-};
 use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread::{self, Builder},
+    sync::{mpsc::Receiver, Arc},
     time::{Duration, Instant},
 };
 
-use crate::{dbg_println, logging, EspData};
+use crate::{
+    can_service,
+    can_stats::CanStats,
+    can_supervisor::CanHealth,
+    config::ConfigStore,
+    dbg_println,
+    freshness::FreshnessTracker,
+    gauge::{Gauge, Sweep},
+    hal::{AnalogInput, CanFrame, Clock, DigitalOutput, Firmware, FrequencyOutput, SharedCan},
+    oil_pressure::{OilInput, OilPressure, OilPressureConfig},
+    parameters::{Parameter, Parameters},
+    protocol::{
+        filter::AcceptanceFilter,
+        oem::{
+            GraNeu, Motor1, Motor2, Motor3, Motor5, Motor6, Motor7, GRA_NEU_ID, MOTOR_1_ID,
+            MOTOR_2_ID, MOTOR_3_ID, MOTOR_5_ID, MOTOR_6_ID, MOTOR_7_ID,
+        },
+        ErrorCode, KombiinstrumentStatus, NodeCommand, NodeRequest, ParameterRequest,
+        UniversalFrame, WheelSpeeds, ENGINE_BAY_UNIT_ID, NODE_REQUEST_ID, PARAMETER_REQUEST_ID,
+        WHEEL_SPEEDS_ID,
+    },
+    scheduler::{Scheduler, TaskContext},
+    self_test::SelfTestCheck,
+    speedometer::{SpeedometerCalibration, FULL_SCALE_KMH},
+    status::NodeStatus,
+    tachometer::{RpmFilter, TachometerCalibration, FULL_SCALE_RPM},
+    vehicle_speed::{Axle, SpeedModel},
+};
+
+#[cfg(target_os = "espidf")]
+mod esp;
+#[cfg(target_os = "espidf")]
+pub use esp::kombiinstrument;
+
+/// Frames the app_thread handles, everything else is dropped by the TWAI filter or the
+/// can_receiver.
+const SUBSCRIBED_IDS: [u32; 11] = [
+    NODE_REQUEST_ID,
+    PARAMETER_REQUEST_ID,
+    ENGINE_BAY_UNIT_ID,
+    WHEEL_SPEEDS_ID,
+    MOTOR_1_ID,
+    MOTOR_2_ID,
+    MOTOR_3_ID,
+    GRA_NEU_ID,
+    MOTOR_5_ID,
+    MOTOR_6_ID,
+    MOTOR_7_ID,
+];
+
+/// Period of the `can_rx` task, short enough to keep up with the engine ECU.
+const CAN_RX_PERIOD: Duration = Duration::from_millis(10);
+
+// --- Parameters, see crate::parameters ---
+
+/// Period of the `cycle` task in ms, which also sends the status frame. Applies after a
+/// restart, the other nodes time out after 500 ms.
+const CYCLE_TIME: Parameter = Parameter {
+    number: 0x01,
+    key: "cycle_ms",
+    default: 100,
+    min: 20,
+    max: 200,
+};
+
+/// Brake pedal input above this percentage of VDC counts as braking.
+const BRAKE_THRESHOLD: Parameter = Parameter {
+    number: 0x02,
+    key: "brake_thr_perc",
+    default: 50,
+    min: 5,
+    max: 95,
+};
+
+/// Above this engine speed in rpm the oil pressure has to reach the high switch point.
+const OIL_HIGH_PRESSURE_RPM: Parameter = Parameter {
+    number: 0x04,
+    key: "oil_hp_rpm",
+    default: 2000,
+    min: 0,
+    max: 8000,
+};
+
+// 0x03 was the speed scale, replaced by the speed model below.
+
+/// Teeth of the ABS tone rings.
+const TONE_RING_TEETH: Parameter = Parameter {
+    number: 0x05,
+    key: "teeth",
+    default: 48,
+    min: 1,
+    max: 200,
+};
+
+/// Dynamic rolling circumference of the tyres in mm.
+const TYRE_CIRCUMFERENCE: Parameter = Parameter {
+    number: 0x06,
+    key: "tyre_circ_mm",
+    default: 1950,
+    min: 1000,
+    max: 3000,
+};
+
+/// Speed correction in 1/1000.
+const SPEED_CALIBRATION: Parameter = Parameter {
+    number: 0x07,
+    key: "speed_cal",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
+
+/// 0 front, 1 rear.
+const DRIVEN_AXLE: Parameter = Parameter {
+    number: 0x08,
+    key: "driven_axle",
+    default: 0,
+    min: 0,
+    max: 1,
+};
+
+/// Pulses per km the speedometer expects, the K number on the back of the gauge.
+const SPEEDOMETER_PULSES_PER_KM: Parameter = Parameter {
+    number: 0x09,
+    key: "speedo_ppk",
+    default: 3600,
+    min: 500,
+    max: 20000,
+};
+
+// Speedometer corrections in 1/1000, interpolated between the speeds.
 
-pub fn calc_speed(abs_sens_fl: u16, abs_sens_fr: u16, abs_sens_rl: u16, abs_sens_rr: u16) -> u8 {
-    let highest_freq = *[abs_sens_fl, abs_sens_fr, abs_sens_rl, abs_sens_rr]
-        .iter()
-        .max()
-        .unwrap();
-    let speed = highest_freq as f32 / 1000.0;
+/// At 20 km/h.
+const SPEEDOMETER_CORRECTION_20: Parameter = Parameter {
+    number: 0x0a,
+    key: "speedo_c20",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
 
-    speed as u8
+/// At 50 km/h.
+const SPEEDOMETER_CORRECTION_50: Parameter = Parameter {
+    number: 0x0b,
+    key: "speedo_c50",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
+
+/// At 100 km/h.
+const SPEEDOMETER_CORRECTION_100: Parameter = Parameter {
+    number: 0x0c,
+    key: "speedo_c100",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
+
+/// At 150 km/h.
+const SPEEDOMETER_CORRECTION_150: Parameter = Parameter {
+    number: 0x0d,
+    key: "speedo_c150",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
+
+/// At 200 km/h.
+const SPEEDOMETER_CORRECTION_200: Parameter = Parameter {
+    number: 0x0e,
+    key: "speedo_c200",
+    default: 1000,
+    min: 500,
+    max: 1500,
+};
+
+/// Tachometer pulses per revolution in 0.1, 20 for four cylinders.
+const TACHOMETER_PULSES_PER_REV: Parameter = Parameter {
+    number: 0x0f,
+    key: "tach_ppr_x10",
+    default: 20,
+    min: 5,
+    max: 120,
+};
+
+/// Time constant of the tachometer smoothing in ms, 0 is off.
+const TACHOMETER_FILTER: Parameter = Parameter {
+    number: 0x10,
+    key: "tach_filter_ms",
+    default: 150,
+    min: 0,
+    max: 1000,
+};
+
+/// 0 pressure switch, 1 analog sender.
+const OIL_INPUT: Parameter = Parameter {
+    number: 0x11,
+    key: "oil_input",
+    default: 1,
+    min: 0,
+    max: 1,
+};
+
+/// Oil pressure sender input in mV at 0 bar.
+const OIL_SENDER_ZERO: Parameter = Parameter {
+    number: 0x12,
+    key: "oil_zero_mv",
+    default: 330,
+    min: 0,
+    max: 3000,
+};
+
+/// Oil pressure sender input in mV at OIL_SENDER_RANGE.
+const OIL_SENDER_FULL: Parameter = Parameter {
+    number: 0x13,
+    key: "oil_full_mv",
+    default: 2970,
+    min: 100,
+    max: 3300,
+};
+
+/// Oil pressure sender range in 0.1 bar.
+const OIL_SENDER_RANGE: Parameter = Parameter {
+    number: 0x14,
+    key: "oil_full_dbar",
+    default: 100,
+    min: 10,
+    max: 250,
+};
+
+/// Low oil pressure switch point in 0.1 bar.
+const OIL_LOW_POINT: Parameter = Parameter {
+    number: 0x15,
+    key: "oil_low_dbar",
+    default: 3,
+    min: 1,
+    max: 50,
+};
+
+/// High oil pressure switch point in 0.1 bar.
+const OIL_HIGH_POINT: Parameter = Parameter {
+    number: 0x16,
+    key: "oil_high_dbar",
+    default: 14,
+    min: 1,
+    max: 100,
+};
+
+/// Hysteresis of both oil pressure switch points in 0.1 bar.
+const OIL_HYSTERESIS: Parameter = Parameter {
+    number: 0x17,
+    key: "oil_hyst_dbar",
+    default: 2,
+    min: 0,
+    max: 20,
+};
+
+/// An oil pressure switch point has to be crossed for this long in ms.
+const OIL_DEBOUNCE: Parameter = Parameter {
+    number: 0x18,
+    key: "oil_debounce_ms",
+    default: 500,
+    min: 0,
+    max: 5000,
+};
+
+static PARAMETERS: [Parameter; 23] = [
+    CYCLE_TIME,
+    BRAKE_THRESHOLD,
+    OIL_HIGH_PRESSURE_RPM,
+    TONE_RING_TEETH,
+    TYRE_CIRCUMFERENCE,
+    SPEED_CALIBRATION,
+    DRIVEN_AXLE,
+    SPEEDOMETER_PULSES_PER_KM,
+    SPEEDOMETER_CORRECTION_20,
+    SPEEDOMETER_CORRECTION_50,
+    SPEEDOMETER_CORRECTION_100,
+    SPEEDOMETER_CORRECTION_150,
+    SPEEDOMETER_CORRECTION_200,
+    TACHOMETER_PULSES_PER_REV,
+    TACHOMETER_FILTER,
+    OIL_INPUT,
+    OIL_SENDER_ZERO,
+    OIL_SENDER_FULL,
+    OIL_SENDER_RANGE,
+    OIL_LOW_POINT,
+    OIL_HIGH_POINT,
+    OIL_HYSTERESIS,
+    OIL_DEBOUNCE,
+];
+
+/// 0x222 is sent every 100 ms, so five missed frames mean the engine bay unit is gone.
+const WHEEL_SPEEDS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Motor_1 is sent every 10 ms, it only stops with the ignition or a dead engine ECU.
+const ENGINE_DATA_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Brake pedal state sent while the pedal or VDC cannot be read, e.g. while Wi-Fi blocks
+/// ADC2 during an update. The engine_bay_unit keeps the brake lights on with it.
+const BRAKE_PEDAL_SAFE_STATE: bool = true;
+
+/// Everything the app_thread drives or reads, see [`crate::hal`].
+pub struct KombiinstrumentIo<'a> {
+    pub vehicle_speed: Box<dyn FrequencyOutput + 'a>,
+    pub engine_speed: Box<dyn FrequencyOutput + 'a>,
+    pub oil_pressure_low_pressure: Box<dyn DigitalOutput + 'a>,
+    pub oil_pressure_high_pressure: Box<dyn DigitalOutput + 'a>,
+    pub vdc: Box<dyn AnalogInput + 'a>,
+    /// Oil pressure sender or switch.
+    pub oil_pressure: Box<dyn AnalogInput + 'a>,
+    pub brake_pedal: Box<dyn AnalogInput + 'a>,
+    pub firmware: Box<dyn Firmware + 'a>,
+    /// Where the parameters are saved.
+    pub parameter_store: Box<dyn ConfigStore + 'a>,
+}
+
+pub fn acceptance_filter() -> AcceptanceFilter {
+    AcceptanceFilter::new(&SUBSCRIBED_IDS)
+}
+
+/// Spawns the can_receiver thread, which forwards the frames of interest to the app_thread.
+pub fn spawn_can_receiver(can: SharedCan, stats: Arc<CanStats>) -> Receiver<CanFrame> {
+    can_service::spawn_receiver(can, acceptance_filter(), stats, "KBI")
 }
 
-pub fn kombiinstrument(data: EspData, own_identifier: u32) {
-    logging::init(false);
-    dbg_println!("Init Kombiinstrument at 0x{own_identifier:X}");
-
-    // Channel for the CAN receiver to send received frames to the app_thread
-    let (incoming_frames_tx, incoming_frames_rx) = mpsc::sync_channel(20);
-
-    let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
-    let pins = peripherals.pins;
-
-    // Initialize onboard LED (ESP32-S3-DevKit-C1 uses GPIO48)
-    // let mut onboard_led = PinDriver::output(pins.gpio38).unwrap();
-    // onboard_led.set_low().unwrap(); // Set LED to 0% duty cycle (off) - try low
-
-    let can_config = data.can_config().clone(); // cloning seems kind of unnecessary, but we obey the compiler
-    let can_config = can_config.filter(Filter::Standard { filter: 0x222, mask: 0b11111100000 }); 
-
-    // init CAN/TWAI
-    let mut can_driver = CanDriver::new(
-        peripherals.can,
-        pins.gpio48,
-        pins.gpio47,
-        &can_config,
-    )
-    .unwrap();
-
-    can_driver.start().expect("Failed to start CAN driver");
-    let can_driver = Arc::new(Mutex::new(can_driver));
-
-    let can_receiver_can_driver = Arc::clone(&can_driver);
-    let can_receiver_thread_builder = Builder::new()
-        .name("can_receiver".into())
-        .stack_size(8 * 1024);
-    let _ = can_receiver_thread_builder.spawn(move || {
-        loop {
-            {
-                let can = can_receiver_can_driver.lock().unwrap();
-                // Attempt to receive frames, non-blocking.
-                for _ in 0..10 {
-                    if let Ok(frame) = can.receive(0) {
-                        dbg_println!("[KBI/can <-] {:X} {:?}", frame.identifier(), frame.data());
-                        if frame.identifier() == 0x222 || frame.identifier() == 0x210 {
-                            // Only forward frames that are of interest to the app_thread.
-                            if let Err(e) = incoming_frames_tx.try_send(frame) {
-                                dbg_println!(
-                                    "[KBI/can   ] Incoming frame dropped, channel full: {:?}",
-                                    e
-                                );
+/// State shared by the tasks of the app_thread.
+struct Kombiinstrument<'a> {
+    io: KombiinstrumentIo<'a>,
+    can: SharedCan,
+    can_health: CanHealth,
+    own_identifier: u32,
+    incoming_frames_rx: Receiver<CanFrame>,
+    node_status: NodeStatus,
+    freshness: FreshnessTracker,
+    parameters: Parameters,
+    /// Shared by both gauges.
+    sweep: Sweep,
+    speedometer: Gauge,
+    tachometer: Gauge,
+    tachometer_filter: RpmFilter,
+    oil_pressure: OilPressure,
+    /// 0.01 km/h.
+    vehicle_speed: u16,
+    engine_rpm: u16,
+    coolant_temp: Option<f32>,
+    throttle_perc: Option<f32>,
+    // Only logged until their layouts are confirmed, see crate::protocol::oem.
+    cruise_control: Option<GraNeu>,
+    fuel_consumption_ul: Option<u16>,
+    engine_torque_perc: Option<f32>,
+    oil_temp: Option<f32>,
+}
+
+impl Kombiinstrument<'_> {
+    /// Picks up the received frames, the engine ECU alone sends one every few ms.
+    fn receive(&mut self, context: &TaskContext) {
+        let mut latest_speed_data: Option<WheelSpeeds> = None;
+
+        // if the incoming_frames is flooded with messages, this will appear to hang.
+        while let Ok(frame) = self.incoming_frames_rx.try_recv() {
+            match frame.identifier() {
+                NODE_REQUEST_ID => match NodeRequest::decode(frame.data()) {
+                    Ok(request) if request.addresses(self.own_identifier) => {
+                        match request.command {
+                            NodeCommand::Update => {
+                                dbg_println!("[KBI/can   ] Update requested");
+                                self.io.firmware.request_update();
                             }
+                            NodeCommand::Reset => self.reset(),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid node request: {}", e),
+                },
+                PARAMETER_REQUEST_ID => match ParameterRequest::decode(frame.data()) {
+                    Ok(request) if request.target == self.own_identifier => {
+                        let response = self
+                            .parameters
+                            .handle(&request, &mut *self.io.parameter_store);
+                        dbg_println!("[KBI/param ] {:?} -> {:?}", request, response);
+                        let frame = CanFrame::new(
+                            self.own_identifier,
+                            &UniversalFrame::Parameter(response).encode(),
+                        )
+                        .unwrap();
+                        if let Err(e) = self.can.transmit(&frame) {
+                            dbg_println!("[KBI/param ] Failed to answer: {}", e);
                         }
-                    } else {
-                        // No more frames in the queue
-                        break;
                     }
+                    Ok(_) => {}
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid parameter request: {}", e),
+                },
+                ENGINE_BAY_UNIT_ID => {
+                    dbg_println!("[KBI/can <-] {:X} {:?}", frame.identifier(), frame.data());
                 }
-            }
-            // Yield to other threads, though, in theory, this first thread should always be on the first core and the other thread on the second core.
-            thread::sleep(Duration::from_millis(20));
-        }
-    });
-
-    let app_thread_can_driver = Arc::clone(&can_driver);
-    let app_thread_builder = Builder::new()
-        .name("app_thread".into())
-        .stack_size(8 * 1024);
-    let _ = app_thread_builder.spawn(move || {
-        let cycle_time: u8 = 100;
-
-        // --- Hardware and peripheral setup ---
-        let vehicle_speed_pin = pins.gpio10;
-        let oil_pressure_low_pressure_pin = pins.gpio21;
-        let oil_pressure_high_pressure_pin = pins.gpio45;
-        let brake_pedal_pin = pins.gpio12;
-        let vdc_pin = pins.gpio14;
-
-        let adc_2_driver = AdcDriver::new(peripherals.adc2).unwrap();
-        let adc_2_config = AdcChannelConfig {
-            attenuation: DB_11,
-            ..Default::default()
-        };
-        
-        let mut vdc_channel_driver =
-        AdcChannelDriver::new(&adc_2_driver, vdc_pin, &adc_2_config).unwrap();
-        let mut brake_pedal_channel_driver =
-        AdcChannelDriver::new(&adc_2_driver, brake_pedal_pin, &adc_2_config).unwrap();
-
-        // Speed Timer Driver
-        let mut timer_driver = LedcTimerDriver::new(
-            peripherals.ledc.timer0,
-            &TimerConfig {
-                frequency: Hertz(2),
-                resolution: Resolution::Bits14,
-                ..Default::default()
-            },
-        )
-        .expect("Failed to init timer driver");
+                WHEEL_SPEEDS_ID => {
+                    dbg_println!("[KBI/can <-] {:X} {:?}", frame.identifier(), frame.data());
 
-        let mut channel = LedcDriver::new(
-            peripherals.ledc.channel0,
-            &mut timer_driver,
-            vehicle_speed_pin,
-        )
-        .expect("Failed to drive Channel");
-
-        let max_duty = channel.get_max_duty();
-        channel
-            .set_duty(max_duty / 2)
-            .expect("Failed to set duty");
-
-        // Oil Pressure PinDriver init
-        let mut oil_status_pin_low_pressure =
-            PinDriver::output(oil_pressure_low_pressure_pin).unwrap();
-        let mut oil_status_pin_high_pressure =
-            PinDriver::output(oil_pressure_high_pressure_pin).unwrap();
-
-        // set frequency to 200 Hertz
-        timer_driver
-            .set_frequency(Hertz(200))
-            .expect("Failed to set frequency");
-
-        // --- Local state variables ---
-        let mut tachotest_wait_counter: u8 = 4;
-        let mut vehicle_speed: u8 = 0;
-        let oil_pressure_status_low_pressure: bool = false;
-        // let oil_pressure_status_high_pressure: bool = false; // Placeholder
-        let engine_rpm: u16 = 0; // Placeholder
-        let mut tct_perc: u8 = 0;
-
-        loop {
-            let start_time = Instant::now();
-
-            // --- CAN Frame Reception ---
-            let mut latest_speed_data: Option<(u16, u16, u16, u16)> = None;
-
-            // if the incoming_frames is flooded with messages, this will appear to hang.
-            while let Ok(frame) = incoming_frames_rx.try_recv() {
-                match frame.identifier() {
-                    0x210 => {
-                        dbg_println!("[KBI/can <-] {:X} {:?}", frame.identifier(), frame.data());
-                    }
-                    0x222 => {
-                        dbg_println!("[KBI/can <-] {:X} {:?}", frame.identifier(), frame.data());
-
-                        let data = frame.data();
-                        let abs_sens_fl = u16::from_be_bytes([data[0], data[1]]);
-                        let abs_sens_fr = u16::from_be_bytes([data[2], data[3]]);
-                        let abs_sens_rl = u16::from_be_bytes([data[4], data[5]]);
-                        let abs_sens_rr = u16::from_be_bytes([data[6], data[7]]);
-                        latest_speed_data =
-                            Some((abs_sens_fl, abs_sens_fr, abs_sens_rl, abs_sens_rr));
+                    match WheelSpeeds::decode(frame.data()) {
+                        Ok(wheel_speeds) => {
+                            self.freshness.received(WHEEL_SPEEDS_ID, context.now);
+                            latest_speed_data = Some(wheel_speeds);
+                        }
+                        Err(e) => dbg_println!("[KBI/can   ] Invalid wheel speeds: {}", e),
                     }
-                    _ => {}
                 }
+                // The engine ECU sends every 10 ms, only the latest values are kept.
+                MOTOR_1_ID => match Motor1::decode(frame.data()) {
+                    Ok(motor_1) => {
+                        self.freshness.received(MOTOR_1_ID, context.now);
+                        self.engine_rpm = motor_1.engine_rpm;
+                    }
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_1: {}", e),
+                },
+                MOTOR_2_ID => match Motor2::decode(frame.data()) {
+                    Ok(motor_2) => self.coolant_temp = Some(motor_2.coolant_temp),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_2: {}", e),
+                },
+                MOTOR_3_ID => match Motor3::decode(frame.data()) {
+                    Ok(motor_3) => self.throttle_perc = Some(motor_3.throttle_perc),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_3: {}", e),
+                },
+                GRA_NEU_ID => match GraNeu::decode(frame.data()) {
+                    Ok(gra_neu) => self.cruise_control = Some(gra_neu),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid GRA_Neu: {}", e),
+                },
+                MOTOR_5_ID => match Motor5::decode(frame.data()) {
+                    Ok(motor_5) => self.fuel_consumption_ul = Some(motor_5.fuel_consumption_ul),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_5: {}", e),
+                },
+                MOTOR_6_ID => match Motor6::decode(frame.data()) {
+                    Ok(motor_6) => self.engine_torque_perc = Some(motor_6.engine_torque_perc),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_6: {}", e),
+                },
+                MOTOR_7_ID => match Motor7::decode(frame.data()) {
+                    Ok(motor_7) => self.oil_temp = Some(motor_7.oil_temp),
+                    Err(e) => dbg_println!("[KBI/can   ] Invalid Motor_7: {}", e),
+                },
+                _ => {}
             }
+        }
 
-            if let Some((fl, fr, rl, rr)) = latest_speed_data {
-                vehicle_speed = calc_speed(fl, fr, rl, rr);
-            }
+        if let Some(wheel_speeds) = latest_speed_data {
+            self.vehicle_speed = self.speed_model().vehicle_speed(wheel_speeds.hertz());
+        }
+    }
+
+    /// Built from the parameters every time, so written values apply right away.
+    fn speed_model(&self) -> SpeedModel {
+        SpeedModel {
+            teeth: self.parameters.get(&TONE_RING_TEETH),
+            circumference_mm: self.parameters.get(&TYRE_CIRCUMFERENCE),
+            calibration_permille: self.parameters.get(&SPEED_CALIBRATION),
+            driven_axle: match self.parameters.get(&DRIVEN_AXLE) {
+                0 => Axle::Front,
+                _ => Axle::Rear,
+            },
+        }
+    }
+
+    fn speedometer_calibration(&self) -> SpeedometerCalibration {
+        SpeedometerCalibration {
+            pulses_per_km: self.parameters.get(&SPEEDOMETER_PULSES_PER_KM),
+            corrections: [
+                &SPEEDOMETER_CORRECTION_20,
+                &SPEEDOMETER_CORRECTION_50,
+                &SPEEDOMETER_CORRECTION_100,
+                &SPEEDOMETER_CORRECTION_150,
+                &SPEEDOMETER_CORRECTION_200,
+            ]
+            .map(|parameter| self.parameters.get(parameter)),
+        }
+    }
 
-            if tachotest_wait_counter == 0  || tachotest_wait_counter > 0 && vehicle_speed > 0 {
-                timer_driver.set_frequency(Hertz(2)).expect("Failed to set frequency");
-            }
-            if vehicle_speed == 0 && tachotest_wait_counter > 0 {
-                tachotest_wait_counter -= 1;
+    fn oil_pressure_config(&self) -> OilPressureConfig {
+        OilPressureConfig {
+            input: match self.parameters.get(&OIL_INPUT) {
+                0 => OilInput::Switch,
+                _ => OilInput::Sender,
+            },
+            zero_mv: self.parameters.get(&OIL_SENDER_ZERO),
+            full_mv: self.parameters.get(&OIL_SENDER_FULL),
+            full_dbar: self.parameters.get(&OIL_SENDER_RANGE),
+            low_dbar: self.parameters.get(&OIL_LOW_POINT),
+            high_dbar: self.parameters.get(&OIL_HIGH_POINT),
+            hysteresis_dbar: self.parameters.get(&OIL_HYSTERESIS),
+            high_pressure_rpm: self.parameters.get(&OIL_HIGH_PRESSURE_RPM),
+            debounce: Duration::from_millis(self.parameters.get(&OIL_DEBOUNCE) as u64),
+        }
+    }
+
+    fn tachometer_calibration(&self) -> TachometerCalibration {
+        TachometerCalibration {
+            pulses_per_rev_x10: self.parameters.get(&TACHOMETER_PULSES_PER_REV),
+        }
+    }
+
+    /// Confirms the reset command before restarting.
+    fn reset(&mut self) {
+        dbg_println!("[KBI/can   ] Reset requested");
+        let frame =
+            CanFrame::new(self.own_identifier, &UniversalFrame::Resetting.encode()).unwrap();
+        if let Err(e) = self.can.transmit(&frame) {
+            dbg_println!("[KBI/can   ] Failed to confirm the reset: {}", e);
+        }
+        self.io.firmware.restart();
+    }
+
+    /// Reads the sensors, drives the outputs and sends the status frame.
+    fn cycle(&mut self, context: &TaskContext) {
+        let now = context.now;
+        let speedometer_calibration = self.speedometer_calibration();
+        let tachometer_calibration = self.tachometer_calibration();
+        let oil_pressure_config = self.oil_pressure_config();
+        let tachometer_filter =
+            Duration::from_millis(self.parameters.get(&TACHOMETER_FILTER) as u64);
+        let io = &mut self.io;
+        let node_status = &mut self.node_status;
+
+        // Never show the last known speed when the wheel speeds stopped arriving.
+        if self.freshness.is_stale(WHEEL_SPEEDS_ID, now) {
+            self.vehicle_speed = 0;
+        }
+        // Same for the engine, a stale rpm would keep the high pressure switch armed.
+        if self.freshness.is_stale(MOTOR_1_ID, now) {
+            self.engine_rpm = 0;
+            self.coolant_temp = None;
+            self.throttle_perc = None;
+            self.cruise_control = None;
+            self.fuel_consumption_ul = None;
+            self.engine_torque_perc = None;
+            self.oil_temp = None;
+        }
+        self.freshness.report(node_status, now);
+        self.can_health.report(node_status, now);
+        io.firmware.report(node_status);
+
+        // --- Sensor Reading ---
+
+        let vdc = io.vdc.read();
+        let brake_pedal_value = io.brake_pedal.read();
+        let oil_pressure_value = io.oil_pressure.read();
+        let adc_ok = vdc.is_ok() && brake_pedal_value.is_ok() && oil_pressure_value.is_ok();
+        node_status.check(ErrorCode::AdcReadFailed, adc_ok);
+        if adc_ok {
+            io.firmware.pass(SelfTestCheck::AdcReadable);
+        }
+        let brake_pedal_active = match (&vdc, &brake_pedal_value) {
+            (Ok(vdc), Ok(brake_pedal_value)) => {
+                let brake_threshold = *vdc as u32 * self.parameters.get(&BRAKE_THRESHOLD) / 100;
+                *brake_pedal_value as u32 > brake_threshold
             }
+            _ => BRAKE_PEDAL_SAFE_STATE,
+        };
+        let vdc = vdc.unwrap_or(0);
+        let brake_pedal_value = brake_pedal_value.unwrap_or(0);
 
-            // --- Sensor Reading ---
+        // Keeps the last switch states while the input cannot be read.
+        if let Ok(millivolts) = oil_pressure_value {
+            self.oil_pressure
+                .update(millivolts, self.engine_rpm, &oil_pressure_config, now);
+        }
+        let oil_pressure = self.oil_pressure.state();
+        node_status.check(ErrorCode::OilPressureLow, !oil_pressure.too_low);
+
+        // --- Actuator/Output Logic ---
+
+        // --- Gauges ---
+        // The first real reading ends the sweep, it must never hide one.
+        if self.vehicle_speed > 0 || self.engine_rpm > 0 {
+            self.sweep.end();
+        }
+        let engine_rpm = self.tachometer_filter.update(
+            self.engine_rpm as f32,
+            context.period,
+            tachometer_filter,
+        );
+        let (speedometer_hz, tachometer_hz) = match self.sweep.position(now) {
+            Some(position) => (
+                position * speedometer_calibration.frequency(FULL_SCALE_KMH),
+                position * tachometer_calibration.frequency(FULL_SCALE_RPM),
+            ),
+            None => (
+                speedometer_calibration.frequency(self.vehicle_speed as f32 / 100.0),
+                tachometer_calibration.frequency(engine_rpm),
+            ),
+        };
+        let speedometer_result = self.speedometer.set(&mut *io.vehicle_speed, speedometer_hz);
+        let tachometer_result = self.tachometer.set(&mut *io.engine_speed, tachometer_hz);
+
+        // The cluster sees its own switches, high above the switch point.
+        let oil_high_result = io.oil_pressure_high_pressure.set(oil_pressure.above_high);
+        let oil_low_result = io.oil_pressure_low_pressure.set(oil_pressure.above_low);
+        node_status.check(
+            ErrorCode::OutputFailed,
+            oil_high_result.is_ok()
+                && oil_low_result.is_ok()
+                && speedometer_result.is_ok()
+                && tachometer_result.is_ok(),
+        );
+
+        // --- CAN Frame Transmission ---
+        let tct_perc = context.load_perc();
+        let frame_data = KombiinstrumentStatus {
+            brake_pedal_active_0: brake_pedal_active,
+            brake_pedal_active_1: brake_pedal_active,
+            reset_reason: io.firmware.reset_reason(),
+            tct_perc,
+        }
+        .encode();
+        let frame = CanFrame::new(self.own_identifier, &frame_data).unwrap();
+
+        // Warnings, errors and update progress are sent in addition to the status frame,
+        // which already carries the "online" status byte.
+        let universal_frame = node_status.next_frame();
+        let universal_frame = (universal_frame != UniversalFrame::Online)
+            .then(|| CanFrame::new(self.own_identifier, &universal_frame.encode()).unwrap());
+
+        let can_send_status = self.can.transmit(&frame).is_ok()
+            && universal_frame.map_or(true, |frame| self.can.transmit(&frame).is_ok());
+        if can_send_status {
+            io.firmware.pass(SelfTestCheck::HeartbeatSent);
+        }
+        node_status.check(ErrorCode::CanTransmitFailed, can_send_status);
+
+        // --- Logging ---
+        dbg_println!(
+            "[KBI/app   ] V: {:.2} km/h | RPM: {} | Coolant: {:?}°C | Throttle: {:?}% | Brake: {} ({}mV) | Oil: {}/{} ({}mV) | VDC: {}mV | Q_kbi:{} | {:?} | Cycle: {:?} / {}%",
+            self.vehicle_speed as f32 / 100.0,
+            self.engine_rpm,
+            self.coolant_temp,
+            self.throttle_perc,
+            brake_pedal_active,
+            brake_pedal_value,
+            oil_pressure.above_low,
+            oil_pressure.above_high,
+            oil_pressure_value.unwrap_or(0),
+            vdc,
+            can_send_status,
+            node_status.state(),
+            context.stats.last_runtime,
+            tct_perc
+        );
+        dbg_println!(
+            "[KBI/ecu   ] GRA: {:?} | Fuel: {:?}µl | Torque: {:?}% | Oil temp: {:?}°C",
+            self.cruise_control,
+            self.fuel_consumption_ul,
+            self.engine_torque_perc,
+            self.oil_temp
+        );
+    }
+}
 
-            let vdc = vdc_channel_driver.read().unwrap();
-            let brake_pedal_value = brake_pedal_channel_driver.read().unwrap();
+impl<'a> Kombiinstrument<'a> {
+    fn new(
+        io: KombiinstrumentIo<'a>,
+        now: Instant,
+        can: SharedCan,
+        can_health: CanHealth,
+        own_identifier: u32,
+        incoming_frames_rx: Receiver<CanFrame>,
+    ) -> Self {
+        // Swapped switch points would turn the hysteresis upside down.
+        let parameters = Parameters::load(&PARAMETERS, &*io.parameter_store, "KBI")
+            .with_order(&OIL_LOW_POINT, &OIL_HIGH_POINT);
+        let freshness = FreshnessTracker::new(now)
+            .with_signal(
+                WHEEL_SPEEDS_ID,
+                WHEEL_SPEEDS_TIMEOUT,
+                ErrorCode::WheelSpeedsTimeout,
+            )
+            .with_signal(
+                MOTOR_1_ID,
+                ENGINE_DATA_TIMEOUT,
+                ErrorCode::EngineDataTimeout,
+            );
 
+        Self {
+            io,
+            can,
+            can_health,
+            own_identifier,
+            incoming_frames_rx,
+            node_status: NodeStatus::new(),
+            freshness,
+            parameters,
+            sweep: Sweep::new(now),
+            speedometer: Gauge::default(),
+            tachometer: Gauge::default(),
+            tachometer_filter: RpmFilter::default(),
+            oil_pressure: OilPressure::default(),
+            vehicle_speed: 0,
+            engine_rpm: 0,
+            coolant_temp: None,
+            throttle_perc: None,
+            cruise_control: None,
+            fuel_consumption_ul: None,
+            engine_torque_perc: None,
+            oil_temp: None,
+        }
+    }
+}
 
-            let brake_pedal_active = brake_pedal_value > vdc / 2; // brake pedal threshold is 50% of vdc
+pub fn app_thread(
+    io: KombiinstrumentIo<'_>,
+    clock: Box<dyn Clock + '_>,
+    can: SharedCan,
+    can_health: CanHealth,
+    own_identifier: u32,
+    incoming_frames_rx: Receiver<CanFrame>,
+) {
+    let mut kombiinstrument = Kombiinstrument::new(
+        io,
+        clock.now(),
+        can,
+        can_health,
+        own_identifier,
+        incoming_frames_rx,
+    );
+    let cycle_period = Duration::from_millis(kombiinstrument.parameters.get(&CYCLE_TIME) as u64);
+
+    Scheduler::new(clock, "KBI")
+        .with_task("can_rx", CAN_RX_PERIOD, Kombiinstrument::receive)
+        .with_task("cycle", cycle_period, Kombiinstrument::cycle)
+        .run(&mut kombiinstrument);
+}
 
-            // --- Actuator/Output Logic ---
-            let freq_value = vehicle_speed as u32;
-            let freq = if freq_value > 2 {
-                Hertz(freq_value)
-            } else {
-                Hertz(2)
+// Bug: When vdc is 0, the brake pedal is not read correctly.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        can_supervisor,
+        hal::host::{
+            SimAnalogInput, SimConfigStore, SimFirmware, SimFrequencyOutput, SimOutput, TestBus,
+        },
+        protocol::{
+            ParameterCommand, ParameterResponse, ParameterResult, ResetReason, KOMBIINSTRUMENT_ID,
+        },
+        scheduler::TaskStats,
+    };
+
+    const VDC_MV: u16 = 2400;
+
+    /// About 100 km/h with the default tone ring and tyres.
+    const WHEEL_FREQUENCY: f32 = 683.8;
+
+    /// Wi-Fi holds ADC2 during an update.
+    struct BusyAdc;
+
+    impl AnalogInput for BusyAdc {
+        fn read(&mut self) -> anyhow::Result<u16> {
+            anyhow::bail!("ADC2 is used by Wi-Fi")
+        }
+    }
+
+    /// The node on a virtual bus and its outputs.
+    struct Bench {
+        node: Kombiinstrument<'static>,
+        bus: TestBus,
+        vehicle_speed: SimFrequencyOutput,
+        engine_speed: SimFrequencyOutput,
+        oil_pressure_outputs: (SimOutput, SimOutput),
+        start: Instant,
+    }
+
+    impl Bench {
+        fn new(brake_pedal: Box<dyn AnalogInput>) -> Self {
+            let (bus, incoming_frames_rx) = TestBus::new();
+            let vehicle_speed = SimFrequencyOutput::default();
+            let engine_speed = SimFrequencyOutput::default();
+            let oil_pressure_outputs = (SimOutput::default(), SimOutput::default());
+            let vdc = SimAnalogInput::default();
+            vdc.set(VDC_MV);
+            // 3.5 bar from the sender with the default range.
+            let oil_pressure = SimAnalogInput::default();
+            oil_pressure.set(1254);
+
+            let io = KombiinstrumentIo {
+                vehicle_speed: Box::new(vehicle_speed.clone()),
+                engine_speed: Box::new(engine_speed.clone()),
+                oil_pressure_low_pressure: Box::new(oil_pressure_outputs.0.clone()),
+                oil_pressure_high_pressure: Box::new(oil_pressure_outputs.1.clone()),
+                vdc: Box::new(vdc),
+                oil_pressure: Box::new(oil_pressure),
+                brake_pedal,
+                firmware: Box::new(SimFirmware),
+                parameter_store: Box::new(SimConfigStore::default()),
             };
-            timer_driver
-                .set_frequency(freq)
-                .expect("Failed to set frequency");
-
-            // Placeholder oil pressure logic
-            let oil_pressure_status_high_pressure = engine_rpm > 2000;
-            if oil_pressure_status_high_pressure {
-                oil_status_pin_high_pressure.set_high().unwrap();
-            } else {
-                oil_status_pin_high_pressure.set_low().unwrap();
-            }
-            if oil_pressure_status_low_pressure {
-                oil_status_pin_low_pressure.set_high().unwrap();
-            } else {
-                oil_status_pin_low_pressure.set_low().unwrap();
+            let can_health = can_supervisor::spawn_supervisor(bus.node_can(), "KBI");
+            let start = Instant::now();
+            let node = Kombiinstrument::new(
+                io,
+                start,
+                bus.node_can(),
+                can_health,
+                KOMBIINSTRUMENT_ID,
+                incoming_frames_rx,
+            );
+
+            Self {
+                node,
+                bus,
+                vehicle_speed,
+                engine_speed,
+                oil_pressure_outputs,
+                start,
             }
+        }
 
-            // --- CAN Frame Transmission ---
-            let brake_pedal_active_byte = if brake_pedal_active {
-                0b11000000
-            } else {
-                0b00000000
-            };
-            
-            let frame_data = [
-                0x11,
-                brake_pedal_active_byte,
-                0,
-                0,
-                0,
-                0,
-                0,
-                tct_perc,
-            ];
-            let frame = Frame::new(own_identifier, enum_set!(Flags::None), &frame_data).unwrap();
-
-            let can_send_status = {
-                app_thread_can_driver
-                    .lock()
-                    .unwrap()
-                    .transmit(&frame, 2)
-                    .is_ok()
-            };
+        /// Sends `frames` to the node and runs `can_rx` at `at` after the start.
+        fn receive(&mut self, frames: &[CanFrame], at: Duration) -> Vec<CanFrame> {
+            for frame in frames {
+                self.bus.send(*frame, &acceptance_filter());
+            }
+            self.node.receive(&self.context(CAN_RX_PERIOD, at));
+            self.bus.sent()
+        }
 
-            // --- Cycle Time Calculation and Logging ---
-            let elapsed = start_time.elapsed();
-            let cycle_time_percentage = 100 * elapsed.as_millis() / cycle_time as u128;
-            tct_perc = cycle_time_percentage as u8;
+        /// Runs `cycle` at `at` after the start, returns the frames the node sent.
+        fn cycle(&mut self, at: Duration) -> Vec<CanFrame> {
+            self.node
+                .cycle(&self.context(Duration::from_millis(100), at));
+            self.bus.sent()
+        }
 
-            dbg_println!(
-                "[KBI/app   ] V: {} | RPM: {} | Brake: {} ({}mV) | VDC: {}mV | Q_kbi:{} | Cycle: {:?} / {}%",
-                vehicle_speed,
-                engine_rpm,
-                brake_pedal_active,
-                brake_pedal_value,
-                vdc,
-                can_send_status,
-                elapsed,
-                tct_perc
-            );
+        /// Receives `frames` and runs `cycle` every 100 ms from `from` to `to`, returns the
+        /// frames sent by the last cycle.
+        fn run(&mut self, frames: &[CanFrame], from: Duration, to: Duration) -> Vec<CanFrame> {
+            let mut at = from;
+            loop {
+                self.receive(frames, at);
+                let sent = self.cycle(at);
+                at += Duration::from_millis(100);
+                if at > to {
+                    return sent;
+                }
+            }
+        }
 
-            // --- Sleep ---
-            if let Some(remaining) = Duration::from_millis(cycle_time as u64).checked_sub(elapsed) {
-                thread::sleep(remaining);
+        fn context(&self, period: Duration, at: Duration) -> TaskContext {
+            TaskContext {
+                now: self.start + at,
+                period,
+                stats: TaskStats::default(),
             }
         }
-    });
-}
+    }
+
+    fn brake_pedal(millivolts: u16) -> (SimAnalogInput, Box<dyn AnalogInput>) {
+        let input = SimAnalogInput::default();
+        input.set(millivolts);
+        (input.clone(), Box::new(input))
+    }
+
+    fn wheel_speeds(hertz: f32) -> CanFrame {
+        CanFrame::new(
+            WHEEL_SPEEDS_ID,
+            &WheelSpeeds::from_hertz([hertz; 4]).encode(),
+        )
+        .unwrap()
+    }
+
+    fn motor_1(rpm: u16) -> CanFrame {
+        let raw = (rpm * 4).to_le_bytes();
+        CanFrame::new(MOTOR_1_ID, &[0, 0, raw[0], raw[1], 0, 0, 0, 0]).unwrap()
+    }
+
+    fn status_frame(sent: &[CanFrame]) -> KombiinstrumentStatus {
+        let frame = sent
+            .iter()
+            .find(|frame| frame.identifier() == KOMBIINSTRUMENT_ID)
+            .expect("status frame");
+        KombiinstrumentStatus::decode(frame.data()).unwrap()
+    }
+
+    #[test]
+    fn needle_sweep() {
+        let mut bench = Bench::new(brake_pedal(0).1);
+
+        bench.cycle(Duration::ZERO);
+        assert_eq!(bench.vehicle_speed.frequency(), 0);
+        bench.cycle(Duration::from_millis(600));
+        assert_eq!(bench.vehicle_speed.frequency(), 130);
+        bench.cycle(Duration::from_millis(1200));
+        assert_eq!(bench.vehicle_speed.frequency(), 260);
+        assert_eq!(bench.engine_speed.frequency(), 267);
+        bench.cycle(Duration::from_millis(2400));
+        assert_eq!(bench.vehicle_speed.frequency(), 0);
+        assert_eq!(bench.engine_speed.frequency(), 0);
+    }
+
+    #[test]
+    fn gauges_follow_the_bus() {
+        let mut bench = Bench::new(brake_pedal(0).1);
+        let frames = [wheel_speeds(WHEEL_FREQUENCY), motor_1(2500)];
+
+        // The first real reading ends the sweep.
+        bench.run(&frames, Duration::ZERO, Duration::from_secs(2));
+        assert_eq!(bench.vehicle_speed.frequency(), 100);
+        assert_eq!(bench.engine_speed.frequency(), 83);
+    }
+
+    #[test]
+    fn gauges_drop_to_zero_without_data() {
+        let mut bench = Bench::new(brake_pedal(0).1);
+        let frames = [wheel_speeds(WHEEL_FREQUENCY), motor_1(2500)];
+        bench.run(&frames, Duration::ZERO, Duration::from_secs(1));
+
+        let mut sent = bench.run(&[], Duration::from_millis(1100), Duration::from_secs(3));
+        assert_eq!(bench.vehicle_speed.frequency(), 0);
+        assert_eq!(bench.engine_speed.frequency(), 0);
+
+        // Both warnings take turns.
+        sent.extend(bench.cycle(Duration::from_millis(3100)));
+        let warnings: Vec<_> = sent
+            .iter()
+            .filter_map(|frame| UniversalFrame::decode(frame.data()).ok())
+            .collect();
+        for code in [ErrorCode::WheelSpeedsTimeout, ErrorCode::EngineDataTimeout] {
+            assert!(warnings.contains(&UniversalFrame::Warning(code.number())));
+        }
+    }
+
+    #[test]
+    fn oil_pressure_switches() {
+        let mut bench = Bench::new(brake_pedal(0).1);
+        let frames = [motor_1(2500)];
+
+        bench.run(&frames, Duration::ZERO, Duration::from_secs(5));
+        assert!(bench.oil_pressure_outputs.0.is_high());
+        assert!(bench.oil_pressure_outputs.1.is_high());
+    }
+
+    #[test]
+    fn oil_pressure_switch_points_stay_in_order() {
+        let mut bench = Bench::new(brake_pedal(0).1);
+        let write = ParameterRequest {
+            command: ParameterCommand::Write,
+            target: KOMBIINSTRUMENT_ID,
+            parameter: OIL_LOW_POINT.number,
+            value: OIL_HIGH_POINT.default,
+        };
 
-// Bug: When vdc is 0, the brake pedal is not read correctly.
\ No newline at end of file
+        let frame = CanFrame::new(PARAMETER_REQUEST_ID, &write.encode()).unwrap();
+        let sent = bench.receive(&[frame], Duration::ZERO);
+        assert_eq!(
+            sent.iter()
+                .map(|frame| UniversalFrame::decode(frame.data()))
+                .collect::<Vec<_>>(),
+            [Ok(UniversalFrame::Parameter(ParameterResponse {
+                command: ParameterCommand::Write,
+                parameter: OIL_LOW_POINT.number,
+                result: ParameterResult::OutOfRange,
+                value: OIL_LOW_POINT.default,
+            }))]
+        );
+    }
+
+    #[test]
+    fn brake_pedal_status() {
+        let (pedal, input) = brake_pedal(0);
+        let mut bench = Bench::new(input);
+
+        let status = status_frame(&bench.cycle(Duration::ZERO));
+        assert!(!status.brake_pedal_active_0 && !status.brake_pedal_active_1);
+        assert_eq!(status.reset_reason, ResetReason::PowerOn);
+
+        // Above half of VDC.
+        pedal.set(VDC_MV / 2 + 1);
+        let status = status_frame(&bench.cycle(Duration::from_millis(100)));
+        assert!(status.brake_pedal_active_0 && status.brake_pedal_active_1);
+    }
+
+    #[test]
+    fn brake_pedal_safe_state_without_adc() {
+        let mut bench = Bench::new(Box::new(BusyAdc));
+        let sent = bench.cycle(Duration::ZERO);
+
+        let status = status_frame(&sent);
+        assert!(status.brake_pedal_active_0 && status.brake_pedal_active_1);
+        assert!(sent.iter().any(|frame| frame.data()
+            == UniversalFrame::Warning(ErrorCode::AdcReadFailed.number()).encode()));
+    }
+}
@@ -1,54 +1,133 @@
+#[cfg(target_os = "espidf")]
 use enumset::enum_set;
+#[cfg(target_os = "espidf")]
 use esp_idf_hal::can::{
-    config::{Config, Mode, Timing},
+    config::{Config, Mode},
     Alert,
 };
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
 
+#[cfg(target_os = "espidf")]
+use config::{NodeConfig, Role};
+#[cfg(target_os = "espidf")]
+use protocol::ResetReason;
+
+mod can_service;
+mod can_stats;
+mod can_supervisor;
+mod config;
+#[cfg(target_os = "espidf")]
 mod dev_can_sender;
 mod engine_bay_unit;
+mod freshness;
+mod gauge;
+mod hal;
 mod kombiinstrument;
 mod logging;
+mod oil_pressure;
+#[cfg(target_os = "espidf")]
+mod ota;
+#[cfg(target_os = "espidf")]
 mod output_test;
+mod parameters;
+mod protocol;
+mod scheduler;
+#[cfg(target_os = "espidf")]
 mod secret;
+mod self_test;
+#[cfg(not(target_os = "espidf"))]
+mod sim;
+mod speedometer;
+mod status;
+mod tachometer;
+#[cfg(target_os = "espidf")]
 mod util;
+mod vehicle_speed;
+mod wheel_speed;
 
+#[cfg(target_os = "espidf")]
 #[derive(Clone)]
-struct EspData(Config);
+struct EspData {
+    can_config: Config,
+    config: NodeConfig,
+    nvs: EspDefaultNvsPartition,
+    reset_reason: ResetReason,
+}
 
+#[cfg(target_os = "espidf")]
 impl EspData {
     fn can_config(&self) -> &Config {
-        &self.0
+        &self.can_config
+    }
+
+    fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    /// Wi-Fi keeps its calibration in the same partition.
+    fn nvs(&self) -> EspDefaultNvsPartition {
+        self.nvs.clone()
+    }
+
+    /// Sent in the online frames.
+    fn reset_reason(&self) -> ResetReason {
+        self.reset_reason
     }
 }
 
+#[cfg(target_os = "espidf")]
 fn main() -> anyhow::Result<()> {
     esp_idf_hal::sys::link_patches();
 
-    // TODO: OTA-Update preparation and update on CAN-Signal
-    // TODO: reset/update on CAN-Signal
-    // TODO: global cycle time
-
-    // msg adresses: 640, 648, 896, 1416, 1160, 1152, 906
+    // Read before anything can restart the chip again.
+    let reset_reason = hal::esp::take_reset_reason();
+    println!("Reset reason: {reset_reason:?}");
 
+    // Every alert wakes up the can_supervisor, which then reads the error state.
     let alerts = enum_set!(
-        Alert::BusOffline | Alert::TransmitFailed | Alert::BusError | Alert::TransmitRetried
+        Alert::BusOffline
+            | Alert::BusRecovered
+            | Alert::AboveErrorWarning
+            | Alert::BelowErrorWarning
+            | Alert::ErrorPassive
+            | Alert::ErrorActive
+            | Alert::TransmitFailed
+            | Alert::BusError
+            | Alert::TransmitRetried
     );
-    let data = EspData(
-        Config::new()
-            .timing(Timing::B500K)
+
+    // A board without a valid configuration restarts with this error until it is written.
+    let nvs = EspDefaultNvsPartition::take()?;
+    let config = NodeConfig::load(&EspNvs::new(nvs.clone(), config::NAMESPACE, true)?)
+        .map_err(|e| anyhow::anyhow!("Invalid node configuration in NVS: {e}"))?;
+    println!("Node configuration: {config:?}");
+
+    let data = EspData {
+        can_config: Config::new()
+            .timing(config.bitrate.into())
             .mode(Mode::Normal)
+            // The can_receiver empties it right away, this only covers it being descheduled
+            // during a burst of the engine ECU.
+            .rx_queue_len(32)
             .alerts(alerts),
-    );
+        config,
+        nvs,
+        reset_reason,
+    };
 
-    if cfg!(feature = "dev_can_sender") {
-        dev_can_sender::dev_can_sender(0x777);
-    } else if cfg!(feature = "kombiinstrument") {
-        kombiinstrument::kombiinstrument(data.clone(), 0x310);
-    } else if cfg!(feature = "engine_bay_unit") {
-        engine_bay_unit::engine_bay_unit(data.clone(), 0x210);
-    } else if cfg!(feature = "output_test") {
-        output_test::output_test(data.clone(), 0x776);
+    match data.config().role {
+        Role::DevCanSender => dev_can_sender::dev_can_sender(data),
+        Role::Kombiinstrument => kombiinstrument::kombiinstrument(data),
+        Role::EngineBayUnit => engine_bay_unit::engine_bay_unit(data),
+        Role::OutputTest => output_test::output_test(data),
     }
 
     Ok(())
 }
+
+/// On the host all nodes run together on a virtual bus, see [`sim`].
+#[cfg(not(target_os = "espidf"))]
+fn main() -> anyhow::Result<()> {
+    sim::run()
+}
@@ -0,0 +1,235 @@
+//! Oil pressure of the kombiinstrument.
+//!
+//! The pressure comes from an analog sender or a single pressure switch on an ADC pin.
+//! It is compared against a low and a high switch point, each with hysteresis and a
+//! debounce time, which drive the two switch outputs of the OEM cluster. With the engine
+//! running the pressure has to stay above the low point, and above the high point over
+//! `high_pressure_rpm`, otherwise it is too low.
+
+use std::time::{Duration, Instant};
+
+/// Engine speed above which the engine runs, cranking stays below.
+const ENGINE_RUNNING_RPM: u16 = 400;
+
+/// The pressure needs a moment to build up after the start.
+const BUILD_UP_TIME: Duration = Duration::from_secs(3);
+
+/// A closed switch pulls the input below this.
+const SWITCH_CLOSED_MV: u16 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OilInput {
+    /// Closes to ground with pressure, which counts as above both switch points.
+    Switch,
+    /// Linear from `zero_mv` at 0 bar to `full_mv` at `full_dbar`.
+    Sender,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OilPressureConfig {
+    pub input: OilInput,
+    pub zero_mv: u32,
+    pub full_mv: u32,
+    /// 0.1 bar.
+    pub full_dbar: u32,
+    pub low_dbar: u32,
+    pub high_dbar: u32,
+    pub hysteresis_dbar: u32,
+    pub high_pressure_rpm: u32,
+    pub debounce: Duration,
+}
+
+impl OilPressureConfig {
+    /// `millivolts` of the input, returns 0.1 bar.
+    pub fn pressure_dbar(&self, millivolts: u16) -> u32 {
+        match self.input {
+            OilInput::Switch if millivolts < SWITCH_CLOSED_MV => u32::MAX,
+            OilInput::Switch => 0,
+            OilInput::Sender => {
+                let span = self.full_mv.saturating_sub(self.zero_mv).max(1);
+                (millivolts as u32).saturating_sub(self.zero_mv) * self.full_dbar / span
+            }
+        }
+    }
+}
+
+/// Above `point` once the pressure exceeded it, below again at `point - hysteresis`. A
+/// change only applies after it held for the debounce time.
+#[derive(Debug, Default)]
+struct SwitchPoint {
+    above: bool,
+    pending_since: Option<Instant>,
+}
+
+impl SwitchPoint {
+    fn update(&mut self, pressure: u32, point: u32, config: &OilPressureConfig, now: Instant) {
+        let above = if self.above {
+            pressure > point.saturating_sub(config.hysteresis_dbar)
+        } else {
+            pressure > point
+        };
+
+        if above == self.above {
+            self.pending_since = None;
+            return;
+        }
+        let pending_since = *self.pending_since.get_or_insert(now);
+        if now.saturating_duration_since(pending_since) >= config.debounce {
+            self.above = above;
+            self.pending_since = None;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OilPressureState {
+    pub above_low: bool,
+    pub above_high: bool,
+    /// Too low with the engine running.
+    pub too_low: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct OilPressure {
+    low: SwitchPoint,
+    high: SwitchPoint,
+    running_since: Option<Instant>,
+    state: OilPressureState,
+}
+
+impl OilPressure {
+    pub fn update(
+        &mut self,
+        millivolts: u16,
+        engine_rpm: u16,
+        config: &OilPressureConfig,
+        now: Instant,
+    ) {
+        let pressure = config.pressure_dbar(millivolts);
+        self.low.update(pressure, config.low_dbar, config, now);
+        self.high.update(pressure, config.high_dbar, config, now);
+
+        let running = if engine_rpm > ENGINE_RUNNING_RPM {
+            let since = *self.running_since.get_or_insert(now);
+            now.saturating_duration_since(since) >= BUILD_UP_TIME
+        } else {
+            self.running_since = None;
+            false
+        };
+        let high_required = engine_rpm as u32 > config.high_pressure_rpm;
+
+        self.state = OilPressureState {
+            above_low: self.low.above,
+            above_high: self.high.above,
+            too_low: running && (!self.low.above || high_required && !self.high.above),
+        };
+    }
+
+    pub fn state(&self) -> OilPressureState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 bar over 2640 mV, so 264 mV per bar.
+    const CONFIG: OilPressureConfig = OilPressureConfig {
+        input: OilInput::Sender,
+        zero_mv: 330,
+        full_mv: 2970,
+        full_dbar: 100,
+        low_dbar: 3,
+        high_dbar: 14,
+        hysteresis_dbar: 2,
+        high_pressure_rpm: 2000,
+        debounce: Duration::from_millis(500),
+    };
+
+    fn millivolts(dbar: u32) -> u16 {
+        (330 + (dbar * 264 + 9) / 10) as u16
+    }
+
+    /// Runs `steps` updates 100 ms apart, returns the time after them.
+    fn run(
+        oil_pressure: &mut OilPressure,
+        dbar: u32,
+        rpm: u16,
+        start: Instant,
+        steps: u32,
+    ) -> Instant {
+        let mut now = start;
+        for _ in 0..steps {
+            oil_pressure.update(millivolts(dbar), rpm, &CONFIG, now);
+            now += Duration::from_millis(100);
+        }
+        now
+    }
+
+    #[test]
+    fn sender() {
+        assert_eq!(CONFIG.pressure_dbar(330), 0);
+        assert_eq!(CONFIG.pressure_dbar(100), 0);
+        assert_eq!(CONFIG.pressure_dbar(1650), 50);
+        assert_eq!(CONFIG.pressure_dbar(2970), 100);
+    }
+
+    #[test]
+    fn switch() {
+        let config = OilPressureConfig {
+            input: OilInput::Switch,
+            ..CONFIG
+        };
+
+        assert_eq!(config.pressure_dbar(0), u32::MAX);
+        assert_eq!(config.pressure_dbar(3300), 0);
+    }
+
+    #[test]
+    fn debounce() {
+        let mut oil_pressure = OilPressure::default();
+        let now = run(&mut oil_pressure, 20, 1000, Instant::now(), 5);
+        assert!(!oil_pressure.state().above_low);
+
+        run(&mut oil_pressure, 20, 1000, now, 1);
+        assert!(oil_pressure.state().above_low);
+        assert!(oil_pressure.state().above_high);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut oil_pressure = OilPressure::default();
+        let now = run(&mut oil_pressure, 20, 1000, Instant::now(), 10);
+
+        // 1.3 bar is within the hysteresis of the high point, 1.2 bar is below it.
+        let now = run(&mut oil_pressure, 13, 1000, now, 10);
+        assert!(oil_pressure.state().above_high);
+        run(&mut oil_pressure, 12, 1000, now, 10);
+        assert!(!oil_pressure.state().above_high);
+        assert!(oil_pressure.state().above_low);
+    }
+
+    #[test]
+    fn too_low_only_with_the_engine_running() {
+        let mut oil_pressure = OilPressure::default();
+        run(&mut oil_pressure, 0, 0, Instant::now(), 50);
+        assert!(!oil_pressure.state().too_low);
+
+        let mut oil_pressure = OilPressure::default();
+        let now = run(&mut oil_pressure, 0, 800, Instant::now(), 29);
+        assert!(!oil_pressure.state().too_low);
+        run(&mut oil_pressure, 0, 800, now, 2);
+        assert!(oil_pressure.state().too_low);
+    }
+
+    #[test]
+    fn high_point_above_high_pressure_rpm() {
+        let mut oil_pressure = OilPressure::default();
+        let now = run(&mut oil_pressure, 10, 800, Instant::now(), 40);
+        assert!(!oil_pressure.state().too_low);
+
+        run(&mut oil_pressure, 10, 3000, now, 1);
+        assert!(oil_pressure.state().too_low);
+    }
+}
@@ -0,0 +1,498 @@
+//! CAN-triggered OTA update into the inactive `ota_0`/`ota_1` slot.
+//!
+//! The update runs in its own thread, so the app_thread keeps driving its outputs
+//! while the image is downloaded. Progress and failures are handed back through
+//! [`OtaHandle::report`], which feeds them into the node's [`NodeStatus`].
+//!
+//! A new image boots in the pending-verify state (`CONFIG_BOOTLOADER_APP_ROLLBACK_ENABLE`).
+//! [`BootValidation`] only marks it valid after the [`SelfTest`] passed, otherwise the
+//! bootloader rolls back to the previous slot.
+//!
+//! Images are signed by the ota_server tooling: the Ed25519 signature covers the
+//! SHA-256 digest of the image, checked against [`OTA_PUBLIC_KEY`] before the download
+//! starts. The download has to hash to that digest, otherwise the slot is never marked
+//! bootable.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use esp_idf_hal::{modem::Modem, task::block_on};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    http::{
+        client::{Configuration as HttpConfiguration, EspHttpConnection},
+        Method,
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    ota::{EspOta, SlotState},
+    timer::EspTaskTimerService,
+    wifi::{AsyncWifi, EspWifi},
+};
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, Builder},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{self, NodeConfig},
+    dbg_println, hal,
+    protocol::{ErrorCode, ResetReason},
+    secret::{OTA_PUBLIC_KEY, OTA_SERVER},
+    self_test::{SelfTest, SelfTestCheck, SelfTestResult},
+    status::NodeStatus,
+    util::connect_wifi,
+};
+
+const CHUNK_SIZE: usize = 4096;
+
+/// A dropped download is resumed with a Range request this many times before giving up.
+const MAX_RESUMES: u8 = 5;
+const RESUME_DELAY: Duration = Duration::from_secs(1);
+
+/// Time to publish the final progress frame before rebooting into the new image.
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
+
+/// NVS key of the flash address of the rolled back slot that was already reported, so
+/// the rollback is only raised on the first boot after it.
+const ROLLBACK_REPORTED_KEY: &str = "ota_rolled_back";
+
+/// Time to publish the critical error of a failed self-test before rolling back.
+const ROLLBACK_DELAY: Duration = Duration::from_secs(1);
+
+const SELF_TEST_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Errors an update can fail with, see [`OtaState::Failed`].
+const UPDATE_ERRORS: [ErrorCode; 5] = [
+    ErrorCode::OtaWifiFailed,
+    ErrorCode::OtaDownloadFailed,
+    ErrorCode::OtaChecksumMismatch,
+    ErrorCode::OtaWriteFailed,
+    ErrorCode::OtaSignatureInvalid,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OtaState {
+    Idle,
+    Updating { progress: u8 },
+    Failed(ErrorCode),
+}
+
+#[derive(Debug)]
+struct OtaError(ErrorCode, String);
+
+/// The fields of an ota_server manifest entry the device needs.
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    version: Version,
+    pcb_revision: String,
+    file: String,
+    size: u64,
+    crc32: u32,
+    /// Lowercase hex.
+    sha256: String,
+    /// Ed25519 signature of the SHA-256 digest, lowercase hex.
+    signature: String,
+}
+
+impl ManifestEntry {
+    /// Returns the signed SHA-256 digest the image has to match.
+    fn verified_sha256(&self) -> Result<[u8; 32], OtaError> {
+        let invalid = |reason: &str| {
+            OtaError(
+                ErrorCode::OtaSignatureInvalid,
+                format!("{}: {reason}", self.file),
+            )
+        };
+
+        let key =
+            VerifyingKey::from_bytes(&OTA_PUBLIC_KEY).map_err(|_| invalid("invalid public key"))?;
+        let sha256: [u8; 32] = parse_hex(&self.sha256).ok_or_else(|| invalid("invalid sha256"))?;
+        let signature: [u8; 64] =
+            parse_hex(&self.signature).ok_or_else(|| invalid("not signed"))?;
+
+        key.verify_strict(&sha256, &Signature::from_bytes(&signature))
+            .map_err(|_| invalid("signature does not match"))?;
+
+        Ok(sha256)
+    }
+}
+
+enum UpdateOutcome {
+    Updated,
+    UpToDate,
+}
+
+pub struct OtaHandle {
+    requests: SyncSender<()>,
+    state: Arc<Mutex<OtaState>>,
+}
+
+impl OtaHandle {
+    /// Spawns the update thread. Role and PCB revision of `config` select the image from
+    /// the ota_server manifest.
+    pub fn spawn(modem: Modem, nvs: EspDefaultNvsPartition, config: &NodeConfig) -> Self {
+        let (requests, requests_rx) = mpsc::sync_channel(1);
+        let state = Arc::new(Mutex::new(OtaState::Idle));
+
+        let role = config.role.name();
+        let pcb_revision = config.pcb_revision.clone();
+        let thread_state = Arc::clone(&state);
+        let ota_thread_builder = Builder::new().name("ota".into()).stack_size(16 * 1024);
+        let _ = ota_thread_builder.spawn(move || {
+            let mut modem = modem;
+            while requests_rx.recv().is_ok() {
+                *thread_state.lock().unwrap() = OtaState::Updating { progress: 0 };
+                match update(&mut modem, &nvs, role, &pcb_revision, &thread_state) {
+                    Ok(UpdateOutcome::Updated) => {
+                        thread::sleep(REBOOT_DELAY);
+                        hal::esp::restart(ResetReason::Update);
+                    }
+                    Ok(UpdateOutcome::UpToDate) => {
+                        dbg_println!("[OTA       ] Already up to date");
+                        *thread_state.lock().unwrap() = OtaState::Idle;
+                    }
+                    Err(OtaError(code, message)) => {
+                        dbg_println!("[OTA       ] Update failed: {}", message);
+                        *thread_state.lock().unwrap() = OtaState::Failed(code);
+                    }
+                }
+            }
+        });
+
+        Self { requests, state }
+    }
+
+    /// Starts an update, unless one is already running.
+    pub fn request_update(&self) {
+        let _ = self.requests.try_send(());
+    }
+
+    pub fn report(&self, node_status: &mut NodeStatus) {
+        let state = *self.state.lock().unwrap();
+        match state {
+            OtaState::Idle | OtaState::Failed(_) => node_status.update_finished(),
+            OtaState::Updating { progress } => node_status.update_progress(progress),
+        }
+
+        // Only the failure of the latest update stays raised.
+        for code in UPDATE_ERRORS {
+            node_status.check(code, state != OtaState::Failed(code));
+        }
+    }
+}
+
+/// Wi-Fi is only brought up for the update and torn down again if it fails.
+fn update(
+    modem: &mut Modem,
+    nvs: &EspDefaultNvsPartition,
+    role: &str,
+    pcb_revision: &str,
+    state: &Mutex<OtaState>,
+) -> Result<UpdateOutcome, OtaError> {
+    let wifi_error = |e: anyhow::Error| OtaError(ErrorCode::OtaWifiFailed, e.to_string());
+    let download_error = |e: anyhow::Error| OtaError(ErrorCode::OtaDownloadFailed, e.to_string());
+    let write_error = |e: anyhow::Error| OtaError(ErrorCode::OtaWriteFailed, e.to_string());
+
+    // --- Wi-Fi ---
+    let sysloop = EspSystemEventLoop::take().map_err(|e| wifi_error(e.into()))?;
+    let timer_service = EspTaskTimerService::new().map_err(|e| wifi_error(e.into()))?;
+    let mut wifi = AsyncWifi::wrap(
+        EspWifi::new(modem, sysloop.clone(), Some(nvs.clone()))
+            .map_err(|e| wifi_error(e.into()))?,
+        sysloop,
+        timer_service,
+    )
+    .map_err(|e| wifi_error(e.into()))?;
+    block_on(connect_wifi(&mut wifi)).map_err(wifi_error)?;
+
+    // --- Manifest ---
+    let manifest_url = format!("{OTA_SERVER}/manifest/{role}?pcb_revision={pcb_revision}");
+    let mut connection = get(&manifest_url, 0).map_err(download_error)?;
+    let manifest = read_to_end(&mut connection).map_err(download_error)?;
+    let entries: Vec<ManifestEntry> =
+        serde_json::from_slice(&manifest).map_err(|e| download_error(e.into()))?;
+    let entry = entries
+        .into_iter()
+        .find(|entry| entry.pcb_revision == pcb_revision)
+        .ok_or_else(|| download_error(anyhow::anyhow!("no image for {pcb_revision}")))?;
+
+    let running_version = Version::parse(env!("CARGO_PKG_VERSION")).expect("valid crate version");
+    if entry.version <= running_version {
+        return Ok(UpdateOutcome::UpToDate);
+    }
+
+    let signed_sha256 = entry.verified_sha256()?;
+
+    // --- Download ---
+    let url = format!("{OTA_SERVER}/{}", entry.file);
+    dbg_println!("[OTA       ] Downloading {} {}", url, entry.version);
+
+    let mut connection = get(&url, 0).map_err(download_error)?;
+    let content_length = header_value(&connection, "Content-Length").map_err(download_error)?;
+    let expected_crc32 = header_value(&connection, "X-Crc32").map_err(download_error)? as u32;
+    if content_length != entry.size || expected_crc32 != entry.crc32 {
+        return Err(OtaError(
+            ErrorCode::OtaChecksumMismatch,
+            format!("{} does not match the manifest", entry.file),
+        ));
+    }
+
+    // --- Write the inactive slot ---
+    let mut ota = EspOta::new().map_err(|e| write_error(e.into()))?;
+    let mut ota_update = ota.initiate_update().map_err(|e| write_error(e.into()))?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut sha256 = Sha256::new();
+    let mut buffer = [0; CHUNK_SIZE];
+    let mut received: u64 = 0;
+    let mut resumes = 0;
+    while received < content_length {
+        let read = match connection.read(&mut buffer) {
+            Ok(read) if read > 0 => read,
+            result => {
+                // Dropped Wi-Fi, continue where the download stopped.
+                let error = match result {
+                    Err(e) => e.to_string(),
+                    Ok(_) => "connection closed".to_string(),
+                };
+                dbg_println!("[OTA       ] {} after {} bytes, resuming", error, received);
+
+                loop {
+                    if resumes == MAX_RESUMES {
+                        let _ = ota_update.abort();
+                        return Err(download_error(anyhow::anyhow!(
+                            "{error} after {received} of {content_length} bytes"
+                        )));
+                    }
+                    resumes += 1;
+                    thread::sleep(RESUME_DELAY);
+
+                    if let Ok(resumed) = get(&url, received) {
+                        connection = resumed;
+                        break;
+                    }
+                }
+                continue;
+            }
+        };
+
+        hasher.update(&buffer[..read]);
+        sha256.update(&buffer[..read]);
+        if let Err(e) = ota_update.write(&buffer[..read]) {
+            let _ = ota_update.abort();
+            return Err(write_error(e.into()));
+        }
+
+        received += read as u64;
+        let progress = (received * 255 / content_length) as u8;
+        *state.lock().unwrap() = OtaState::Updating { progress };
+    }
+
+    let crc32 = hasher.finalize();
+    if crc32 != expected_crc32 {
+        let _ = ota_update.abort();
+        return Err(OtaError(
+            ErrorCode::OtaChecksumMismatch,
+            format!("crc32 {crc32} does not match {expected_crc32}"),
+        ));
+    }
+
+    if sha256.finalize().as_slice() != signed_sha256 {
+        let _ = ota_update.abort();
+        return Err(OtaError(
+            ErrorCode::OtaSignatureInvalid,
+            format!("{} does not match its signed sha256", entry.file),
+        ));
+    }
+
+    // Marks the written slot as the next boot partition.
+    ota_update.complete().map_err(|e| write_error(e.into()))?;
+    // The slot is no longer invalid, a failure of the new image is reported again.
+    if let Err(e) = EspNvs::new(nvs.clone(), config::NAMESPACE, true)
+        .and_then(|mut store| store.remove(ROLLBACK_REPORTED_KEY))
+    {
+        dbg_println!("[OTA       ] Failed to reset the rollback report: {}", e);
+    }
+    dbg_println!(
+        "[OTA       ] Update of {} bytes complete, rebooting",
+        received
+    );
+
+    Ok(UpdateOutcome::Updated)
+}
+
+/// Sends a GET request and returns the connection ready to read the body.
+///
+/// With an `offset` the body starts at that byte, using a Range request.
+fn get(url: &str, offset: u64) -> anyhow::Result<EspHttpConnection> {
+    let mut connection = EspHttpConnection::new(&HttpConfiguration {
+        buffer_size: Some(CHUNK_SIZE),
+        ..Default::default()
+    })?;
+
+    let range = format!("bytes={offset}-");
+    let (headers, expected_status): (&[(&str, &str)], u16) = if offset == 0 {
+        (&[], 200)
+    } else {
+        (&[("Range", &range)], 206)
+    };
+    connection.initiate_request(Method::Get, url, headers)?;
+    connection.initiate_response()?;
+
+    match connection.status() {
+        status if status == expected_status => Ok(connection),
+        status => Err(anyhow::anyhow!("{url}: unexpected status {status}")),
+    }
+}
+
+fn read_to_end(connection: &mut EspHttpConnection) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        match connection.read(&mut buffer)? {
+            0 => return Ok(body),
+            read => body.extend_from_slice(&buffer[..read]),
+        }
+    }
+}
+
+/// Validates a freshly updated image, see the module documentation.
+#[derive(Clone)]
+pub struct BootValidation {
+    /// `None` if the running image is already valid.
+    self_test: Option<Arc<Mutex<SelfTest>>>,
+    rolled_back: bool,
+}
+
+impl BootValidation {
+    /// Starts the self-test if the running image is pending verification.
+    pub fn start(nvs: EspDefaultNvsPartition, checks: &[SelfTestCheck]) -> Self {
+        let rolled_back = first_boot_after_rollback(nvs);
+
+        let pending_verify = EspOta::new()
+            .and_then(|ota| ota.get_running_slot())
+            .map(|slot| slot.state == SlotState::Unverified)
+            .unwrap_or(false);
+        if !pending_verify {
+            return Self {
+                self_test: None,
+                rolled_back,
+            };
+        }
+
+        dbg_println!("[OTA       ] New image, self-test running");
+        let self_test = Arc::new(Mutex::new(SelfTest::new(Instant::now(), checks)));
+
+        let thread_self_test = Arc::clone(&self_test);
+        let validation_thread_builder = Builder::new()
+            .name("boot_validation".into())
+            .stack_size(4 * 1024);
+        let _ = validation_thread_builder.spawn(move || loop {
+            thread::sleep(SELF_TEST_POLL_INTERVAL);
+
+            let (result, missing) = {
+                let mut self_test = thread_self_test.lock().unwrap();
+                (self_test.poll(Instant::now()), self_test.missing().to_vec())
+            };
+            match result {
+                SelfTestResult::Pending => {}
+                SelfTestResult::Passed => {
+                    match EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+                        Ok(()) => dbg_println!("[OTA       ] Self-test passed, image marked valid"),
+                        Err(e) => dbg_println!("[OTA       ] Failed to mark image valid: {}", e),
+                    }
+                    break;
+                }
+                SelfTestResult::Failed => {
+                    dbg_println!(
+                        "[OTA       ] Self-test failed, missing {:?}, rolling back",
+                        missing
+                    );
+                    thread::sleep(ROLLBACK_DELAY);
+                    if let Ok(mut ota) = EspOta::new() {
+                        let e = ota.mark_running_slot_invalid_and_reboot();
+                        dbg_println!("[OTA       ] Rollback failed: {}", e);
+                    }
+                    break;
+                }
+            }
+        });
+
+        Self {
+            self_test: Some(self_test),
+            rolled_back,
+        }
+    }
+
+    pub fn pass(&self, check: SelfTestCheck) {
+        if let Some(self_test) = &self.self_test {
+            self_test.lock().unwrap().pass(check);
+        }
+    }
+
+    pub fn report(&self, node_status: &mut NodeStatus) {
+        if self.rolled_back {
+            node_status.critical(ErrorCode::OtaRolledBack);
+        }
+
+        let failed = self
+            .self_test
+            .as_ref()
+            .is_some_and(|self_test| self_test.lock().unwrap().result() == SelfTestResult::Failed);
+        if failed {
+            node_status.critical(ErrorCode::OtaSelfTestFailed);
+        }
+    }
+}
+
+/// A previous image that failed its self-test or crashed stays invalid until its slot is
+/// written again, it is only reported once.
+fn first_boot_after_rollback(nvs: EspDefaultNvsPartition) -> bool {
+    let invalid = unsafe { esp_idf_hal::sys::esp_ota_get_last_invalid_partition() };
+    if invalid.is_null() {
+        return false;
+    }
+    let address = unsafe { (*invalid).address };
+
+    let store = match EspNvs::new(nvs, config::NAMESPACE, true) {
+        Ok(store) => store,
+        Err(e) => {
+            dbg_println!("[OTA       ] Failed to open NVS: {}", e);
+            return true;
+        }
+    };
+    if store.get_u32(ROLLBACK_REPORTED_KEY).ok().flatten() == Some(address) {
+        return false;
+    }
+    if let Err(e) = store.set_u32(ROLLBACK_REPORTED_KEY, address) {
+        dbg_println!("[OTA       ] Failed to save the rollback report: {}", e);
+    }
+
+    true
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+fn header_value(connection: &EspHttpConnection, name: &str) -> anyhow::Result<u64> {
+    let value = connection
+        .header(name)
+        .ok_or_else(|| anyhow::anyhow!("missing {name} header"))?;
+
+    Ok(value.trim().parse()?)
+}
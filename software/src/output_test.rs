@@ -1,4 +1,6 @@
+use enumset::enum_set;
 use esp_idf_hal::{
+    can::{CanDriver, Flags, Frame},
     gpio::{DriveStrength, PinDriver},
     prelude::Peripherals,
 };
@@ -7,15 +9,48 @@ use std::{
     time::Duration,
 };
 
-use crate::{dbg_println, logging, EspData};
+use crate::{
+    dbg_println,
+    hal::esp::{self, can_pins, RESTART_DELAY},
+    logging,
+    ota::{BootValidation, OtaHandle},
+    protocol::{
+        filter::AcceptanceFilter, ErrorCode, NodeCommand, NodeRequest, ResetReason, UniversalFrame,
+        NODE_REQUEST_ID,
+    },
+    self_test::SelfTestCheck,
+    status::NodeStatus,
+    EspData,
+};
 
-pub fn output_test(_data: EspData, own_identifier: u32) {
+pub fn output_test(data: EspData) {
+    let own_identifier = data.config().own_identifier;
     logging::init(false);
     dbg_println!("Init Output Test at 0x{own_identifier:X}");
 
+    // No ADC is used by the output test
+    let boot_validation = BootValidation::start(
+        data.nvs(),
+        &[SelfTestCheck::CanStarted, SelfTestCheck::HeartbeatSent],
+    );
+
     let peripherals = Peripherals::take().expect("Failed to initialize peripherals");
     let pins = peripherals.pins;
 
+    // init CAN/TWAI, only used to publish the heartbeat and to receive node requests
+    let acceptance_filter = AcceptanceFilter::new(&[NODE_REQUEST_ID]);
+    let can_config = data
+        .can_config()
+        .clone()
+        .filter(acceptance_filter.filters().into());
+    let (can_tx_pin, can_rx_pin) = can_pins(data.config().can_pins);
+    let mut can_driver =
+        CanDriver::new(peripherals.can, can_tx_pin, can_rx_pin, &can_config).unwrap();
+    can_driver.start().expect("Failed to start CAN driver");
+    boot_validation.pass(SelfTestCheck::CanStarted);
+
+    let ota = OtaHandle::spawn(peripherals.modem, data.nvs(), data.config());
+
     let output_test_thread_builder = Builder::new()
         .name("output_test_thread".into())
         .stack_size(4 * 1024);
@@ -33,11 +68,49 @@ pub fn output_test(_data: EspData, own_identifier: u32) {
         // test_pin_3.set_drive_strength(DriveStrength::I40mA).unwrap();
 
         let wait = 2000;
+        let mut node_status = NodeStatus::new();
 
         loop {
+            while let Ok(frame) = can_driver.receive(0) {
+                if !acceptance_filter.subscribed(frame.identifier()) {
+                    continue;
+                }
+                match NodeRequest::decode(frame.data()) {
+                    Ok(request) if request.addresses(own_identifier) => match request.command {
+                        NodeCommand::Update => ota.request_update(),
+                        NodeCommand::Reset => {
+                            let frame_data = UniversalFrame::Resetting.encode();
+                            let frame =
+                                Frame::new(own_identifier, enum_set!(Flags::None), &frame_data)
+                                    .unwrap();
+                            let _ = can_driver.transmit(&frame, 2);
+                            thread::sleep(RESTART_DELAY);
+                            esp::restart(ResetReason::Command);
+                        }
+                    },
+                    Ok(_) => {}
+                    Err(e) => dbg_println!("[OUT/can   ] Invalid node request: {}", e),
+                }
+            }
+            ota.report(&mut node_status);
+            boot_validation.report(&mut node_status);
+
+            let frame_data = node_status.next_frame().encode();
+            let frame = Frame::new(own_identifier, enum_set!(Flags::None), &frame_data).unwrap();
+            let can_send_status = can_driver.transmit(&frame, 2).is_ok();
+            node_status.check(ErrorCode::CanTransmitFailed, can_send_status);
+            if can_send_status {
+                boot_validation.pass(SelfTestCheck::HeartbeatSent);
+            }
+            dbg_println!(
+                "[OUT/app   ] Q_out:{} | {:?}",
+                can_send_status,
+                node_status.state()
+            );
+
             println!("Low");
 
-            test_pin_0.set_low().unwrap();
+            node_status.check(ErrorCode::OutputFailed, test_pin_0.set_low().is_ok());
             // test_pin_1.set_low().unwrap();
             // test_pin_2.set_low().unwrap();
             // test_pin_3.set_low().unwrap();
@@ -46,7 +119,7 @@ pub fn output_test(_data: EspData, own_identifier: u32) {
 
             println!("High");
 
-            test_pin_0.set_high().unwrap();
+            node_status.check(ErrorCode::OutputFailed, test_pin_0.set_high().is_ok());
             // test_pin_1.set_high().unwrap();
             // test_pin_2.set_high().unwrap();
             // test_pin_3.set_high().unwrap();
@@ -0,0 +1,359 @@
+//! Tunable values of a node, read and written over CAN.
+//!
+//! Every role has a table of [`Parameter`]s. The values start from the ones saved in NVS
+//! and can be changed with [`ParameterRequest`]s on 0x101, the node answers each one
+//! with a [`ParameterResponse`] on its own identifier. Written values apply right away
+//! unless their description says otherwise, they only survive a restart once saved.
+
+use crate::{
+    config::ConfigStore,
+    dbg_println,
+    protocol::{ParameterCommand, ParameterRequest, ParameterResponse, ParameterResult},
+};
+
+#[derive(Debug)]
+pub struct Parameter {
+    /// `pp` of the request, unique within the table of a role.
+    pub number: u8,
+    /// NVS key, at most 15 characters.
+    pub key: &'static str,
+    pub default: u32,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Parameter {
+    fn accepts(&self, value: u32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// Current values of a parameter table.
+#[derive(Debug)]
+pub struct Parameters {
+    table: &'static [Parameter],
+    values: Vec<u32>,
+    /// Indices of parameters that have to stay below another one, see
+    /// [`Parameters::with_order`].
+    orders: Vec<(usize, usize)>,
+    /// Prefixes the log lines, e.g. `KBI`.
+    tag: &'static str,
+}
+
+impl Parameters {
+    /// Starts from the saved values, missing or invalid ones fall back to the default.
+    pub fn load(table: &'static [Parameter], store: &dyn ConfigStore, tag: &'static str) -> Self {
+        let values = table
+            .iter()
+            .map(|parameter| match store.get_u32(parameter.key) {
+                Ok(Some(value)) if parameter.accepts(value) => value,
+                Ok(None) => parameter.default,
+                Ok(Some(value)) => {
+                    dbg_println!(
+                        "[{}/param ] Saved {} {} out of range, using {}",
+                        tag,
+                        parameter.key,
+                        value,
+                        parameter.default
+                    );
+                    parameter.default
+                }
+                Err(e) => {
+                    dbg_println!("[{}/param ] Failed to read {}: {}", tag, parameter.key, e);
+                    parameter.default
+                }
+            })
+            .collect();
+
+        Self {
+            table,
+            values,
+            orders: Vec::new(),
+            tag,
+        }
+    }
+
+    /// Keeps `lower` below `upper`, writes that would break this are out of range. Saved
+    /// values that already break it fall back to the defaults of both.
+    pub fn with_order(mut self, lower: &Parameter, upper: &Parameter) -> Self {
+        let index = |parameter: &Parameter| {
+            self.index(parameter.number)
+                .expect("parameter of another table")
+        };
+        let (lower_index, upper_index) = (index(lower), index(upper));
+
+        if self.values[lower_index] >= self.values[upper_index] {
+            dbg_println!(
+                "[{}/param ] Saved {} {} not below {} {}, using {} and {}",
+                self.tag,
+                lower.key,
+                self.values[lower_index],
+                upper.key,
+                self.values[upper_index],
+                lower.default,
+                upper.default
+            );
+            self.values[lower_index] = lower.default;
+            self.values[upper_index] = upper.default;
+        }
+
+        self.orders.push((lower_index, upper_index));
+        self
+    }
+
+    /// `parameter` has to be an entry of the table.
+    pub fn get(&self, parameter: &Parameter) -> u32 {
+        self.index(parameter.number)
+            .map(|index| self.values[index])
+            .expect("parameter of another table")
+    }
+
+    fn index(&self, number: u8) -> Option<usize> {
+        self.table.iter().position(|p| p.number == number)
+    }
+
+    /// Whether `value` at `index` keeps every order.
+    fn in_order(&self, index: usize, value: u32) -> bool {
+        self.orders.iter().all(|&(lower, upper)| {
+            if index == lower {
+                value < self.values[upper]
+            } else if index == upper {
+                self.values[lower] < value
+            } else {
+                true
+            }
+        })
+    }
+
+    pub fn handle(
+        &mut self,
+        request: &ParameterRequest,
+        store: &mut dyn ConfigStore,
+    ) -> ParameterResponse {
+        let response = |result, value| ParameterResponse {
+            command: request.command,
+            parameter: request.parameter,
+            result,
+            value,
+        };
+
+        match request.command {
+            ParameterCommand::Read | ParameterCommand::Write => {
+                let Some(index) = self.index(request.parameter) else {
+                    return response(ParameterResult::UnknownParameter, 0);
+                };
+                if request.command == ParameterCommand::Write {
+                    if !self.table[index].accepts(request.value)
+                        || !self.in_order(index, request.value)
+                    {
+                        return response(ParameterResult::OutOfRange, self.values[index]);
+                    }
+                    self.values[index] = request.value;
+                }
+
+                response(ParameterResult::Ok, self.values[index])
+            }
+            ParameterCommand::Save => {
+                let saved = self
+                    .table
+                    .iter()
+                    .zip(&self.values)
+                    .try_for_each(|(parameter, value)| store.set_u32(parameter.key, *value));
+
+                match saved {
+                    Ok(()) => response(ParameterResult::Ok, 0),
+                    Err(e) => {
+                        dbg_println!("[{}/param ] Failed to save: {}", self.tag, e);
+                        response(ParameterResult::SaveFailed, 0)
+                    }
+                }
+            }
+            ParameterCommand::ResetDefaults => {
+                for (parameter, value) in self.table.iter().zip(&mut self.values) {
+                    *value = parameter.default;
+                }
+
+                response(ParameterResult::Ok, 0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::host::SimConfigStore;
+
+    const THRESHOLD: Parameter = Parameter {
+        number: 0x02,
+        key: "threshold",
+        default: 50,
+        min: 5,
+        max: 95,
+    };
+    const TIMEOUT: Parameter = Parameter {
+        number: 0x05,
+        key: "timeout",
+        default: 1000,
+        min: 100,
+        max: 5000,
+    };
+    const LOW: Parameter = Parameter {
+        number: 0x06,
+        key: "low",
+        default: 3,
+        min: 1,
+        max: 50,
+    };
+    const HIGH: Parameter = Parameter {
+        number: 0x07,
+        key: "high",
+        default: 14,
+        min: 1,
+        max: 100,
+    };
+    const TABLE: &[Parameter] = &[THRESHOLD, TIMEOUT, LOW, HIGH];
+
+    /// NVS that cannot be written.
+    struct ReadOnlyStore;
+
+    impl ConfigStore for ReadOnlyStore {
+        fn get_u32(&self, _key: &str) -> anyhow::Result<Option<u32>> {
+            Ok(None)
+        }
+
+        fn get_str(&self, _key: &str) -> anyhow::Result<Option<String>> {
+            Ok(None)
+        }
+
+        fn set_u32(&mut self, _key: &str, _value: u32) -> anyhow::Result<()> {
+            anyhow::bail!("read only")
+        }
+    }
+
+    fn request(command: ParameterCommand, parameter: u8, value: u32) -> ParameterRequest {
+        ParameterRequest {
+            command,
+            target: 0x310,
+            parameter,
+            value,
+        }
+    }
+
+    fn result(response: ParameterResponse) -> (ParameterResult, u32) {
+        (response.result, response.value)
+    }
+
+    #[test]
+    fn load_falls_back_to_the_defaults() {
+        let mut store = SimConfigStore::default();
+        store.set_u32("threshold", 96).unwrap();
+        store.set_u32("timeout", 2000).unwrap();
+
+        let parameters = Parameters::load(TABLE, &store, "TST");
+        assert_eq!(parameters.get(&THRESHOLD), 50);
+        assert_eq!(parameters.get(&TIMEOUT), 2000);
+    }
+
+    #[test]
+    fn read_and_write() {
+        let mut store = SimConfigStore::default();
+        let mut parameters = Parameters::load(TABLE, &store, "TST");
+
+        let response = parameters.handle(&request(ParameterCommand::Read, 0x02, 0), &mut store);
+        assert_eq!(response.command, ParameterCommand::Read);
+        assert_eq!(response.parameter, 0x02);
+        assert_eq!(result(response), (ParameterResult::Ok, 50));
+
+        let write = |value| request(ParameterCommand::Write, 0x02, value);
+        for value in [5, 95] {
+            let response = parameters.handle(&write(value), &mut store);
+            assert_eq!(result(response), (ParameterResult::Ok, value));
+            assert_eq!(parameters.get(&THRESHOLD), value);
+        }
+
+        // Rejected values keep the current one.
+        for value in [4, 96] {
+            let response = parameters.handle(&write(value), &mut store);
+            assert_eq!(result(response), (ParameterResult::OutOfRange, 95));
+        }
+        assert_eq!(parameters.get(&THRESHOLD), 95);
+        // Nothing is saved without a save request.
+        assert_eq!(store.get_u32("threshold").unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_parameter() {
+        let mut store = SimConfigStore::default();
+        let mut parameters = Parameters::load(TABLE, &store, "TST");
+
+        for command in [ParameterCommand::Read, ParameterCommand::Write] {
+            let response = parameters.handle(&request(command, 0x03, 10), &mut store);
+            assert_eq!(result(response), (ParameterResult::UnknownParameter, 0));
+        }
+    }
+
+    #[test]
+    fn save_and_reset_defaults() {
+        let mut store = SimConfigStore::default();
+        let mut parameters = Parameters::load(TABLE, &store, "TST");
+        parameters.handle(&request(ParameterCommand::Write, 0x05, 300), &mut store);
+
+        let response = parameters.handle(&request(ParameterCommand::Save, 0, 0), &mut store);
+        assert_eq!(result(response), (ParameterResult::Ok, 0));
+        assert_eq!(store.get_u32("threshold").unwrap(), Some(50));
+        assert_eq!(store.get_u32("timeout").unwrap(), Some(300));
+
+        let response =
+            parameters.handle(&request(ParameterCommand::ResetDefaults, 0, 0), &mut store);
+        assert_eq!(result(response), (ParameterResult::Ok, 0));
+        assert_eq!(parameters.get(&TIMEOUT), 1000);
+        // The saved values stay until the next save.
+        assert_eq!(store.get_u32("timeout").unwrap(), Some(300));
+        assert_eq!(Parameters::load(TABLE, &store, "TST").get(&TIMEOUT), 300);
+    }
+
+    #[test]
+    fn save_failed() {
+        let mut parameters = Parameters::load(TABLE, &ReadOnlyStore, "TST");
+
+        let response =
+            parameters.handle(&request(ParameterCommand::Save, 0, 0), &mut ReadOnlyStore);
+        assert_eq!(result(response), (ParameterResult::SaveFailed, 0));
+    }
+
+    #[test]
+    fn order_is_kept() {
+        let mut store = SimConfigStore::default();
+        let mut parameters = Parameters::load(TABLE, &store, "TST").with_order(&LOW, &HIGH);
+        let mut write = |parameter: &Parameter, value| {
+            let request = request(ParameterCommand::Write, parameter.number, value);
+            result(parameters.handle(&request, &mut store))
+        };
+
+        assert_eq!(write(&LOW, 14), (ParameterResult::OutOfRange, 3));
+        assert_eq!(write(&HIGH, 3), (ParameterResult::OutOfRange, 14));
+        assert_eq!(write(&LOW, 13), (ParameterResult::Ok, 13));
+        assert_eq!(write(&HIGH, 13), (ParameterResult::OutOfRange, 14));
+        assert_eq!(write(&HIGH, 40), (ParameterResult::Ok, 40));
+        assert_eq!(write(&LOW, 39), (ParameterResult::Ok, 39));
+    }
+
+    #[test]
+    fn saved_values_out_of_order_fall_back() {
+        let mut store = SimConfigStore::default();
+        store.set_u32("low", 20).unwrap();
+        store.set_u32("high", 20).unwrap();
+        store.set_u32("threshold", 60).unwrap();
+
+        let parameters = Parameters::load(TABLE, &store, "TST").with_order(&LOW, &HIGH);
+        assert_eq!(parameters.get(&LOW), 3);
+        assert_eq!(parameters.get(&HIGH), 14);
+        assert_eq!(parameters.get(&THRESHOLD), 60);
+
+        store.set_u32("high", 21).unwrap();
+        let parameters = Parameters::load(TABLE, &store, "TST").with_order(&LOW, &HIGH);
+        assert_eq!(parameters.get(&LOW), 20);
+        assert_eq!(parameters.get(&HIGH), 21);
+    }
+}
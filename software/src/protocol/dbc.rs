@@ -0,0 +1,434 @@
+//! The espio CAN matrix as DBC file, for SavvyCAN, cantools and friends.
+//!
+//! The signals below describe the layouts encoded in [`super`] and decoded in
+//! [`super::oem`]; when a layout changes, change it here as well and regenerate
+//! `espio.dbc` with `espio dbc`.
+
+use std::fmt::Write;
+
+use super::{
+    diagnostic_id,
+    oem::{GRA_NEU_ID, MOTOR_1_ID, MOTOR_2_ID, MOTOR_3_ID, MOTOR_5_ID, MOTOR_6_ID, MOTOR_7_ID},
+    CanErrors, CanTraffic, ErrorCode, NodeCommand, ParameterCommand, ParameterResult, ResetReason,
+    WheelSpeeds, ENGINE_BAY_UNIT_ID, ERROR_STATE_CRITICAL, ERROR_STATE_WARNING, KOMBIINSTRUMENT_ID,
+    NODE_REQUEST_ID, OUTPUT_TEST_ID, PARAMETER_REQUEST_ID, STATUS_ERROR, STATUS_ONLINE,
+    STATUS_PARAMETER, STATUS_RESET, STATUS_UPDATE, WHEEL_SPEEDS_ID,
+};
+
+/// Transmitter of frames that do not come from one of our nodes, e.g. the update tool.
+const NO_NODE: &str = "Vector__XXX";
+
+/// Transmitter of the vehicle's engine broadcasts, not one of our nodes either.
+const ENGINE_ECU: &str = "Motor";
+
+const NODES: [&str; 3] = ["engine_bay_unit", "kombiinstrument", "output_test"];
+
+/// The nodes with a parameter table, see [`crate::parameters`].
+const PARAMETER_NODES: [&str; 2] = ["engine_bay_unit", "kombiinstrument"];
+
+#[derive(Clone, Copy)]
+enum Multiplex {
+    None,
+    Multiplexor,
+    Value(u8),
+}
+
+struct Signal {
+    name: &'static str,
+    multiplex: Multiplex,
+    /// Most significant bit, in the sawtooth numbering of big endian DBC signals, or the
+    /// least significant bit of little endian ones.
+    start_bit: u8,
+    length: u8,
+    little_endian: bool,
+    factor: f32,
+    offset: f32,
+    unit: &'static str,
+    receivers: &'static [&'static str],
+    values: Vec<(u8, String)>,
+}
+
+impl Signal {
+    fn new(
+        name: &'static str,
+        start_bit: u8,
+        length: u8,
+        receivers: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            multiplex: Multiplex::None,
+            start_bit,
+            length,
+            little_endian: false,
+            factor: 1.0,
+            offset: 0.0,
+            unit: "",
+            receivers,
+            values: Vec::new(),
+        }
+    }
+
+    fn multiplex(mut self, multiplex: Multiplex) -> Self {
+        self.multiplex = multiplex;
+        self
+    }
+
+    /// Our own frames are big endian, the vehicle's are not.
+    fn little_endian(mut self) -> Self {
+        self.little_endian = true;
+        self
+    }
+
+    /// Physical value = raw * factor + offset.
+    fn scale(mut self, factor: f32, offset: f32) -> Self {
+        self.factor = factor;
+        self.offset = offset;
+        self
+    }
+
+    fn unit(mut self, unit: &'static str) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    fn values(mut self, values: Vec<(u8, String)>) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// 32 bit raw values do not fit into an f32 exactly.
+    fn max(&self) -> String {
+        let raw = (1u64 << self.length) - 1;
+        if self.factor == 1.0 && self.offset == 0.0 {
+            raw.to_string()
+        } else {
+            (raw as f32 * self.factor + self.offset).to_string()
+        }
+    }
+}
+
+struct Message {
+    identifier: u32,
+    name: &'static str,
+    transmitter: &'static str,
+    signals: Vec<Signal>,
+}
+
+fn parameter_commands() -> Vec<(u8, String)> {
+    ParameterCommand::ALL
+        .iter()
+        .map(|command| (*command as u8, format!("{command:?}")))
+        .collect()
+}
+
+fn reset_reasons() -> Vec<(u8, String)> {
+    ResetReason::ALL
+        .iter()
+        .map(|reason| (*reason as u8, format!("{reason:?}")))
+        .collect()
+}
+
+/// Byte 0 of every node frame, see [`super::UniversalFrame`].
+fn universal_signals(receivers: &'static [&'static str]) -> Vec<Signal> {
+    let error_codes: Vec<(u8, String)> = ErrorCode::ALL
+        .iter()
+        .map(|code| (code.number(), format!("{code:?}")))
+        .collect();
+    let parameter = Multiplex::Value(STATUS_PARAMETER);
+
+    vec![
+        Signal::new("Status", 7, 8, receivers)
+            .multiplex(Multiplex::Multiplexor)
+            .values(vec![
+                (STATUS_ONLINE, "Online".into()),
+                (STATUS_UPDATE, "Updating".into()),
+                (STATUS_PARAMETER, "Parameter".into()),
+                (STATUS_RESET, "Resetting".into()),
+                (STATUS_ERROR | ERROR_STATE_WARNING, "Warning".into()),
+                (STATUS_ERROR | ERROR_STATE_CRITICAL, "Critical".into()),
+            ]),
+        Signal::new("UpdateProgress", 15, 8, receivers).multiplex(Multiplex::Value(STATUS_UPDATE)),
+        Signal::new("ParameterCommand", 15, 8, receivers)
+            .multiplex(parameter)
+            .values(parameter_commands()),
+        Signal::new("ParameterNumber", 23, 8, receivers).multiplex(parameter),
+        Signal::new("ParameterResult", 31, 8, receivers)
+            .multiplex(parameter)
+            .values(
+                ParameterResult::ALL
+                    .iter()
+                    .map(|result| (*result as u8, format!("{result:?}")))
+                    .collect(),
+            ),
+        Signal::new("ParameterValue", 39, 32, receivers).multiplex(parameter),
+        Signal::new("Warning", 15, 8, receivers)
+            .multiplex(Multiplex::Value(STATUS_ERROR | ERROR_STATE_WARNING))
+            .values(error_codes.clone()),
+        Signal::new("Critical", 15, 8, receivers)
+            .multiplex(Multiplex::Value(STATUS_ERROR | ERROR_STATE_CRITICAL))
+            .values(error_codes),
+    ]
+}
+
+/// Both pages of the diagnostic frame, see [`super::CanTraffic`] and [`super::CanErrors`].
+fn diagnostic_signals() -> Vec<Signal> {
+    let traffic = Multiplex::Value(CanTraffic::PAGE);
+    let errors = Multiplex::Value(CanErrors::PAGE);
+
+    vec![
+        Signal::new("Page", 7, 8, &[NO_NODE])
+            .multiplex(Multiplex::Multiplexor)
+            .values(vec![
+                (CanTraffic::PAGE, "Traffic".into()),
+                (CanErrors::PAGE, "Errors".into()),
+            ]),
+        Signal::new("RxFrames", 15, 16, &[NO_NODE]).multiplex(traffic),
+        Signal::new("TxFrames", 31, 16, &[NO_NODE]).multiplex(traffic),
+        Signal::new("BusLoad", 47, 8, &[NO_NODE])
+            .multiplex(traffic)
+            .unit("%"),
+        Signal::new("RxDropped", 55, 8, &[NO_NODE]).multiplex(traffic),
+        Signal::new("RxMissed", 63, 8, &[NO_NODE]).multiplex(traffic),
+        Signal::new("TxFailed", 15, 8, &[NO_NODE]).multiplex(errors),
+        Signal::new("TxRetried", 23, 8, &[NO_NODE]).multiplex(errors),
+        Signal::new("BusErrors", 31, 8, &[NO_NODE]).multiplex(errors),
+        Signal::new("ArbitrationLost", 39, 8, &[NO_NODE]).multiplex(errors),
+        Signal::new("TecPeak", 47, 8, &[NO_NODE]).multiplex(errors),
+        Signal::new("RecPeak", 55, 8, &[NO_NODE]).multiplex(errors),
+    ]
+}
+
+fn messages() -> Vec<Message> {
+    let online = Multiplex::Value(STATUS_ONLINE);
+
+    let mut engine_bay_unit = universal_signals(&["kombiinstrument"]);
+    engine_bay_unit.extend([
+        Signal::new("ResetReason", 23, 8, &["kombiinstrument"])
+            .multiplex(online)
+            .values(reset_reasons()),
+        Signal::new("TaskCycleTime", 55, 8, &["kombiinstrument"])
+            .multiplex(online)
+            .unit("%"),
+    ]);
+
+    let mut kombiinstrument = universal_signals(&["engine_bay_unit"]);
+    kombiinstrument.extend([
+        Signal::new("BrakePedalActive0", 15, 1, &["engine_bay_unit"]).multiplex(online),
+        Signal::new("BrakePedalActive1", 14, 1, &["engine_bay_unit"]).multiplex(online),
+        Signal::new("ResetReason", 23, 8, &["engine_bay_unit"])
+            .multiplex(online)
+            .values(reset_reasons()),
+        Signal::new("TaskCycleTime", 63, 8, &["engine_bay_unit"])
+            .multiplex(online)
+            .unit("%"),
+    ]);
+
+    vec![
+        Message {
+            identifier: NODE_REQUEST_ID,
+            name: "NodeRequest",
+            transmitter: NO_NODE,
+            signals: vec![
+                Signal::new("Command", 7, 8, &NODES).values(
+                    NodeCommand::ALL
+                        .iter()
+                        .map(|command| (*command as u8, format!("{command:?}")))
+                        .collect(),
+                ),
+                Signal::new("Target", 15, 16, &NODES),
+            ],
+        },
+        Message {
+            identifier: PARAMETER_REQUEST_ID,
+            name: "ParameterRequest",
+            transmitter: NO_NODE,
+            signals: vec![
+                Signal::new("Command", 7, 8, &PARAMETER_NODES).values(parameter_commands()),
+                Signal::new("Target", 15, 16, &PARAMETER_NODES),
+                Signal::new("Parameter", 31, 8, &PARAMETER_NODES),
+                Signal::new("Value", 39, 32, &PARAMETER_NODES),
+            ],
+        },
+        Message {
+            identifier: ENGINE_BAY_UNIT_ID,
+            name: "EngineBayUnit",
+            transmitter: "engine_bay_unit",
+            signals: engine_bay_unit,
+        },
+        Message {
+            identifier: WHEEL_SPEEDS_ID,
+            name: "WheelSpeeds",
+            transmitter: "engine_bay_unit",
+            signals: [
+                "WheelSpeedFL",
+                "WheelSpeedFR",
+                "WheelSpeedRL",
+                "WheelSpeedRR",
+            ]
+            .into_iter()
+            .zip([7, 23, 39, 55])
+            .map(|(name, start_bit)| {
+                Signal::new(name, start_bit, 16, &["kombiinstrument"])
+                    .scale(WheelSpeeds::RESOLUTION_HZ, 0.0)
+                    .unit("Hz")
+            })
+            .collect(),
+        },
+        Message {
+            identifier: KOMBIINSTRUMENT_ID,
+            name: "Kombiinstrument",
+            transmitter: "kombiinstrument",
+            signals: kombiinstrument,
+        },
+        Message {
+            identifier: MOTOR_1_ID,
+            name: "Motor_1",
+            transmitter: ENGINE_ECU,
+            signals: vec![
+                Signal::new("EngineSpeed", 16, 16, &["kombiinstrument"])
+                    .little_endian()
+                    .scale(0.25, 0.0)
+                    .unit("rpm"),
+                Signal::new("AcceleratorPedal", 40, 8, &[NO_NODE])
+                    .little_endian()
+                    .scale(0.4, 0.0)
+                    .unit("%"),
+            ],
+        },
+        Message {
+            identifier: MOTOR_2_ID,
+            name: "Motor_2",
+            transmitter: ENGINE_ECU,
+            signals: vec![Signal::new("CoolantTemp", 8, 8, &["kombiinstrument"])
+                .little_endian()
+                .scale(0.75, -48.0)
+                .unit("degC")],
+        },
+        Message {
+            identifier: MOTOR_3_ID,
+            name: "Motor_3",
+            transmitter: ENGINE_ECU,
+            signals: vec![
+                Signal::new("IntakeAirTemp", 8, 8, &[NO_NODE])
+                    .little_endian()
+                    .scale(0.75, -48.0)
+                    .unit("degC"),
+                Signal::new("ThrottleValve", 56, 8, &["kombiinstrument"])
+                    .little_endian()
+                    .scale(0.4, 0.0)
+                    .unit("%"),
+            ],
+        },
+        Message {
+            identifier: GRA_NEU_ID,
+            name: "GRA_Neu",
+            transmitter: ENGINE_ECU,
+            signals: vec![
+                Signal::new("MainSwitch", 8, 1, &["kombiinstrument"]).little_endian(),
+                Signal::new("Cancel", 9, 1, &["kombiinstrument"]).little_endian(),
+            ],
+        },
+        Message {
+            identifier: MOTOR_5_ID,
+            name: "Motor_5",
+            transmitter: ENGINE_ECU,
+            signals: vec![Signal::new("FuelConsumption", 16, 15, &["kombiinstrument"])
+                .little_endian()
+                .unit("ul")],
+        },
+        Message {
+            identifier: MOTOR_6_ID,
+            name: "Motor_6",
+            transmitter: ENGINE_ECU,
+            signals: vec![Signal::new("EngineTorque", 16, 8, &["kombiinstrument"])
+                .little_endian()
+                .scale(0.39, 0.0)
+                .unit("%")],
+        },
+        Message {
+            identifier: MOTOR_7_ID,
+            name: "Motor_7",
+            transmitter: ENGINE_ECU,
+            signals: vec![Signal::new("OilTemp", 8, 8, &["kombiinstrument"])
+                .little_endian()
+                .scale(1.0, -60.0)
+                .unit("degC")],
+        },
+        Message {
+            identifier: diagnostic_id(ENGINE_BAY_UNIT_ID),
+            name: "EngineBayUnitDiagnostics",
+            transmitter: "engine_bay_unit",
+            signals: diagnostic_signals(),
+        },
+        Message {
+            identifier: diagnostic_id(KOMBIINSTRUMENT_ID),
+            name: "KombiinstrumentDiagnostics",
+            transmitter: "kombiinstrument",
+            signals: diagnostic_signals(),
+        },
+        Message {
+            identifier: OUTPUT_TEST_ID,
+            name: "OutputTest",
+            transmitter: "output_test",
+            signals: universal_signals(&[NO_NODE]),
+        },
+    ]
+}
+
+pub fn dbc() -> String {
+    let messages = messages();
+    let mut dbc = String::new();
+
+    // Writing to a String does not fail.
+    let _ = writeln!(dbc, "VERSION \"\"\n\nNS_ :\n\nBS_:\n");
+    let _ = writeln!(dbc, "BU_: {} {ENGINE_ECU}\n", NODES.join(" "));
+
+    for message in &messages {
+        let _ = writeln!(
+            dbc,
+            "BO_ {} {}: 8 {}",
+            message.identifier, message.name, message.transmitter
+        );
+        for signal in &message.signals {
+            let multiplex = match signal.multiplex {
+                Multiplex::None => String::new(),
+                Multiplex::Multiplexor => " M".into(),
+                Multiplex::Value(value) => format!(" m{value}"),
+            };
+            let _ = writeln!(
+                dbc,
+                " SG_ {}{} : {}|{}@{}+ ({},{}) [{}|{}] \"{}\" {}",
+                signal.name,
+                multiplex,
+                signal.start_bit,
+                signal.length,
+                if signal.little_endian { 1 } else { 0 },
+                signal.factor,
+                signal.offset,
+                signal.offset,
+                signal.max(),
+                signal.unit,
+                signal.receivers.join(",")
+            );
+        }
+        let _ = writeln!(dbc);
+    }
+
+    for message in &messages {
+        for signal in message.signals.iter().filter(|s| !s.values.is_empty()) {
+            let values: String = signal
+                .values
+                .iter()
+                .map(|(value, name)| format!(" {value} \"{name}\""))
+                .collect();
+            let _ = writeln!(
+                dbc,
+                "VAL_ {} {}{} ;",
+                message.identifier, signal.name, values
+            );
+        }
+    }
+
+    dbc
+}
@@ -0,0 +1,236 @@
+//! TWAI acceptance filters computed from the identifiers a node subscribes to.
+//!
+//! The hardware filter only knows code and mask bits, so it usually lets a few more
+//! identifiers through than asked for. The can_receivers therefore check
+//! [`AcceptanceFilter::subscribed`] as well; the hardware filter only keeps the RX queue
+//! free of frames nobody is interested in.
+
+/// All 11 identifier bits.
+const STANDARD_MASK: u16 = 0x7ff;
+
+/// Identifiers that are distributed over both filters of the dual mode, see
+/// [`AcceptanceFilter::new`].
+const MAX_SPLIT_IDS: usize = 12;
+
+/// One code/mask pair. Mask bits set to 1 must match `filter`, bits set to 0 are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdFilter {
+    pub filter: u16,
+    pub mask: u16,
+}
+
+impl IdFilter {
+    /// The tightest filter that accepts all `identifiers`.
+    ///
+    /// Without identifiers, only 0x000 is accepted.
+    fn covering(identifiers: &[u16]) -> Self {
+        let Some(&first) = identifiers.first() else {
+            return Self {
+                filter: 0,
+                mask: STANDARD_MASK,
+            };
+        };
+
+        let differing = identifiers.iter().fold(0, |bits, &id| bits | (id ^ first));
+        let mask = STANDARD_MASK & !differing;
+
+        Self {
+            filter: first & mask,
+            mask,
+        }
+    }
+
+    /// Number of identifiers that pass, every ignored bit doubles it.
+    fn accepted_count(&self) -> usize {
+        1 << (11 - self.mask.count_ones())
+    }
+
+    /// Number of identifiers both filters let through.
+    fn overlap(&self, other: &Self) -> usize {
+        if (self.filter ^ other.filter) & self.mask & other.mask != 0 {
+            return 0;
+        }
+
+        1 << (11 - (self.mask | other.mask).count_ones())
+    }
+}
+
+/// The TWAI either runs one filter over the whole identifier or two independent ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filters {
+    Single(IdFilter),
+    Dual(IdFilter, IdFilter),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptanceFilter {
+    subscribed: Vec<u32>,
+    filters: Filters,
+}
+
+impl AcceptanceFilter {
+    /// Picks the single or dual filter that lets the fewest identifiers through. Only
+    /// standard (11 bit) identifiers are supported, higher bits are ignored.
+    pub fn new(subscribed: &[u32]) -> Self {
+        let mut subscribed: Vec<u32> = subscribed
+            .iter()
+            .map(|&id| id & STANDARD_MASK as u32)
+            .collect();
+        subscribed.sort_unstable();
+        subscribed.dedup();
+        let identifiers: Vec<u16> = subscribed.iter().map(|&id| id as u16).collect();
+
+        let mut filters = Filters::Single(IdFilter::covering(&identifiers));
+        let mut count = accepted_count(&filters);
+
+        // Every split into two groups, the first identifier always stays in the first
+        // group so that no split is tried twice. A node subscribes to a handful of
+        // identifiers, beyond MAX_SPLIT_IDS the rest always goes to the first group.
+        let splits = 1u32 << (identifiers.len().saturating_sub(1)).min(MAX_SPLIT_IDS);
+        for split in 1..splits {
+            let (mut first, mut second) = (Vec::new(), Vec::new());
+            for (i, &id) in identifiers.iter().enumerate() {
+                if i > 0 && i <= MAX_SPLIT_IDS && split & (1 << (i - 1)) != 0 {
+                    second.push(id);
+                } else {
+                    first.push(id);
+                }
+            }
+
+            let candidate = Filters::Dual(IdFilter::covering(&first), IdFilter::covering(&second));
+            // The single filter wins a tie.
+            if accepted_count(&candidate) < count {
+                filters = candidate;
+                count = accepted_count(&candidate);
+            }
+        }
+
+        Self {
+            subscribed,
+            filters,
+        }
+    }
+
+    pub fn filters(&self) -> Filters {
+        self.filters
+    }
+
+    /// The software check behind the hardware filter.
+    pub fn subscribed(&self, identifier: u32) -> bool {
+        self.subscribed.contains(&identifier)
+    }
+
+    /// Identifiers the hardware filter lets through although nobody subscribed to them.
+    pub fn false_positives(&self) -> usize {
+        accepted_count(&self.filters) - self.subscribed.len()
+    }
+}
+
+fn accepted_count(filters: &Filters) -> usize {
+    match filters {
+        Filters::Single(filter) => filter.accepted_count(),
+        Filters::Dual(first, second) => {
+            first.accepted_count() + second.accepted_count() - first.overlap(second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The acceptance check of the TWAI, with code and mask set up the way esp-idf-hal
+    /// does it. In the registers a mask bit set to 1 means "don't care".
+    fn hardware_accepts(filters: Filters, identifier: u32) -> bool {
+        let register = |filter: &IdFilter, shift: u32| {
+            let code = (filter.filter as u32) << shift;
+            let mask = !((filter.mask as u32) << shift);
+            let bits = (STANDARD_MASK as u32) << shift;
+            ((identifier << shift) ^ code) & !mask & bits == 0
+        };
+
+        match filters {
+            Filters::Single(filter) => register(&filter, 21),
+            Filters::Dual(first, second) => register(&first, 21) || register(&second, 5),
+        }
+    }
+
+    fn accepted(filter: &AcceptanceFilter) -> Vec<u32> {
+        (0..=STANDARD_MASK as u32)
+            .filter(|&id| hardware_accepts(filter.filters(), id))
+            .collect()
+    }
+
+    #[test]
+    fn single_filter_for_close_identifiers() {
+        let filter = AcceptanceFilter::new(&[0x288, 0x280]);
+
+        assert_eq!(
+            filter.filters(),
+            Filters::Single(IdFilter {
+                filter: 0x280,
+                mask: 0x7f7,
+            })
+        );
+        assert_eq!(accepted(&filter), [0x280, 0x288]);
+        assert_eq!(filter.false_positives(), 0);
+    }
+
+    #[test]
+    fn dual_filter_for_distant_identifiers() {
+        let filter = AcceptanceFilter::new(&[0x610, 0x280, 0x288]);
+
+        assert_eq!(
+            filter.filters(),
+            Filters::Dual(
+                IdFilter {
+                    filter: 0x280,
+                    mask: 0x7f7,
+                },
+                IdFilter {
+                    filter: 0x610,
+                    mask: 0x7ff,
+                }
+            )
+        );
+        assert_eq!(accepted(&filter), [0x280, 0x288, 0x610]);
+    }
+
+    #[test]
+    fn false_positives_are_rejected_in_software() {
+        let subscribed = [0x280, 0x288, 0x380, 0x310];
+        let filter = AcceptanceFilter::new(&subscribed);
+        let accepted = accepted(&filter);
+
+        assert!(filter.false_positives() > 0);
+        assert_eq!(accepted.len(), subscribed.len() + filter.false_positives());
+        for id in accepted {
+            assert_eq!(filter.subscribed(id), subscribed.contains(&id), "{id:#x}");
+        }
+    }
+
+    #[test]
+    fn more_identifiers_than_splits() {
+        // The last identifiers are beyond MAX_SPLIT_IDS and always stay in the first filter.
+        let subscribed: Vec<u32> = (0x100..0x100 + MAX_SPLIT_IDS as u32 + 4)
+            .chain([0x700, 0x7ff])
+            .collect();
+        let filter = AcceptanceFilter::new(&subscribed);
+        let accepted = accepted(&filter);
+
+        for id in &subscribed {
+            assert!(accepted.contains(id), "{id:#x}");
+            assert!(filter.subscribed(*id));
+        }
+        assert_eq!(accepted.len(), subscribed.len() + filter.false_positives());
+    }
+
+    #[test]
+    fn only_standard_identifiers() {
+        let filter = AcceptanceFilter::new(&[0x280, 0x280 | 0x1000]);
+
+        assert!(filter.subscribed(0x280));
+        assert!(!filter.subscribed(0x1280));
+        assert_eq!(filter.false_positives(), 0);
+    }
+}
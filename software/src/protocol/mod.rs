@@ -0,0 +1,952 @@
+//! Hardware-independent CAN message layer.
+//!
+//! Everything in here is plain Rust without any esp-idf dependency, so the frame
+//! layouts from `readme.md` live in exactly one place and can be built and checked
+//! on the host. Every frame has both its encoder and its decoder, even where only one
+//! side of it runs on a node, e.g. for the frames a node only sends.
+#![allow(dead_code)]
+
+use std::fmt;
+
+pub mod dbc;
+pub mod filter;
+pub mod oem;
+
+// --- CAN identifiers ---
+pub const NODE_REQUEST_ID: u32 = 0x100;
+pub const PARAMETER_REQUEST_ID: u32 = 0x101;
+pub const ENGINE_BAY_UNIT_ID: u32 = 0x210;
+pub const WHEEL_SPEEDS_ID: u32 = 0x222;
+pub const KOMBIINSTRUMENT_ID: u32 = 0x310;
+pub const OUTPUT_TEST_ID: u32 = 0x776;
+pub const DEV_CAN_SENDER_ID: u32 = 0x777;
+
+/// Every frame of this protocol is sent with the full 8 data bytes.
+pub const FRAME_LEN: usize = 8;
+
+// --- Universal status byte (byte 0 of every node frame) ---
+const STATUS_ONLINE: u8 = 0x11;
+const STATUS_UPDATE: u8 = 0x02;
+const STATUS_PARAMETER: u8 = 0x03;
+const STATUS_RESET: u8 = 0x04;
+const STATUS_ERROR: u8 = 0xf0;
+const ERROR_STATE_WARNING: u8 = 0x0;
+const ERROR_STATE_CRITICAL: u8 = 0xf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The frame carries fewer data bytes than the layout requires.
+    InvalidLength { expected: usize, actual: usize },
+    /// Byte 0 holds a universal status the decoder does not handle.
+    UnexpectedStatus(u8),
+    /// A field holds a value that is not defined by the layout.
+    InvalidValue(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidLength { expected, actual } => {
+                write!(f, "invalid frame length: expected {expected}, got {actual}")
+            }
+            DecodeError::UnexpectedStatus(status) => {
+                write!(f, "unexpected status byte 0x{status:02X}")
+            }
+            DecodeError::InvalidValue(value) => write!(f, "invalid value 0x{value:02X}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn check_len(data: &[u8]) -> Result<(), DecodeError> {
+    if data.len() < FRAME_LEN {
+        return Err(DecodeError::InvalidLength {
+            expected: FRAME_LEN,
+            actual: data.len(),
+        });
+    }
+
+    Ok(())
+}
+
+fn check_online(data: &[u8]) -> Result<(), DecodeError> {
+    check_len(data)?;
+    match data[0] {
+        STATUS_ONLINE => Ok(()),
+        status => Err(DecodeError::UnexpectedStatus(status)),
+    }
+}
+
+/// The universal payloads every node can send on its own identifier.
+///
+/// - `[11]` ecu online
+/// - `[02 xx]` confirm update, xx = progress 0-255
+/// - `[03 cc pp rr vv vv vv vv]` parameter acknowledgement, see [`ParameterResponse`]
+/// - `[04]` confirm reset, the node restarts right after it
+/// - `[fy xx]` error, y = 0 warning / f critical, xx = error number
+///
+/// For [`UniversalFrame::Online`] the remaining bytes carry the node specific data,
+/// see [`KombiinstrumentStatus`] and [`EngineBayStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniversalFrame {
+    Online,
+    UpdateProgress(u8),
+    Parameter(ParameterResponse),
+    Resetting,
+    Warning(u8),
+    Critical(u8),
+}
+
+impl UniversalFrame {
+    pub fn encode(&self) -> [u8; 8] {
+        let mut data = [0; 8];
+        match *self {
+            UniversalFrame::Online => data[0] = STATUS_ONLINE,
+            UniversalFrame::UpdateProgress(progress) => {
+                data[0] = STATUS_UPDATE;
+                data[1] = progress;
+            }
+            UniversalFrame::Parameter(response) => data = response.encode(),
+            UniversalFrame::Resetting => data[0] = STATUS_RESET,
+            UniversalFrame::Warning(error_number) => {
+                data[0] = STATUS_ERROR | ERROR_STATE_WARNING;
+                data[1] = error_number;
+            }
+            UniversalFrame::Critical(error_number) => {
+                data[0] = STATUS_ERROR | ERROR_STATE_CRITICAL;
+                data[1] = error_number;
+            }
+        }
+
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+        match data[0] {
+            STATUS_ONLINE => Ok(UniversalFrame::Online),
+            STATUS_UPDATE => Ok(UniversalFrame::UpdateProgress(data[1])),
+            STATUS_PARAMETER => Ok(UniversalFrame::Parameter(ParameterResponse::decode(data)?)),
+            STATUS_RESET => Ok(UniversalFrame::Resetting),
+            status if status & 0xf0 == STATUS_ERROR => match status & 0x0f {
+                ERROR_STATE_WARNING => Ok(UniversalFrame::Warning(data[1])),
+                ERROR_STATE_CRITICAL => Ok(UniversalFrame::Critical(data[1])),
+                _ => Err(DecodeError::InvalidValue(status)),
+            },
+            status => Err(DecodeError::UnexpectedStatus(status)),
+        }
+    }
+}
+
+/// Error numbers sent as `xx` in the `[fy xx]` universal frame.
+///
+/// The numbers are shared by all nodes, so the dashboard can look them up without
+/// knowing which role sent them. Never reuse a number for a different meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+    CanTransmitFailed = 0x01,
+    AdcReadFailed = 0x02,
+    PulseCounterFailed = 0x03,
+    OutputFailed = 0x04,
+    CanErrorCounters = 0x05,
+    CanBusOff = 0x06,
+    WheelSpeedsTimeout = 0x10,
+    KombiinstrumentTimeout = 0x11,
+    EngineDataTimeout = 0x12,
+    OtaWifiFailed = 0x20,
+    OtaDownloadFailed = 0x21,
+    OtaChecksumMismatch = 0x22,
+    OtaWriteFailed = 0x23,
+    OtaSelfTestFailed = 0x24,
+    OtaRolledBack = 0x25,
+    OtaSignatureInvalid = 0x26,
+    OilPressureLow = 0x30,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 17] = [
+        ErrorCode::CanTransmitFailed,
+        ErrorCode::AdcReadFailed,
+        ErrorCode::PulseCounterFailed,
+        ErrorCode::OutputFailed,
+        ErrorCode::CanErrorCounters,
+        ErrorCode::CanBusOff,
+        ErrorCode::WheelSpeedsTimeout,
+        ErrorCode::KombiinstrumentTimeout,
+        ErrorCode::EngineDataTimeout,
+        ErrorCode::OtaWifiFailed,
+        ErrorCode::OtaDownloadFailed,
+        ErrorCode::OtaChecksumMismatch,
+        ErrorCode::OtaWriteFailed,
+        ErrorCode::OtaSelfTestFailed,
+        ErrorCode::OtaRolledBack,
+        ErrorCode::OtaSignatureInvalid,
+        ErrorCode::OilPressureLow,
+    ];
+
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NodeCommand {
+    /// Asks the ota_server for a newer image, see `ota.rs`.
+    Update = 0x01,
+    /// Confirms with [`UniversalFrame::Resetting`] and restarts.
+    Reset = 0x02,
+}
+
+impl NodeCommand {
+    pub const ALL: [NodeCommand; 2] = [NodeCommand::Update, NodeCommand::Reset];
+
+    fn decode(value: u8) -> Result<Self, DecodeError> {
+        Self::ALL
+            .into_iter()
+            .find(|command| *command as u8 == value)
+            .ok_or(DecodeError::InvalidValue(value))
+    }
+}
+
+/// 0x100: addressed node request, `[cc tt tt 00 00 00 00 00]`.
+///
+/// cc is the [`NodeCommand`], tt tt the big endian identifier of the node or
+/// [`NodeRequest::ALL_NODES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeRequest {
+    pub command: NodeCommand,
+    pub target: u32,
+}
+
+impl NodeRequest {
+    /// Not a standard identifier, so no single node can be meant.
+    pub const ALL_NODES: u32 = 0xffff;
+
+    pub fn addresses(&self, own_identifier: u32) -> bool {
+        self.target == own_identifier || self.target == Self::ALL_NODES
+    }
+
+    pub fn encode(&self) -> [u8; 8] {
+        let [target_h, target_l] = (self.target as u16).to_be_bytes();
+
+        [self.command as u8, target_h, target_l, 0, 0, 0, 0, 0]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+
+        Ok(NodeRequest {
+            command: NodeCommand::decode(data[0])?,
+            target: u16::from_be_bytes([data[1], data[2]]) as u32,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParameterCommand {
+    Read = 0x01,
+    Write = 0x02,
+    /// Stores all current values in NVS.
+    Save = 0x03,
+    /// Sets all values back to their defaults, NVS keeps the saved ones until the next
+    /// save.
+    ResetDefaults = 0x04,
+}
+
+impl ParameterCommand {
+    pub const ALL: [ParameterCommand; 4] = [
+        ParameterCommand::Read,
+        ParameterCommand::Write,
+        ParameterCommand::Save,
+        ParameterCommand::ResetDefaults,
+    ];
+
+    fn decode(value: u8) -> Result<Self, DecodeError> {
+        Self::ALL
+            .into_iter()
+            .find(|command| *command as u8 == value)
+            .ok_or(DecodeError::InvalidValue(value))
+    }
+}
+
+/// 0x101: addressed parameter request, `[cc tt tt pp vv vv vv vv]`.
+///
+/// cc is the [`ParameterCommand`], tt tt the big endian identifier of the node, pp the
+/// parameter number of its table and vv vv vv vv the big endian value to write.
+/// Save and reset ignore pp and vv, read ignores vv.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterRequest {
+    pub command: ParameterCommand,
+    pub target: u32,
+    pub parameter: u8,
+    pub value: u32,
+}
+
+impl ParameterRequest {
+    pub fn encode(&self) -> [u8; 8] {
+        let [target_h, target_l] = (self.target as u16).to_be_bytes();
+        let [v0, v1, v2, v3] = self.value.to_be_bytes();
+
+        [
+            self.command as u8,
+            target_h,
+            target_l,
+            self.parameter,
+            v0,
+            v1,
+            v2,
+            v3,
+        ]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+
+        Ok(ParameterRequest {
+            command: ParameterCommand::decode(data[0])?,
+            target: u16::from_be_bytes([data[1], data[2]]) as u32,
+            parameter: data[3],
+            value: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParameterResult {
+    Ok = 0x00,
+    UnknownParameter = 0x01,
+    OutOfRange = 0x02,
+    /// NVS could not be written.
+    SaveFailed = 0x03,
+}
+
+impl ParameterResult {
+    pub const ALL: [ParameterResult; 4] = [
+        ParameterResult::Ok,
+        ParameterResult::UnknownParameter,
+        ParameterResult::OutOfRange,
+        ParameterResult::SaveFailed,
+    ];
+}
+
+/// Answer to a [`ParameterRequest`] on the node's own identifier,
+/// `[03 cc pp rr vv vv vv vv]`.
+///
+/// cc and pp repeat the request, rr is the [`ParameterResult`] and vv vv vv vv the big
+/// endian value the parameter has now (0 for save and reset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterResponse {
+    pub command: ParameterCommand,
+    pub parameter: u8,
+    pub result: ParameterResult,
+    pub value: u32,
+}
+
+impl ParameterResponse {
+    fn encode(&self) -> [u8; 8] {
+        let [v0, v1, v2, v3] = self.value.to_be_bytes();
+
+        [
+            STATUS_PARAMETER,
+            self.command as u8,
+            self.parameter,
+            self.result as u8,
+            v0,
+            v1,
+            v2,
+            v3,
+        ]
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let result = ParameterResult::ALL
+            .into_iter()
+            .find(|result| *result as u8 == data[3])
+            .ok_or(DecodeError::InvalidValue(data[3]))?;
+
+        Ok(ParameterResponse {
+            command: ParameterCommand::decode(data[1])?,
+            parameter: data[2],
+            result,
+            value: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
+}
+
+/// Why a node started, byte 2 of its online frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetReason {
+    #[default]
+    Unknown = 0x00,
+    PowerOn = 0x01,
+    /// Reset button or EN pin.
+    External = 0x02,
+    /// [`NodeCommand::Reset`].
+    Command = 0x03,
+    /// Restart into a new image after an OTA update.
+    Update = 0x04,
+    /// Any other restart of the firmware itself, e.g. after an invalid configuration.
+    Software = 0x05,
+    Panic = 0x06,
+    Watchdog = 0x07,
+    Brownout = 0x08,
+}
+
+impl ResetReason {
+    pub const ALL: [ResetReason; 9] = [
+        ResetReason::Unknown,
+        ResetReason::PowerOn,
+        ResetReason::External,
+        ResetReason::Command,
+        ResetReason::Update,
+        ResetReason::Software,
+        ResetReason::Panic,
+        ResetReason::Watchdog,
+        ResetReason::Brownout,
+    ];
+
+    /// Numbers added by newer firmware decode as [`ResetReason::Unknown`], the rest of
+    /// the status frame must not be lost over it.
+    pub fn decode(value: u8) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|reason| *reason as u8 == value)
+            .unwrap_or_default()
+    }
+}
+
+/// 0x222: wheel speed sensor frequencies in 0.1 Hz, `[aa aa bb bb cc cc dd dd]` big endian.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WheelSpeeds {
+    pub fl: u16,
+    pub fr: u16,
+    pub rl: u16,
+    pub rr: u16,
+}
+
+impl WheelSpeeds {
+    pub const RESOLUTION_HZ: f32 = 0.1;
+
+    /// Front left, front right, rear left, rear right. Saturates at 6553.5 Hz.
+    pub fn from_hertz(hertz: [f32; 4]) -> Self {
+        let [fl, fr, rl, rr] = hertz.map(|hertz| (hertz / Self::RESOLUTION_HZ).round() as u16);
+
+        WheelSpeeds { fl, fr, rl, rr }
+    }
+
+    pub fn hertz(&self) -> [f32; 4] {
+        [self.fl, self.fr, self.rl, self.rr].map(|raw| raw as f32 * Self::RESOLUTION_HZ)
+    }
+
+    pub fn encode(&self) -> [u8; 8] {
+        let [fl_h, fl_l] = self.fl.to_be_bytes();
+        let [fr_h, fr_l] = self.fr.to_be_bytes();
+        let [rl_h, rl_l] = self.rl.to_be_bytes();
+        let [rr_h, rr_l] = self.rr.to_be_bytes();
+
+        [fl_h, fl_l, fr_h, fr_l, rl_h, rl_l, rr_h, rr_l]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+
+        Ok(WheelSpeeds {
+            fl: u16::from_be_bytes([data[0], data[1]]),
+            fr: u16::from_be_bytes([data[2], data[3]]),
+            rl: u16::from_be_bytes([data[4], data[5]]),
+            rr: u16::from_be_bytes([data[6], data[7]]),
+        })
+    }
+}
+
+/// 0x310: kombiinstrument status, `[11 yz?????? rr 00 00 00 00 tct]`.
+///
+/// Bit 7 (y) and bit 6 (z) of byte 1 drive the two brake light outputs of the
+/// engine bay unit, rr is the [`ResetReason`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KombiinstrumentStatus {
+    pub brake_pedal_active_0: bool,
+    pub brake_pedal_active_1: bool,
+    pub reset_reason: ResetReason,
+    /// Cycle time usage of the previous cycle in percent.
+    pub tct_perc: u8,
+}
+
+impl KombiinstrumentStatus {
+    const BRAKE_PEDAL_0: u8 = 1 << 7;
+    const BRAKE_PEDAL_1: u8 = 1 << 6;
+
+    pub fn encode(&self) -> [u8; 8] {
+        let mut brake_byte = 0;
+        if self.brake_pedal_active_0 {
+            brake_byte |= Self::BRAKE_PEDAL_0;
+        }
+        if self.brake_pedal_active_1 {
+            brake_byte |= Self::BRAKE_PEDAL_1;
+        }
+
+        [
+            STATUS_ONLINE,
+            brake_byte,
+            self.reset_reason as u8,
+            0,
+            0,
+            0,
+            0,
+            self.tct_perc,
+        ]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_online(data)?;
+
+        Ok(KombiinstrumentStatus {
+            brake_pedal_active_0: data[1] & Self::BRAKE_PEDAL_0 != 0,
+            brake_pedal_active_1: data[1] & Self::BRAKE_PEDAL_1 != 0,
+            reset_reason: ResetReason::decode(data[2]),
+            tct_perc: data[7],
+        })
+    }
+}
+
+/// 0x210: engine bay unit status, `[11 00 rr 00 00 00 tct 00]`, rr is the [`ResetReason`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineBayStatus {
+    pub reset_reason: ResetReason,
+    /// Cycle time usage of the previous cycle in percent.
+    pub tct_perc: u8,
+}
+
+impl EngineBayStatus {
+    pub fn encode(&self) -> [u8; 8] {
+        [
+            STATUS_ONLINE,
+            0,
+            self.reset_reason as u8,
+            0,
+            0,
+            0,
+            self.tct_perc,
+            0,
+        ]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_online(data)?;
+
+        Ok(EngineBayStatus {
+            reset_reason: ResetReason::decode(data[2]),
+            tct_perc: data[6],
+        })
+    }
+}
+
+/// Diagnostic frames of a node are sent on its identifier + 0x400, e.g. 0x610 for the
+/// engine bay unit at 0x210.
+pub const DIAGNOSTIC_OFFSET: u32 = 0x400;
+
+pub fn diagnostic_id(node_identifier: u32) -> u32 {
+    node_identifier + DIAGNOSTIC_OFFSET
+}
+
+/// Diagnostic page 1: CAN traffic of the last second, `[01 rr rr tt tt ll dd mm]`.
+///
+/// rr rr received and tt tt transmitted frames (big endian), ll bus load in percent,
+/// dd frames dropped by the node and mm frames missed by the controller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CanTraffic {
+    pub rx_frames: u16,
+    pub tx_frames: u16,
+    /// Of the frames this node received and sent, the acceptance filter hides the rest.
+    pub bus_load_perc: u8,
+    /// The app_thread did not keep up.
+    pub rx_dropped: u8,
+    /// The controller's RX queue was full.
+    pub rx_missed: u8,
+}
+
+impl CanTraffic {
+    pub const PAGE: u8 = 0x01;
+
+    pub fn encode(&self) -> [u8; 8] {
+        let [rx_h, rx_l] = self.rx_frames.to_be_bytes();
+        let [tx_h, tx_l] = self.tx_frames.to_be_bytes();
+
+        [
+            Self::PAGE,
+            rx_h,
+            rx_l,
+            tx_h,
+            tx_l,
+            self.bus_load_perc,
+            self.rx_dropped,
+            self.rx_missed,
+        ]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+        if data[0] != Self::PAGE {
+            return Err(DecodeError::InvalidValue(data[0]));
+        }
+
+        Ok(CanTraffic {
+            rx_frames: u16::from_be_bytes([data[1], data[2]]),
+            tx_frames: u16::from_be_bytes([data[3], data[4]]),
+            bus_load_perc: data[5],
+            rx_dropped: data[6],
+            rx_missed: data[7],
+        })
+    }
+}
+
+/// Diagnostic page 2: CAN errors of the last second, `[02 ff rt be al te re 00]`.
+///
+/// ff failed and rt retried transmissions, be bus errors, al lost arbitrations, te/re
+/// the highest transmit/receive error counter. All counts saturate at 255.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CanErrors {
+    pub tx_failed: u8,
+    pub tx_retried: u8,
+    pub bus_errors: u8,
+    pub arbitration_lost: u8,
+    pub tec_peak: u8,
+    pub rec_peak: u8,
+}
+
+impl CanErrors {
+    pub const PAGE: u8 = 0x02;
+
+    pub fn encode(&self) -> [u8; 8] {
+        [
+            Self::PAGE,
+            self.tx_failed,
+            self.tx_retried,
+            self.bus_errors,
+            self.arbitration_lost,
+            self.tec_peak,
+            self.rec_peak,
+            0,
+        ]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data)?;
+        if data[0] != Self::PAGE {
+            return Err(DecodeError::InvalidValue(data[0]));
+        }
+
+        Ok(CanErrors {
+            tx_failed: data[1],
+            tx_retried: data[2],
+            bus_errors: data[3],
+            arbitration_lost: data[4],
+            tec_peak: data[5],
+            rec_peak: data[6],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn universal_frame_round_trip() {
+        let response = ParameterResponse {
+            command: ParameterCommand::Write,
+            parameter: 0x07,
+            result: ParameterResult::OutOfRange,
+            value: 0x0102_0304,
+        };
+        for frame in [
+            UniversalFrame::Online,
+            UniversalFrame::UpdateProgress(0x80),
+            UniversalFrame::Parameter(response),
+            UniversalFrame::Resetting,
+            UniversalFrame::Warning(0x10),
+            UniversalFrame::Critical(0x24),
+        ] {
+            assert_eq!(UniversalFrame::decode(&frame.encode()), Ok(frame));
+        }
+    }
+
+    #[test]
+    fn universal_frame_layout() {
+        assert_eq!(
+            UniversalFrame::UpdateProgress(0x80).encode()[..2],
+            [0x02, 0x80]
+        );
+        assert_eq!(UniversalFrame::Warning(0x10).encode()[..2], [0xf0, 0x10]);
+        assert_eq!(UniversalFrame::Critical(0x24).encode()[..2], [0xff, 0x24]);
+    }
+
+    #[test]
+    fn universal_frame_rejects() {
+        assert_eq!(
+            UniversalFrame::decode(&[0x11; 7]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 7
+            })
+        );
+        assert_eq!(
+            UniversalFrame::decode(&[0x55, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::UnexpectedStatus(0x55))
+        );
+        assert_eq!(
+            UniversalFrame::decode(&[0xf5, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue(0xf5))
+        );
+        assert_eq!(
+            UniversalFrame::decode(&[0x03, 0x09, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue(0x09))
+        );
+        assert_eq!(
+            UniversalFrame::decode(&[0x03, 0x01, 0, 0x09, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue(0x09))
+        );
+    }
+
+    #[test]
+    fn error_code_numbers() {
+        for (i, code) in ErrorCode::ALL.into_iter().enumerate() {
+            assert!(ErrorCode::ALL[..i]
+                .iter()
+                .all(|other| other.number() != code.number()));
+            assert_eq!(
+                UniversalFrame::decode(&UniversalFrame::Warning(code.number()).encode()),
+                Ok(UniversalFrame::Warning(code as u8))
+            );
+        }
+    }
+
+    #[test]
+    fn node_request_round_trip() {
+        for command in NodeCommand::ALL {
+            let request = NodeRequest {
+                command,
+                target: KOMBIINSTRUMENT_ID,
+            };
+            assert_eq!(NodeRequest::decode(&request.encode()), Ok(request));
+        }
+        assert_eq!(
+            NodeRequest {
+                command: NodeCommand::Reset,
+                target: ENGINE_BAY_UNIT_ID
+            }
+            .encode(),
+            [0x02, 0x02, 0x10, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn node_request_rejects() {
+        assert_eq!(
+            NodeRequest::decode(&[0x01, 0x03, 0x10]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            NodeRequest::decode(&[0x09, 0x03, 0x10, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue(0x09))
+        );
+    }
+
+    #[test]
+    fn node_request_addresses() {
+        let request = NodeRequest {
+            command: NodeCommand::Update,
+            target: KOMBIINSTRUMENT_ID,
+        };
+        let everyone = NodeRequest {
+            target: NodeRequest::ALL_NODES,
+            ..request
+        };
+
+        assert!(request.addresses(KOMBIINSTRUMENT_ID));
+        assert!(!request.addresses(ENGINE_BAY_UNIT_ID));
+        assert!(everyone.addresses(ENGINE_BAY_UNIT_ID));
+    }
+
+    #[test]
+    fn parameter_request_round_trip() {
+        for command in ParameterCommand::ALL {
+            let request = ParameterRequest {
+                command,
+                target: ENGINE_BAY_UNIT_ID,
+                parameter: 0x05,
+                value: 0xdead_beef,
+            };
+            assert_eq!(ParameterRequest::decode(&request.encode()), Ok(request));
+        }
+    }
+
+    #[test]
+    fn parameter_request_rejects() {
+        assert_eq!(
+            ParameterRequest::decode(&[0x02; 4]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 4
+            })
+        );
+        assert_eq!(
+            ParameterRequest::decode(&[0x00, 0x02, 0x10, 0x01, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue(0x00))
+        );
+    }
+
+    #[test]
+    fn parameter_response_round_trip() {
+        for command in ParameterCommand::ALL {
+            for result in ParameterResult::ALL {
+                let frame = UniversalFrame::Parameter(ParameterResponse {
+                    command,
+                    parameter: 0x02,
+                    result,
+                    value: 50,
+                });
+                assert_eq!(UniversalFrame::decode(&frame.encode()), Ok(frame));
+            }
+        }
+    }
+
+    #[test]
+    fn reset_reason() {
+        for reason in ResetReason::ALL {
+            assert_eq!(ResetReason::decode(reason as u8), reason);
+        }
+        assert_eq!(ResetReason::decode(0x42), ResetReason::Unknown);
+    }
+
+    #[test]
+    fn wheel_speeds_round_trip() {
+        let speeds = WheelSpeeds {
+            fl: 1,
+            fr: 0x0203,
+            rl: 6838,
+            rr: u16::MAX,
+        };
+
+        assert_eq!(WheelSpeeds::decode(&speeds.encode()), Ok(speeds));
+        assert_eq!(speeds.encode()[..4], [0x00, 0x01, 0x02, 0x03]);
+        assert_eq!(
+            WheelSpeeds::decode(&[0; 6]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 6
+            })
+        );
+    }
+
+    #[test]
+    fn wheel_speeds_hertz() {
+        let speeds = WheelSpeeds::from_hertz([683.8, 0.04, 0.05, 10_000.0]);
+
+        assert_eq!(speeds.fl, 6838);
+        assert_eq!(speeds.fr, 0);
+        assert_eq!(speeds.rl, 1);
+        assert_eq!(speeds.rr, u16::MAX);
+        assert!((speeds.hertz()[0] - 683.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn kombiinstrument_status_round_trip() {
+        for (brake_pedal_active_0, brake_pedal_active_1) in
+            [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let status = KombiinstrumentStatus {
+                brake_pedal_active_0,
+                brake_pedal_active_1,
+                reset_reason: ResetReason::Watchdog,
+                tct_perc: 42,
+            };
+            assert_eq!(KombiinstrumentStatus::decode(&status.encode()), Ok(status));
+        }
+
+        let status = KombiinstrumentStatus {
+            brake_pedal_active_0: true,
+            brake_pedal_active_1: true,
+            reset_reason: ResetReason::Command,
+            tct_perc: 7,
+        };
+        assert_eq!(status.encode(), [0x11, 0xc0, 0x03, 0, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn kombiinstrument_status_rejects() {
+        assert_eq!(
+            KombiinstrumentStatus::decode(&[0x11, 0xc0]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            KombiinstrumentStatus::decode(&UniversalFrame::Warning(0x01).encode()),
+            Err(DecodeError::UnexpectedStatus(0xf0))
+        );
+    }
+
+    #[test]
+    fn engine_bay_status_round_trip() {
+        let status = EngineBayStatus {
+            reset_reason: ResetReason::Brownout,
+            tct_perc: 99,
+        };
+
+        assert_eq!(EngineBayStatus::decode(&status.encode()), Ok(status));
+        assert_eq!(status.encode(), [0x11, 0, 0x08, 0, 0, 0, 99, 0]);
+        assert_eq!(
+            EngineBayStatus::decode(&UniversalFrame::UpdateProgress(3).encode()),
+            Err(DecodeError::UnexpectedStatus(0x02))
+        );
+        assert_eq!(
+            EngineBayStatus::decode(&[]),
+            Err(DecodeError::InvalidLength {
+                expected: 8,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostic_round_trip() {
+        let traffic = CanTraffic {
+            rx_frames: 1000,
+            tx_frames: 20,
+            bus_load_perc: 35,
+            rx_dropped: 1,
+            rx_missed: 2,
+        };
+        let errors = CanErrors {
+            tx_failed: 1,
+            tx_retried: 2,
+            bus_errors: 3,
+            arbitration_lost: 4,
+            tec_peak: 5,
+            rec_peak: 6,
+        };
+
+        assert_eq!(CanTraffic::decode(&traffic.encode()), Ok(traffic));
+        assert_eq!(CanErrors::decode(&errors.encode()), Ok(errors));
+        assert_eq!(
+            CanTraffic::decode(&errors.encode()),
+            Err(DecodeError::InvalidValue(CanErrors::PAGE))
+        );
+        assert_eq!(
+            CanErrors::decode(&traffic.encode()),
+            Err(DecodeError::InvalidValue(CanTraffic::PAGE))
+        );
+        assert_eq!(diagnostic_id(ENGINE_BAY_UNIT_ID), 0x610);
+    }
+}
@@ -0,0 +1,274 @@
+//! Broadcasts of the vehicle's engine ECU (VW PQ "Motor" messages).
+//!
+//! Unlike our own frames these are little endian. The layouts follow the public PQ
+//! matrix (opendbc `vw_golf_mk4.dbc`); only the signals the kombiinstrument uses are
+//! decoded. The layouts of 0x38A, 0x480, 0x488 and 0x588 are not confirmed for our ECU
+//! yet, their values are only logged and drive no output.
+
+use super::DecodeError;
+
+// --- CAN identifiers ---
+pub const MOTOR_1_ID: u32 = 0x280;
+pub const MOTOR_2_ID: u32 = 0x288;
+pub const MOTOR_3_ID: u32 = 0x380;
+pub const GRA_NEU_ID: u32 = 0x38a;
+pub const MOTOR_5_ID: u32 = 0x480;
+pub const MOTOR_6_ID: u32 = 0x488;
+pub const MOTOR_7_ID: u32 = 0x588;
+
+/// The ECU does not always send all 8 bytes of the Motor messages, every decoder only
+/// asks for the bytes it reads.
+fn check_len(data: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if data.len() < expected {
+        return Err(DecodeError::InvalidLength {
+            expected,
+            actual: data.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Temperatures are sent as `0.75 °C / bit - 48 °C`.
+fn temperature(raw: u8) -> f32 {
+    raw as f32 * 0.75 - 48.0
+}
+
+/// 0x280 Motor_1, sent every 10 ms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motor1 {
+    /// Bytes 2-3, 0.25 rpm / bit.
+    pub engine_rpm: u16,
+}
+
+impl Motor1 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 4)?;
+
+        Ok(Self {
+            engine_rpm: u16::from_le_bytes([data[2], data[3]]) / 4,
+        })
+    }
+}
+
+/// 0x288 Motor_2, sent every 20 ms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motor2 {
+    /// Byte 1.
+    pub coolant_temp: f32,
+}
+
+impl Motor2 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 2)?;
+
+        Ok(Self {
+            coolant_temp: temperature(data[1]),
+        })
+    }
+}
+
+/// 0x380 Motor_3, sent every 10 ms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motor3 {
+    /// Byte 7, throttle valve position, 0.4 % / bit.
+    pub throttle_perc: f32,
+}
+
+impl Motor3 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 8)?;
+
+        Ok(Self {
+            throttle_perc: data[7] as f32 * 0.4,
+        })
+    }
+}
+
+/// 0x38A GRA_Neu, the cruise control lever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraNeu {
+    /// Byte 1 bit 0.
+    pub main_switch: bool,
+    /// Byte 1 bit 1.
+    pub cancel: bool,
+}
+
+impl GraNeu {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 2)?;
+
+        Ok(Self {
+            main_switch: data[1] & 0x01 != 0,
+            cancel: data[1] & 0x02 != 0,
+        })
+    }
+}
+
+/// 0x480 Motor_5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Motor5 {
+    /// Bytes 2-3, lower 15 bits, fuel used in µl. Wraps around, only the difference
+    /// between two frames means anything.
+    pub fuel_consumption_ul: u16,
+}
+
+impl Motor5 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 4)?;
+
+        Ok(Self {
+            fuel_consumption_ul: u16::from_le_bytes([data[2], data[3]]) & 0x7fff,
+        })
+    }
+}
+
+/// 0x488 Motor_6.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motor6 {
+    /// Byte 2, engine torque for the gearbox, 0.39 % of the maximum torque / bit.
+    pub engine_torque_perc: f32,
+}
+
+impl Motor6 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 3)?;
+
+        Ok(Self {
+            engine_torque_perc: data[2] as f32 * 0.39,
+        })
+    }
+}
+
+/// 0x588 Motor_7.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motor7 {
+    /// Byte 1, 1 °C / bit - 60 °C.
+    pub oil_temp: f32,
+}
+
+impl Motor7 {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        check_len(data, 2)?;
+
+        Ok(Self {
+            oil_temp: data[1] as f32 - 60.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn too_short(expected: usize, actual: usize) -> DecodeError {
+        DecodeError::InvalidLength { expected, actual }
+    }
+
+    #[test]
+    fn motor_1() {
+        // 0x1f40 = 8000, a quarter of it in rpm.
+        let data = [0x01, 0x02, 0x40, 0x1f, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(Motor1::decode(&data), Ok(Motor1 { engine_rpm: 2000 }));
+        assert_eq!(
+            Motor1::decode(&[0, 0, 0xff, 0xff]),
+            Ok(Motor1 { engine_rpm: 16383 })
+        );
+
+        assert_eq!(Motor1::decode(&data[..3]), Err(too_short(4, 3)));
+    }
+
+    #[test]
+    fn motor_2() {
+        assert_eq!(
+            Motor2::decode(&[0xff, 184, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Ok(Motor2 { coolant_temp: 90.0 })
+        );
+        assert_eq!(
+            Motor2::decode(&[0, 0]),
+            Ok(Motor2 {
+                coolant_temp: -48.0
+            })
+        );
+        assert_eq!(
+            Motor2::decode(&[0, 0xff]),
+            Ok(Motor2 {
+                coolant_temp: 143.25
+            })
+        );
+
+        assert_eq!(Motor2::decode(&[184]), Err(too_short(2, 1)));
+    }
+
+    #[test]
+    fn motor_3() {
+        let mut data = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 250];
+        assert_eq!(
+            Motor3::decode(&data),
+            Ok(Motor3 {
+                throttle_perc: 100.0
+            })
+        );
+        data[7] = 0;
+        assert_eq!(Motor3::decode(&data), Ok(Motor3 { throttle_perc: 0.0 }));
+
+        // The throttle valve is the last byte.
+        assert_eq!(Motor3::decode(&data[..7]), Err(too_short(8, 7)));
+    }
+
+    #[test]
+    fn gra_neu() {
+        assert_eq!(
+            GraNeu::decode(&[0xaa, 0x01, 0xff, 0xff]),
+            Ok(GraNeu {
+                main_switch: true,
+                cancel: false,
+            })
+        );
+        assert_eq!(
+            GraNeu::decode(&[0xff, 0xfe]),
+            Ok(GraNeu {
+                main_switch: false,
+                cancel: true,
+            })
+        );
+
+        assert_eq!(GraNeu::decode(&[0xaa]), Err(too_short(2, 1)));
+    }
+
+    #[test]
+    fn motor_5() {
+        // The 16th bit is not part of the counter.
+        assert_eq!(
+            Motor5::decode(&[0xff, 0xff, 0x34, 0x92, 0xff, 0xff, 0xff, 0xff]),
+            Ok(Motor5 {
+                fuel_consumption_ul: 0x1234
+            })
+        );
+
+        assert_eq!(Motor5::decode(&[0, 0, 0x34]), Err(too_short(4, 3)));
+    }
+
+    #[test]
+    fn motor_6() {
+        assert_eq!(
+            Motor6::decode(&[0xff, 0xff, 100, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Ok(Motor6 {
+                engine_torque_perc: 39.0
+            })
+        );
+
+        assert_eq!(Motor6::decode(&[0, 0]), Err(too_short(3, 2)));
+    }
+
+    #[test]
+    fn motor_7() {
+        assert_eq!(
+            Motor7::decode(&[0xff, 155, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Ok(Motor7 { oil_temp: 95.0 })
+        );
+        assert_eq!(Motor7::decode(&[0, 0]), Ok(Motor7 { oil_temp: -60.0 }));
+
+        assert_eq!(Motor7::decode(&[]), Err(too_short(2, 0)));
+    }
+}
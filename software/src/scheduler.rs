@@ -0,0 +1,331 @@
+//! Periodic tasks of a node's app_thread.
+//!
+//! Every task has a fixed period and is released at multiples of it, counted from the
+//! start of the scheduler, so a slow run does not shift the following ones. Tasks that
+//! are due at the same time run in the order they were added. Time only comes from the
+//! [`Clock`], so a clock that advances on `sleep` runs the schedule without waiting.
+
+use std::time::{Duration, Instant};
+
+use crate::{dbg_println, hal::Clock};
+
+/// Interval of the statistics log line.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    pub runs: u32,
+    /// Runs that ended after the next release of the task. The releases missed by
+    /// them are skipped.
+    pub overruns: u32,
+    pub last_runtime: Duration,
+    pub max_runtime: Duration,
+    /// Delay between the release and the start of a run.
+    pub max_jitter: Duration,
+}
+
+/// Handed to every run of a task.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskContext {
+    /// Start of this run.
+    pub now: Instant,
+    pub period: Duration,
+    /// Statistics up to the previous run.
+    pub stats: TaskStats,
+}
+
+impl TaskContext {
+    /// Runtime of the previous run in percent of the period.
+    pub fn load_perc(&self) -> u8 {
+        let perc = 100 * self.stats.last_runtime.as_micros() / self.period.as_micros().max(1);
+        perc.min(u8::MAX as u128) as u8
+    }
+}
+
+struct Task<S> {
+    name: &'static str,
+    period: Duration,
+    run: fn(&mut S, &TaskContext),
+    next_release: Instant,
+    stats: TaskStats,
+}
+
+pub struct Scheduler<'a, S> {
+    clock: Box<dyn Clock + 'a>,
+    tasks: Vec<Task<S>>,
+    /// Prefixes the log lines, e.g. `KBI`.
+    tag: &'static str,
+    next_report: Instant,
+}
+
+impl<'a, S> Scheduler<'a, S> {
+    pub fn new(clock: Box<dyn Clock + 'a>, tag: &'static str) -> Self {
+        let next_report = clock.now() + REPORT_INTERVAL;
+
+        Self {
+            clock,
+            tasks: Vec::new(),
+            tag,
+            next_report,
+        }
+    }
+
+    /// Adds a task that is first released right away.
+    ///
+    /// Panics if `period` is zero, such a task would never give up the thread.
+    pub fn with_task(
+        mut self,
+        name: &'static str,
+        period: Duration,
+        run: fn(&mut S, &TaskContext),
+    ) -> Self {
+        assert!(!period.is_zero(), "Task {name} has no period");
+        self.tasks.push(Task {
+            name,
+            period,
+            run,
+            next_release: self.clock.now(),
+            stats: TaskStats::default(),
+        });
+        self
+    }
+
+    pub fn run(&mut self, state: &mut S) -> ! {
+        loop {
+            self.run_next(state);
+        }
+    }
+
+    /// Waits for the next release and runs that task once.
+    pub fn run_next(&mut self, state: &mut S) {
+        // The first of several tasks with the same release wins.
+        let Some(task) = self.tasks.iter_mut().reduce(|first, task| {
+            if task.next_release < first.next_release {
+                task
+            } else {
+                first
+            }
+        }) else {
+            return;
+        };
+
+        let now = self.clock.now();
+        if let Some(remaining) = task.next_release.checked_duration_since(now) {
+            self.clock.sleep(remaining);
+        }
+
+        let start = self.clock.now();
+        let context = TaskContext {
+            now: start,
+            period: task.period,
+            stats: task.stats,
+        };
+        (task.run)(state, &context);
+        let end = self.clock.now();
+
+        let stats = &mut task.stats;
+        stats.runs = stats.runs.wrapping_add(1);
+        stats.last_runtime = end.saturating_duration_since(start);
+        stats.max_runtime = stats.max_runtime.max(stats.last_runtime);
+        stats.max_jitter = stats
+            .max_jitter
+            .max(start.saturating_duration_since(task.next_release));
+
+        task.next_release += task.period;
+        if task.next_release <= end {
+            stats.overruns = stats.overruns.wrapping_add(1);
+            // Stay on the grid of the period instead of running the missed releases.
+            while task.next_release <= end {
+                task.next_release += task.period;
+            }
+        }
+
+        if end >= self.next_report {
+            self.next_report = end + REPORT_INTERVAL;
+            self.report();
+        }
+    }
+
+    /// Logs the statistics and starts the next window for the maxima.
+    fn report(&mut self) {
+        for task in &mut self.tasks {
+            dbg_println!(
+                "[{}/sched ] {} every {:?}: {} runs, {} overruns | Runtime max: {:?} | Jitter max: {:?}",
+                self.tag,
+                task.name,
+                task.period,
+                task.stats.runs,
+                task.stats.overruns,
+                task.stats.max_runtime,
+                task.stats.max_jitter
+            );
+            task.stats.max_runtime = Duration::ZERO;
+            task.stats.max_jitter = Duration::ZERO;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// Only advances on `sleep` and while a task runs.
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    struct State {
+        clock: FakeClock,
+        start: Instant,
+        /// Time every run takes.
+        runtime: Duration,
+        /// Task, start of the run since `start` and its context.
+        runs: Vec<(&'static str, Duration, TaskContext)>,
+    }
+
+    impl State {
+        fn run(&mut self, name: &'static str, context: &TaskContext) {
+            let since_start = context.now - self.start;
+            self.runs.push((name, since_start, *context));
+            self.clock.advance(self.runtime);
+        }
+
+        fn starts(&self) -> Vec<(&'static str, u64)> {
+            self.runs
+                .iter()
+                .map(|(name, start, _)| (*name, start.as_millis() as u64))
+                .collect()
+        }
+    }
+
+    fn fast(state: &mut State, context: &TaskContext) {
+        state.run("fast", context);
+    }
+
+    fn slow(state: &mut State, context: &TaskContext) {
+        state.run("slow", context);
+    }
+
+    fn setup(runtime: Duration) -> (FakeClock, State) {
+        let start = Instant::now();
+        let clock = FakeClock(Rc::new(Cell::new(start)));
+        let state = State {
+            clock: clock.clone(),
+            start,
+            runtime,
+            runs: Vec::new(),
+        };
+        (clock, state)
+    }
+
+    #[test]
+    fn releases_stay_on_the_grid() {
+        let (clock, mut state) = setup(Duration::from_millis(1));
+        let mut scheduler = Scheduler::new(Box::new(clock), "TST")
+            .with_task("fast", Duration::from_millis(10), fast)
+            .with_task("slow", Duration::from_millis(25), slow);
+
+        for _ in 0..9 {
+            scheduler.run_next(&mut state);
+        }
+
+        // The slow task is delayed by the fast one without shifting its later releases.
+        assert_eq!(
+            state.starts(),
+            [
+                ("fast", 0),
+                ("slow", 1),
+                ("fast", 10),
+                ("fast", 20),
+                ("slow", 25),
+                ("fast", 30),
+                ("fast", 40),
+                ("fast", 50),
+                ("slow", 51),
+            ]
+        );
+
+        let (_, _, context) = state.runs[4];
+        assert_eq!(context.period, Duration::from_millis(25));
+        assert_eq!(
+            context.stats,
+            TaskStats {
+                runs: 1,
+                overruns: 0,
+                last_runtime: Duration::from_millis(1),
+                max_runtime: Duration::from_millis(1),
+                max_jitter: Duration::from_millis(1),
+            }
+        );
+        assert_eq!(context.load_perc(), 4);
+    }
+
+    #[test]
+    fn overruns_skip_the_missed_releases() {
+        let (clock, mut state) = setup(Duration::from_millis(25));
+        let mut scheduler = Scheduler::new(Box::new(clock), "TST").with_task(
+            "fast",
+            Duration::from_millis(10),
+            fast,
+        );
+
+        for _ in 0..3 {
+            scheduler.run_next(&mut state);
+        }
+
+        assert_eq!(state.starts(), [("fast", 0), ("fast", 30), ("fast", 60)]);
+        let (_, _, context) = state.runs[2];
+        assert_eq!(context.stats.runs, 2);
+        assert_eq!(context.stats.overruns, 2);
+        assert_eq!(context.stats.max_jitter, Duration::ZERO);
+        assert_eq!(context.load_perc(), 250);
+    }
+
+    #[test]
+    fn report_starts_a_new_window() {
+        let (clock, mut state) = setup(Duration::from_millis(100));
+        let mut scheduler =
+            Scheduler::new(Box::new(clock), "TST").with_task("slow", Duration::from_secs(1), slow);
+
+        scheduler.run_next(&mut state);
+        state.runtime = Duration::from_millis(1);
+        // The run at 10 s ends after the report, the one after it sees the new window.
+        for _ in 0..11 {
+            scheduler.run_next(&mut state);
+        }
+
+        let (_, start, before) = state.runs[10];
+        assert_eq!(start, REPORT_INTERVAL);
+        assert_eq!(before.stats.max_runtime, Duration::from_millis(100));
+
+        let (_, _, after) = state.runs[11];
+        assert_eq!(after.stats.runs, 11);
+        assert_eq!(after.stats.max_runtime, Duration::ZERO);
+        assert_eq!(after.stats.last_runtime, Duration::from_millis(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "no period")]
+    fn zero_period_is_rejected() {
+        let (clock, _) = setup(Duration::ZERO);
+        let _ =
+            Scheduler::<State>::new(Box::new(clock), "TST").with_task("fast", Duration::ZERO, fast);
+    }
+}
@@ -0,0 +1,154 @@
+//! Self-test a freshly updated image has to pass before it is marked valid.
+//!
+//! The image passes once every check was reported and it kept running for
+//! [`STABLE_PERIOD`]; a panic within that period resets the chip before the image
+//! was marked valid. Checks still missing after [`DEADLINE`] fail the self-test.
+
+// The self-test itself only runs in the OTA module on the ESP32-S3.
+#![cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+
+use std::time::{Duration, Instant};
+
+pub const STABLE_PERIOD: Duration = Duration::from_secs(10);
+pub const DEADLINE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestCheck {
+    CanStarted,
+    HeartbeatSent,
+    AdcReadable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestResult {
+    Pending,
+    Passed,
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct SelfTest {
+    started: Instant,
+    missing: Vec<SelfTestCheck>,
+    result: SelfTestResult,
+}
+
+impl SelfTest {
+    pub fn new(now: Instant, checks: &[SelfTestCheck]) -> Self {
+        Self {
+            started: now,
+            missing: checks.to_vec(),
+            result: SelfTestResult::Pending,
+        }
+    }
+
+    pub fn pass(&mut self, check: SelfTestCheck) {
+        self.missing.retain(|c| *c != check);
+    }
+
+    pub fn missing(&self) -> &[SelfTestCheck] {
+        &self.missing
+    }
+
+    /// Decides the result once, later calls return the same result.
+    pub fn poll(&mut self, now: Instant) -> SelfTestResult {
+        if self.result != SelfTestResult::Pending {
+            return self.result;
+        }
+
+        let running = now.saturating_duration_since(self.started);
+        if self.missing.is_empty() && running >= STABLE_PERIOD {
+            self.result = SelfTestResult::Passed;
+        } else if !self.missing.is_empty() && running >= DEADLINE {
+            self.result = SelfTestResult::Failed;
+        }
+
+        self.result
+    }
+
+    pub fn result(&self) -> SelfTestResult {
+        self.result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKS: [SelfTestCheck; 2] = [SelfTestCheck::CanStarted, SelfTestCheck::HeartbeatSent];
+
+    #[test]
+    fn passes_after_the_stable_period() {
+        let start = Instant::now();
+        let mut self_test = SelfTest::new(start, &CHECKS);
+        self_test.pass(SelfTestCheck::CanStarted);
+        self_test.pass(SelfTestCheck::HeartbeatSent);
+        assert!(self_test.missing().is_empty());
+
+        assert_eq!(
+            self_test.poll(start + STABLE_PERIOD - Duration::from_millis(1)),
+            SelfTestResult::Pending
+        );
+        assert_eq!(
+            self_test.poll(start + STABLE_PERIOD),
+            SelfTestResult::Passed
+        );
+    }
+
+    #[test]
+    fn waits_for_every_check() {
+        let start = Instant::now();
+        let mut self_test = SelfTest::new(start, &CHECKS);
+        self_test.pass(SelfTestCheck::CanStarted);
+        // Checks that are not required do not count.
+        self_test.pass(SelfTestCheck::AdcReadable);
+
+        assert_eq!(
+            self_test.poll(start + STABLE_PERIOD),
+            SelfTestResult::Pending
+        );
+        assert_eq!(self_test.missing(), [SelfTestCheck::HeartbeatSent]);
+
+        self_test.pass(SelfTestCheck::HeartbeatSent);
+        assert_eq!(
+            self_test.poll(start + STABLE_PERIOD + Duration::from_secs(1)),
+            SelfTestResult::Passed
+        );
+    }
+
+    #[test]
+    fn fails_at_the_deadline() {
+        let start = Instant::now();
+        let mut self_test = SelfTest::new(start, &CHECKS);
+        self_test.pass(SelfTestCheck::CanStarted);
+
+        assert_eq!(
+            self_test.poll(start + DEADLINE - Duration::from_millis(1)),
+            SelfTestResult::Pending
+        );
+        assert_eq!(self_test.poll(start + DEADLINE), SelfTestResult::Failed);
+        assert_eq!(self_test.result(), SelfTestResult::Failed);
+        assert_eq!(self_test.missing(), [SelfTestCheck::HeartbeatSent]);
+    }
+
+    #[test]
+    fn result_is_final() {
+        let start = Instant::now();
+        let mut self_test = SelfTest::new(start, &CHECKS);
+        assert_eq!(self_test.poll(start + DEADLINE), SelfTestResult::Failed);
+
+        self_test.pass(SelfTestCheck::CanStarted);
+        self_test.pass(SelfTestCheck::HeartbeatSent);
+        assert_eq!(
+            self_test.poll(start + DEADLINE + STABLE_PERIOD),
+            SelfTestResult::Failed
+        );
+
+        let mut self_test = SelfTest::new(start, &[]);
+        assert_eq!(
+            self_test.poll(start + STABLE_PERIOD),
+            SelfTestResult::Passed
+        );
+        assert_eq!(self_test.poll(start + DEADLINE), SelfTestResult::Passed);
+    }
+}
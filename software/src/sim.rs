@@ -0,0 +1,283 @@
+//! Host simulation: kombiinstrument and engine_bay_unit run their app_threads with
+//! simulated sensors instead of the ESP32-S3 peripherals. Next to the kombiinstrument,
+//! a simulated engine ECU broadcasts its Motor messages.
+//!
+//! Without arguments both nodes share a virtual bus in memory. With a SocketCAN interface
+//! they talk over it instead, optionally only one of them:
+//!
+//!     espio vcan0 [kombiinstrument | engine_bay_unit]
+//!
+//! `espio dbc` prints the CAN matrix as DBC file instead.
+
+use std::{
+    env,
+    sync::Arc,
+    thread::{self, Builder},
+    time::Duration,
+};
+
+use crate::{
+    can_stats::{self, CanStats, CountingBus},
+    can_supervisor::{self, CanHealth},
+    config::{NodeConfig, Role},
+    engine_bay_unit::{self, EngineBayUnitIo},
+    hal::{
+        host::{
+            SimAnalogInput, SimConfigStore, SimFirmware, SimFrequencyOutput, SimOutput, SimWheel,
+            VirtualBus,
+        },
+        CanFrame, SharedCan, SystemClock,
+    },
+    kombiinstrument::{self, KombiinstrumentIo},
+    logging,
+    protocol::{
+        dbc, diagnostic_id,
+        oem::{GRA_NEU_ID, MOTOR_1_ID, MOTOR_2_ID, MOTOR_3_ID, MOTOR_5_ID, MOTOR_6_ID, MOTOR_7_ID},
+    },
+};
+
+const USAGE: &str =
+    "usage: espio [dbc | <socketcan interface> [kombiinstrument | engine_bay_unit]]";
+
+/// 12 V on the ADC input, after the voltage divider.
+const VDC_MV: u16 = 2400;
+
+/// 3.5 bar from the oil pressure sender with the default range.
+const OIL_PRESSURE_MV: u16 = 1254;
+
+/// ABS sensor frequency, about 100 km/h with the default tone ring and tyres.
+const WHEEL_FREQUENCY: f64 = 683.8;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Above 2000 rpm, so the kombiinstrument expects the high oil pressure switch point.
+const ENGINE_RPM: u16 = 2500;
+
+/// 90 °C, in 0.75 °C steps from -48 °C.
+const COOLANT_TEMP_RAW: u8 = 184;
+
+/// About 20 % of the maximum torque, in 0.39 % steps.
+const ENGINE_TORQUE_RAW: u8 = 51;
+
+/// 95 °C, in 1 °C steps from -60 °C.
+const OIL_TEMP_RAW: u8 = 155;
+
+const ENGINE_ECU_CYCLE: Duration = Duration::from_millis(10);
+
+enum Bus {
+    Virtual(VirtualBus),
+    SocketCan(String),
+}
+
+impl Bus {
+    fn connect(&self) -> anyhow::Result<SharedCan> {
+        Ok(match self {
+            Bus::Virtual(bus) => Arc::new(bus.connect()),
+            #[cfg(target_os = "linux")]
+            Bus::SocketCan(interface) => Arc::new(
+                crate::hal::socketcan::SocketCan::open(interface)
+                    .map_err(|e| anyhow::anyhow!("Failed to open {interface}: {e}"))?,
+            ),
+            #[cfg(not(target_os = "linux"))]
+            Bus::SocketCan(_) => anyhow::bail!("SocketCAN is only available on Linux"),
+        })
+    }
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("dbc") {
+        print!("{}", dbc::dbc());
+        return Ok(());
+    }
+
+    let bus = match args.first() {
+        None => Bus::Virtual(VirtualBus::default()),
+        Some(interface) => Bus::SocketCan(interface.clone()),
+    };
+    let role = args.get(1).map(String::as_str);
+    if args.len() > 2 || !matches!(role, None | Some("kombiinstrument" | "engine_bay_unit")) {
+        eprintln!("{USAGE}");
+        return Ok(());
+    }
+
+    logging::init(true);
+    println!("Init simulation");
+
+    let brake_lights = match role {
+        Some("kombiinstrument") => None,
+        _ => Some(start_engine_bay_unit(bus.connect()?)?),
+    };
+    let kombiinstrument = match role {
+        Some("engine_bay_unit") => None,
+        _ => Some(start_kombiinstrument(bus.connect()?)?),
+    };
+    if kombiinstrument.is_some() {
+        start_engine_ecu(bus.connect()?)?;
+    }
+
+    // --- Stimulus ---
+    // The brake pedal toggles every report, the brake lights of the engine_bay_unit follow.
+    let mut brake_pedal_pressed = false;
+    loop {
+        thread::sleep(REPORT_INTERVAL);
+
+        if let Some((brake_pedal, vehicle_speed, engine_speed)) = &kombiinstrument {
+            println!(
+                "[SIM       ] Brake pedal: {} | Speedometer: {} Hz | Tachometer: {} Hz",
+                brake_pedal_pressed,
+                vehicle_speed.frequency(),
+                engine_speed.frequency()
+            );
+
+            brake_pedal_pressed = !brake_pedal_pressed;
+            brake_pedal.set(if brake_pedal_pressed { VDC_MV } else { 0 });
+        }
+        if let Some(brake_lights) = &brake_lights {
+            println!(
+                "[SIM       ] Brake lights: {} {}",
+                !brake_lights.0.is_high(),
+                !brake_lights.1.is_high()
+            );
+        }
+    }
+}
+
+/// Wraps the bus into a [`CountingBus`] and starts the can_supervisor and can_stats
+/// threads, like the node's esp.rs does.
+fn start_can(
+    can: SharedCan,
+    config: &NodeConfig,
+    tag: &'static str,
+) -> (SharedCan, Arc<CanStats>, CanHealth) {
+    let stats = Arc::new(CanStats::default());
+    let can: SharedCan = Arc::new(CountingBus::new(can, Arc::clone(&stats)));
+    let health = can_supervisor::spawn_supervisor(Arc::clone(&can), tag);
+    can_stats::spawn_publisher(
+        Arc::clone(&can),
+        Arc::clone(&stats),
+        diagnostic_id(config.own_identifier),
+        config.bitrate,
+        tag,
+    );
+
+    (can, stats, health)
+}
+
+/// Returns the brake light outputs.
+fn start_engine_bay_unit(can: SharedCan) -> anyhow::Result<(SimOutput, SimOutput)> {
+    let brake_lights = (SimOutput::default(), SimOutput::default());
+    let wheel = SimWheel::new(WHEEL_FREQUENCY);
+    let vdc = SimAnalogInput::default();
+    vdc.set(VDC_MV);
+
+    // NVS of a board that only got its role, the parameters are saved in it as well.
+    let store = SimConfigStore::provisioned(Role::EngineBayUnit);
+    let config = NodeConfig::load(&store)?;
+    let (can, can_stats, can_health) = start_can(can, &config, "ECU");
+    let incoming_frames_rx = engine_bay_unit::spawn_can_receiver(Arc::clone(&can), can_stats);
+    let app_brake_lights = brake_lights.clone();
+    Builder::new()
+        .name("ebu_app_thread".into())
+        .spawn(move || {
+            let io = EngineBayUnitIo {
+                brake_lights: (Box::new(app_brake_lights.0), Box::new(app_brake_lights.1)),
+                onboard_led: Box::new(SimOutput::default()),
+                vdc: Box::new(vdc),
+                abs_sensors: [
+                    Box::new(wheel.clone()),
+                    Box::new(wheel.clone()),
+                    Box::new(wheel.clone()),
+                    Box::new(wheel),
+                ],
+                firmware: Box::new(SimFirmware),
+                parameter_store: Box::new(store),
+            };
+            engine_bay_unit::app_thread(
+                io,
+                Box::new(SystemClock),
+                can,
+                can_health,
+                config.own_identifier,
+                incoming_frames_rx,
+            )
+        })?;
+
+    Ok(brake_lights)
+}
+
+/// Returns the brake pedal input and the speedometer and tachometer outputs.
+fn start_kombiinstrument(
+    can: SharedCan,
+) -> anyhow::Result<(SimAnalogInput, SimFrequencyOutput, SimFrequencyOutput)> {
+    let vehicle_speed = SimFrequencyOutput::default();
+    let engine_speed = SimFrequencyOutput::default();
+    let brake_pedal = SimAnalogInput::default();
+    let vdc = SimAnalogInput::default();
+    vdc.set(VDC_MV);
+    let oil_pressure = SimAnalogInput::default();
+    oil_pressure.set(OIL_PRESSURE_MV);
+
+    let store = SimConfigStore::provisioned(Role::Kombiinstrument);
+    let config = NodeConfig::load(&store)?;
+    let (can, can_stats, can_health) = start_can(can, &config, "KBI");
+    let incoming_frames_rx = kombiinstrument::spawn_can_receiver(Arc::clone(&can), can_stats);
+    let (app_vehicle_speed, app_engine_speed, app_brake_pedal) = (
+        vehicle_speed.clone(),
+        engine_speed.clone(),
+        brake_pedal.clone(),
+    );
+    Builder::new()
+        .name("kbi_app_thread".into())
+        .spawn(move || {
+            let io = KombiinstrumentIo {
+                vehicle_speed: Box::new(app_vehicle_speed),
+                engine_speed: Box::new(app_engine_speed),
+                oil_pressure_low_pressure: Box::new(SimOutput::default()),
+                oil_pressure_high_pressure: Box::new(SimOutput::default()),
+                vdc: Box::new(vdc),
+                oil_pressure: Box::new(oil_pressure),
+                brake_pedal: Box::new(app_brake_pedal),
+                firmware: Box::new(SimFirmware),
+                parameter_store: Box::new(store),
+            };
+            kombiinstrument::app_thread(
+                io,
+                Box::new(SystemClock),
+                can,
+                can_health,
+                config.own_identifier,
+                incoming_frames_rx,
+            )
+        })?;
+
+    Ok((brake_pedal, vehicle_speed, engine_speed))
+}
+
+/// Sends the Motor messages at idle throttle with a steady rpm and the cruise control
+/// switched on, see [`crate::protocol::oem`].
+fn start_engine_ecu(can: SharedCan) -> anyhow::Result<()> {
+    let rpm = (ENGINE_RPM * 4).to_le_bytes();
+    let frames = [
+        CanFrame::new(MOTOR_1_ID, &[0, 0, rpm[0], rpm[1], 0, 0, 0, 0]).unwrap(),
+        CanFrame::new(MOTOR_2_ID, &[0, COOLANT_TEMP_RAW, 0, 0, 0, 0, 0, 0]).unwrap(),
+        CanFrame::new(MOTOR_3_ID, &[0, COOLANT_TEMP_RAW, 0, 0, 0, 0, 0, 0]).unwrap(),
+        CanFrame::new(GRA_NEU_ID, &[0, 0x01, 0, 0]).unwrap(),
+        CanFrame::new(MOTOR_5_ID, &[0, 0, 0, 0, 0, 0, 0, 0]).unwrap(),
+        CanFrame::new(MOTOR_6_ID, &[0, 0, ENGINE_TORQUE_RAW, 0, 0, 0, 0, 0]).unwrap(),
+        CanFrame::new(MOTOR_7_ID, &[0, OIL_TEMP_RAW, 0, 0, 0, 0, 0, 0]).unwrap(),
+    ];
+
+    Builder::new()
+        .name("engine_ecu".into())
+        .spawn(move || loop {
+            for frame in &frames {
+                if let Err(e) = can.transmit(frame) {
+                    eprintln!("[SIM       ] Engine ECU transmit failed: {e}");
+                }
+            }
+            thread::sleep(ENGINE_ECU_CYCLE);
+        })?;
+
+    Ok(())
+}
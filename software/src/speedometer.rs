@@ -0,0 +1,101 @@
+//! Speed to pulse frequency for the speedometer of the kombiinstrument.
+//!
+//! The gauge expects `pulses_per_km` pulses per kilometre (the K number on its back), so
+//! the frequency is `km/h * K / 3600`. The needle is seldom exactly linear, so the
+//! frequency is corrected in 1/1000 at [`CORRECTION_SPEEDS`] and interpolated between
+//! them.
+
+use crate::gauge;
+
+/// Speeds of the correction points in km/h.
+pub const CORRECTION_SPEEDS: [u32; 5] = [20, 50, 100, 150, 200];
+
+/// End of the scale, the needle sweep goes up to it.
+pub const FULL_SCALE_KMH: f32 = 260.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedometerCalibration {
+    pub pulses_per_km: u32,
+    /// In 1/1000, one per [`CORRECTION_SPEEDS`].
+    pub corrections: [u32; 5],
+}
+
+impl SpeedometerCalibration {
+    /// `kmh` in km/h, returns Hz.
+    pub fn frequency(&self, kmh: f32) -> f32 {
+        let points: [(f32, f32); 5] = std::array::from_fn(|i| {
+            (
+                CORRECTION_SPEEDS[i] as f32,
+                self.corrections[i] as f32 / 1000.0,
+            )
+        });
+
+        kmh * self.pulses_per_km as f32 / 3600.0 * gauge::interpolate(&points, kmh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PULSES_PER_KM: u32 = 4000;
+
+    fn calibration(corrections: [u32; 5]) -> SpeedometerCalibration {
+        SpeedometerCalibration {
+            pulses_per_km: PULSES_PER_KM,
+            corrections,
+        }
+    }
+
+    fn uncorrected(kmh: f32) -> f32 {
+        kmh * PULSES_PER_KM as f32 / 3600.0
+    }
+
+    fn assert_hz(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} Hz, expected {expected} Hz"
+        );
+    }
+
+    #[test]
+    fn linear() {
+        let linear = calibration([1000; 5]);
+
+        assert_hz(linear.frequency(36.0), 40.0);
+        assert_hz(linear.frequency(90.0), 100.0);
+        assert_hz(linear.frequency(180.0), 200.0);
+    }
+
+    #[test]
+    fn standstill() {
+        assert_eq!(calibration([1000; 5]).frequency(0.0), 0.0);
+        assert_eq!(
+            calibration([900, 1000, 1100, 1000, 900]).frequency(0.0),
+            0.0
+        );
+    }
+
+    #[test]
+    fn interpolates_between_the_corrections() {
+        let calibration = calibration([1000, 1000, 1100, 1000, 1000]);
+
+        assert_hz(calibration.frequency(50.0), uncorrected(50.0));
+        assert_hz(calibration.frequency(75.0), uncorrected(75.0) * 1.05);
+        assert_hz(calibration.frequency(100.0), uncorrected(100.0) * 1.1);
+        assert_hz(calibration.frequency(125.0), uncorrected(125.0) * 1.05);
+    }
+
+    #[test]
+    fn outer_corrections_apply_beyond_them() {
+        let calibration = calibration([900, 1000, 1000, 1000, 1100]);
+
+        assert_hz(calibration.frequency(10.0), uncorrected(10.0) * 0.9);
+        assert_hz(calibration.frequency(20.0), uncorrected(20.0) * 0.9);
+        assert_hz(calibration.frequency(250.0), uncorrected(250.0) * 1.1);
+        assert_hz(
+            calibration.frequency(FULL_SCALE_KMH),
+            uncorrected(FULL_SCALE_KMH) * 1.1,
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Node status state machine for the universal status frames.
+//!
+//! Every role keeps one [`NodeStatus`], raises and clears numbered errors while it
+//! runs and publishes [`NodeStatus::next_frame`] on its own identifier.
+
+use crate::protocol::{ErrorCode, UniversalFrame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Online,
+    Updating { progress: u8 },
+    Warning(ErrorCode),
+    Critical(ErrorCode),
+}
+
+#[derive(Debug, Default)]
+pub struct NodeStatus {
+    update_progress: Option<u8>,
+    active_errors: Vec<(ErrorCode, Severity)>,
+    next_error: usize,
+}
+
+impl NodeStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raises `code`, or changes its severity if it is already active.
+    pub fn raise(&mut self, code: ErrorCode, severity: Severity) {
+        match self.active_errors.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, s)) => *s = severity,
+            None => self.active_errors.push((code, severity)),
+        }
+    }
+
+    pub fn warning(&mut self, code: ErrorCode) {
+        self.raise(code, Severity::Warning);
+    }
+
+    pub fn critical(&mut self, code: ErrorCode) {
+        self.raise(code, Severity::Critical);
+    }
+
+    pub fn clear(&mut self, code: ErrorCode) {
+        self.active_errors.retain(|(c, _)| *c != code);
+    }
+
+    /// Raises `code` as a warning if `ok` is false and clears it otherwise.
+    pub fn check(&mut self, code: ErrorCode, ok: bool) {
+        if ok {
+            self.clear(code);
+        } else {
+            self.warning(code);
+        }
+    }
+
+    /// The most severe state of the node, the first raised error wins on equal severity.
+    pub fn state(&self) -> NodeState {
+        if let Some(progress) = self.update_progress {
+            return NodeState::Updating { progress };
+        }
+
+        let most_severe = self.active_errors.iter().copied().reduce(|worst, error| {
+            if error.1 > worst.1 {
+                error
+            } else {
+                worst
+            }
+        });
+
+        match most_severe {
+            Some((code, Severity::Warning)) => NodeState::Warning(code),
+            Some((code, Severity::Critical)) => NodeState::Critical(code),
+            None => NodeState::Online,
+        }
+    }
+
+    /// The universal frame to publish this cycle.
+    ///
+    /// With several active errors every call returns the next one, so the dashboard
+    /// sees all of them instead of only the first.
+    pub fn next_frame(&mut self) -> UniversalFrame {
+        if let Some(progress) = self.update_progress {
+            return UniversalFrame::UpdateProgress(progress);
+        }
+
+        if self.active_errors.is_empty() {
+            return UniversalFrame::Online;
+        }
+
+        self.next_error %= self.active_errors.len();
+        let (code, severity) = self.active_errors[self.next_error];
+        self.next_error += 1;
+
+        match severity {
+            Severity::Warning => UniversalFrame::Warning(code.number()),
+            Severity::Critical => UniversalFrame::Critical(code.number()),
+        }
+    }
+}
+
+// Only the OTA module on the ESP32-S3 updates the image.
+#[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+impl NodeStatus {
+    /// Enters the updating state. Errors stay active and are reported again
+    /// once [`NodeStatus::update_finished`] was called.
+    pub fn update_progress(&mut self, progress: u8) {
+        self.update_progress = Some(progress);
+    }
+
+    pub fn update_finished(&mut self) {
+        self.update_progress = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn online_without_errors() {
+        let mut status = NodeStatus::new();
+
+        assert_eq!(status.state(), NodeState::Online);
+        assert_eq!(status.next_frame(), UniversalFrame::Online);
+    }
+
+    #[test]
+    fn raise_and_clear() {
+        let mut status = NodeStatus::new();
+        status.warning(ErrorCode::AdcReadFailed);
+        assert_eq!(status.state(), NodeState::Warning(ErrorCode::AdcReadFailed));
+
+        status.critical(ErrorCode::AdcReadFailed);
+        assert_eq!(
+            status.state(),
+            NodeState::Critical(ErrorCode::AdcReadFailed)
+        );
+        assert_eq!(status.next_frame(), UniversalFrame::Critical(0x02));
+
+        status.clear(ErrorCode::AdcReadFailed);
+        assert_eq!(status.state(), NodeState::Online);
+        status.clear(ErrorCode::AdcReadFailed);
+        assert_eq!(status.next_frame(), UniversalFrame::Online);
+    }
+
+    #[test]
+    fn check() {
+        let mut status = NodeStatus::new();
+        status.check(ErrorCode::OilPressureLow, false);
+        assert_eq!(
+            status.state(),
+            NodeState::Warning(ErrorCode::OilPressureLow)
+        );
+
+        status.check(ErrorCode::OilPressureLow, true);
+        assert_eq!(status.state(), NodeState::Online);
+    }
+
+    #[test]
+    fn most_severe_state() {
+        let mut status = NodeStatus::new();
+        status.warning(ErrorCode::CanTransmitFailed);
+        status.warning(ErrorCode::OutputFailed);
+        assert_eq!(
+            status.state(),
+            NodeState::Warning(ErrorCode::CanTransmitFailed)
+        );
+
+        status.critical(ErrorCode::CanBusOff);
+        status.critical(ErrorCode::OtaRolledBack);
+        assert_eq!(status.state(), NodeState::Critical(ErrorCode::CanBusOff));
+    }
+
+    #[test]
+    fn next_frame_round_robin() {
+        let mut status = NodeStatus::new();
+        status.warning(ErrorCode::CanTransmitFailed);
+        status.critical(ErrorCode::CanBusOff);
+        status.warning(ErrorCode::WheelSpeedsTimeout);
+
+        assert_eq!(status.next_frame(), UniversalFrame::Warning(0x01));
+        assert_eq!(status.next_frame(), UniversalFrame::Critical(0x06));
+        assert_eq!(status.next_frame(), UniversalFrame::Warning(0x10));
+        assert_eq!(status.next_frame(), UniversalFrame::Warning(0x01));
+
+        // Clearing an error must not skip or repeat the others.
+        status.clear(ErrorCode::CanBusOff);
+        assert_eq!(status.next_frame(), UniversalFrame::Warning(0x10));
+        assert_eq!(status.next_frame(), UniversalFrame::Warning(0x01));
+    }
+
+    #[test]
+    fn updating() {
+        let mut status = NodeStatus::new();
+        status.critical(ErrorCode::CanBusOff);
+        status.update_progress(0x40);
+
+        assert_eq!(status.state(), NodeState::Updating { progress: 0x40 });
+        assert_eq!(status.next_frame(), UniversalFrame::UpdateProgress(0x40));
+
+        status.update_finished();
+        assert_eq!(status.state(), NodeState::Critical(ErrorCode::CanBusOff));
+        assert_eq!(status.next_frame(), UniversalFrame::Critical(0x06));
+    }
+}
@@ -0,0 +1,85 @@
+//! Engine speed to pulse frequency for the tachometer of the kombiinstrument.
+//!
+//! The gauge expects `pulses_per_rev_x10 / 10` pulses per crankshaft revolution, one
+//! ignition pulse per cylinder every other turn, e.g. 2.0 for four cylinders and 2.5 for
+//! five. Motor_1 jitters by a few rpm from frame to frame, a first order low pass keeps
+//! the needle calm.
+
+use std::time::Duration;
+
+/// End of the scale, the needle sweep goes up to it.
+pub const FULL_SCALE_RPM: f32 = 8000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TachometerCalibration {
+    /// Pulses per revolution in 0.1.
+    pub pulses_per_rev_x10: u32,
+}
+
+impl TachometerCalibration {
+    /// `rpm` in 1/min, returns Hz.
+    pub fn frequency(&self, rpm: f32) -> f32 {
+        rpm * self.pulses_per_rev_x10 as f32 / 10.0 / 60.0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RpmFilter {
+    rpm: f32,
+}
+
+impl RpmFilter {
+    /// Moves towards `rpm` by `dt` against `time_constant`, zero passes `rpm` through.
+    pub fn update(&mut self, rpm: f32, dt: Duration, time_constant: Duration) -> f32 {
+        let span = (time_constant + dt).as_secs_f32();
+        let alpha = if span > 0.0 {
+            dt.as_secs_f32() / span
+        } else {
+            1.0
+        };
+        self.rpm += alpha * (rpm - self.rpm);
+        self.rpm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency() {
+        let four_cylinders = TachometerCalibration {
+            pulses_per_rev_x10: 20,
+        };
+        let five_cylinders = TachometerCalibration {
+            pulses_per_rev_x10: 25,
+        };
+
+        assert_eq!(four_cylinders.frequency(3000.0), 100.0);
+        assert_eq!(five_cylinders.frequency(3000.0), 125.0);
+    }
+
+    #[test]
+    fn filter_settles() {
+        let mut filter = RpmFilter::default();
+        let dt = Duration::from_millis(100);
+
+        assert_eq!(filter.update(2000.0, dt, dt), 1000.0);
+        assert_eq!(filter.update(2000.0, dt, dt), 1500.0);
+        for _ in 0..20 {
+            filter.update(2000.0, dt, dt);
+        }
+        assert!((filter.update(2000.0, dt, dt) - 2000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn filter_off() {
+        let mut filter = RpmFilter::default();
+
+        assert_eq!(
+            filter.update(2000.0, Duration::from_millis(100), Duration::ZERO),
+            2000.0
+        );
+        assert_eq!(filter.update(800.0, Duration::ZERO, Duration::ZERO), 800.0);
+    }
+}
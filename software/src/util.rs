@@ -25,7 +25,7 @@ pub fn send_can_frame(
     }
 }
 
-pub async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'static>>) -> anyhow::Result<()> {
+pub async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'_>>) -> anyhow::Result<()> {
     let wifi_configuration = Configuration::Client(ClientConfiguration {
         ssid: WIFI_SSID.try_into().unwrap(),
         bssid: None,
@@ -49,15 +49,6 @@ pub async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'static>>) -> anyhow::Res
     Ok(())
 }
 
-pub fn frame_data_to_bit_array(frame_data: &u8) -> [bool; 8] {
-    let mut bit_array = [false; 8];
-    for i in 0..8 {
-        bit_array[i] = frame_data & (1 << (7 - i)) != 0;
-    }
-
-    bit_array
-}
-
 // pub fn reset_pins(peripherals: &mut Peripherals) -> Peripherals {
 //     let pins = peripherals.pins;
 //     let _ = PinDriver::output(pins.gpio48).map(|mut pin| {
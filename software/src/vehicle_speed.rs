@@ -0,0 +1,122 @@
+//! Vehicle speed from the four wheel speed sensor frequencies.
+//!
+//! A wheel turns `frequency / teeth` times per second and covers its dynamic rolling
+//! circumference with every turn. Driven wheels spin under traction and any wheel can
+//! lock under braking, so the vehicle speed is the median of the two non-driven wheels
+//! and the mean of the driven ones: a single wheel that is far off is never selected.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axle {
+    Front,
+    Rear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedModel {
+    /// Teeth of the ABS tone ring, the sensor sends one edge per tooth.
+    pub teeth: u32,
+    /// Dynamic rolling circumference of the tyre in mm.
+    pub circumference_mm: u32,
+    /// Correction in 1/1000, e.g. 1020 when a GPS shows 2 % more than the model.
+    pub calibration_permille: u32,
+    pub driven_axle: Axle,
+}
+
+impl SpeedModel {
+    /// `frequency` in Hz, returns km/h.
+    pub fn wheel_kmh(&self, frequency: f32) -> f32 {
+        let meters_per_second = frequency / self.teeth as f32 * self.circumference_mm as f32
+            / 1000.0
+            * self.calibration_permille as f32
+            / 1000.0;
+
+        meters_per_second * 3.6
+    }
+
+    /// `wheel_speeds` in Hz, front left, front right, rear left, rear right. Returns the
+    /// vehicle speed in 0.01 km/h, saturating at 655.35 km/h.
+    pub fn vehicle_speed(&self, wheel_speeds: [f32; 4]) -> u16 {
+        let [fl, fr, rl, rr] = wheel_speeds.map(|frequency| self.wheel_kmh(frequency));
+        let ((driven_l, driven_r), (free_l, free_r)) = match self.driven_axle {
+            Axle::Front => ((fl, fr), (rl, rr)),
+            Axle::Rear => ((rl, rr), (fl, fr)),
+        };
+        let kmh = median(free_l, free_r, (driven_l + driven_r) / 2.0);
+
+        (kmh * 100.0).round() as u16
+    }
+}
+
+fn median(a: f32, b: f32, c: f32) -> f32 {
+    a.min(b).max(a.max(b).min(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 Hz is 0.144 km/h.
+    const MODEL: SpeedModel = SpeedModel {
+        teeth: 50,
+        circumference_mm: 2000,
+        calibration_permille: 1000,
+        driven_axle: Axle::Front,
+    };
+
+    #[test]
+    fn wheel_kmh() {
+        assert!((MODEL.wheel_kmh(500.0) - 72.0).abs() < 1e-3);
+        assert_eq!(MODEL.wheel_kmh(0.0), 0.0);
+    }
+
+    #[test]
+    fn resolution() {
+        assert_eq!(MODEL.vehicle_speed([500.0; 4]), 7200);
+        assert_eq!(MODEL.vehicle_speed([1.0; 4]), 14);
+        assert_eq!(MODEL.vehicle_speed([0.1; 4]), 1);
+        assert_eq!(MODEL.vehicle_speed([0.0; 4]), 0);
+    }
+
+    #[test]
+    fn calibration() {
+        let model = SpeedModel {
+            calibration_permille: 1020,
+            ..MODEL
+        };
+
+        assert_eq!(model.vehicle_speed([500.0; 4]), 7344);
+    }
+
+    #[test]
+    fn saturates() {
+        assert_eq!(MODEL.vehicle_speed([10_000.0; 4]), u16::MAX);
+    }
+
+    #[test]
+    fn ignores_spinning_driven_wheels() {
+        assert_eq!(MODEL.vehicle_speed([900.0, 700.0, 500.0, 500.0]), 7200);
+
+        let rear_driven = SpeedModel {
+            driven_axle: Axle::Rear,
+            ..MODEL
+        };
+        assert_eq!(
+            rear_driven.vehicle_speed([500.0, 500.0, 900.0, 700.0]),
+            7200
+        );
+    }
+
+    #[test]
+    fn ignores_one_locked_wheel() {
+        assert_eq!(MODEL.vehicle_speed([500.0, 500.0, 0.0, 500.0]), 7200);
+        assert_eq!(MODEL.vehicle_speed([500.0, 500.0, 500.0, 0.0]), 7200);
+    }
+
+    #[test]
+    fn median() {
+        assert_eq!(super::median(1.0, 2.0, 3.0), 2.0);
+        assert_eq!(super::median(3.0, 1.0, 2.0), 2.0);
+        assert_eq!(super::median(2.0, 3.0, 1.0), 2.0);
+        assert_eq!(super::median(2.0, 2.0, 9.0), 2.0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Wheel speed from the edges of an ABS sensor.
+//!
+//! The frequency is the number of edges since the previous read divided by the time
+//! between the latest edge of the previous read and the latest edge of this one, so it
+//! always spans whole periods: at high speed it averages over many edges, at low speed
+//! it is the period of the few there were. A read without an edge limits the frequency
+//! to one period of the time since the latest edge, until [`STANDSTILL_TIMEOUT`].
+
+use std::time::{Duration, Instant};
+
+use crate::hal::Edges;
+
+/// Longest period that still counts as turning, about 0.15 km/h with a 48 tooth ring.
+pub const STANDSTILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct WheelSpeed {
+    latest_edge: Option<Instant>,
+    /// Hz.
+    frequency: f32,
+}
+
+impl WheelSpeed {
+    /// `now` is when the edges were taken. Returns the frequency in Hz.
+    pub fn update(&mut self, edges: Edges, now: Instant) -> f32 {
+        let latest_edge = edges
+            .since_latest
+            .and_then(|since_latest| now.checked_sub(since_latest));
+
+        if edges.count > 0 {
+            // The first edge after boot has no previous one to measure against.
+            self.frequency = match (self.latest_edge, latest_edge) {
+                (Some(previous), Some(latest)) if latest > previous => {
+                    edges.count as f32 / (latest - previous).as_secs_f32()
+                }
+                _ => 0.0,
+            };
+            self.latest_edge = latest_edge;
+        } else if let Some(previous) = self.latest_edge {
+            let since_latest = now.saturating_duration_since(previous);
+            self.frequency = if since_latest >= STANDSTILL_TIMEOUT {
+                0.0
+            } else {
+                self.frequency.min(1.0 / since_latest.as_secs_f32())
+            };
+        }
+
+        self.frequency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ_PERIOD: Duration = Duration::from_millis(10);
+
+    /// Reads a sensor with edges at multiples of `period` every 10 ms, starting half a
+    /// read period after the first edge. Returns the frequency after each read.
+    fn run(period: Duration, edges_until: Duration, reads: u32) -> Vec<f32> {
+        let start = Instant::now();
+        let mut wheel_speed = WheelSpeed::default();
+        let mut previous = Duration::ZERO;
+
+        (0..reads)
+            .map(|read| {
+                let now = READ_PERIOD / 2 + READ_PERIOD * read;
+                let edges_before = |time: Duration| {
+                    (time.min(edges_until).as_nanos() / period.as_nanos()) as u32 + 1
+                };
+                let count = edges_before(now) - if read == 0 { 0 } else { edges_before(previous) };
+                let latest = period * (edges_before(now) - 1);
+                previous = now;
+
+                let edges = Edges {
+                    count,
+                    since_latest: Some(now - latest),
+                };
+                wheel_speed.update(edges, start + now)
+            })
+            .collect()
+    }
+
+    fn assert_hz(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-2,
+            "{actual} Hz, expected {expected} Hz"
+        );
+    }
+
+    #[test]
+    fn first_edge_after_boot() {
+        let mut wheel_speed = WheelSpeed::default();
+        let now = Instant::now();
+        let no_edge = Edges {
+            count: 0,
+            since_latest: None,
+        };
+
+        assert_eq!(wheel_speed.update(no_edge, now), 0.0);
+        let first = Edges {
+            count: 1,
+            since_latest: Some(Duration::from_millis(3)),
+        };
+        assert_eq!(wheel_speed.update(first, now + READ_PERIOD), 0.0);
+    }
+
+    #[test]
+    fn low_speed_from_the_period() {
+        // 4 Hz, most reads see no edge.
+        let frequencies = run(Duration::from_millis(250), Duration::MAX, 100);
+
+        assert_eq!(frequencies[..25], [0.0; 25]);
+        for frequency in &frequencies[25..] {
+            assert_hz(*frequency, 4.0);
+        }
+    }
+
+    #[test]
+    fn high_speed_from_the_count() {
+        // 1234 Hz, every read sees 12 or 13 edges.
+        let period = Duration::from_secs(1) / 1234;
+        let frequencies = run(period, Duration::MAX, 100);
+
+        assert_eq!(frequencies[0], 0.0);
+        for frequency in &frequencies[1..] {
+            assert!((frequency - 1234.0).abs() < 1234.0 * 0.01, "{frequency} Hz");
+        }
+    }
+
+    #[test]
+    fn decays_after_the_last_edge() {
+        // 20 Hz until the edge at 500 ms.
+        let frequencies = run(Duration::from_millis(50), Duration::from_millis(500), 152);
+
+        assert_hz(frequencies[49], 20.0);
+        // Keeps the frequency for a period, then limited to one period of the time since
+        // the latest edge.
+        assert_hz(frequencies[54], 20.0);
+        assert_hz(frequencies[59], 1.0 / 0.095);
+        assert_hz(frequencies[99], 1.0 / 0.495);
+        assert_hz(frequencies[149], 1.0 / 0.995);
+        assert_eq!(frequencies[150], 0.0);
+        assert_eq!(frequencies[151], 0.0);
+    }
+
+    #[test]
+    fn standstill_timeout() {
+        let mut wheel_speed = WheelSpeed::default();
+        let start = Instant::now();
+        let edge = |since_latest| Edges {
+            count: 1,
+            since_latest: Some(since_latest),
+        };
+        wheel_speed.update(edge(Duration::ZERO), start);
+        wheel_speed.update(edge(Duration::ZERO), start + Duration::from_millis(100));
+
+        let no_edge = Edges {
+            count: 0,
+            since_latest: Some(STANDSTILL_TIMEOUT),
+        };
+        let now = start + Duration::from_millis(100) + STANDSTILL_TIMEOUT;
+        assert_eq!(wheel_speed.update(no_edge, now), 0.0);
+        assert_eq!(wheel_speed.update(no_edge, now + READ_PERIOD), 0.0);
+    }
+}